@@ -0,0 +1,796 @@
+//!
+//! Reading and writing of FITS (Flexible Image Transport System) files
+//!
+//! Only the primary HDU is supported, with a 2D integer image, or a 32-bit floating-point
+//! image for frames of `f32` (any integer or floating-point image can be read as `f32`).
+//! Unsigned 16, 32 and 64-bit pixels and signed 8-bit pixels are stored using the
+//! standard BZERO offset convention.
+//!
+
+use super::CameraFrame;
+use super::FrameData;
+use super::MonoPixel;
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+
+/// FITS files are made of blocks of this many bytes
+const BLOCK_SIZE: usize = 2880;
+/// Header cards are this many bytes
+const CARD_SIZE: usize = 80;
+
+/// Format used for DATE-OBS and DATE-AVG keywords
+const DATE_FORMAT: &str = "%Y-%m-%dT%H:%M:%S%.6f";
+
+/// Value of a header card
+enum CardValue {
+    Logical(bool),
+    Integer(i128),
+    Float(f64),
+    Text(String),
+}
+
+/// Errors in the structure of a FITS file
+#[derive(Debug, thiserror::Error)]
+pub enum FitsError {
+    #[error("FITS header card {card} is not ASCII")]
+    NotAscii { card: usize },
+    #[error("FITS image of {width} x {height} pixels with BITPIX {bitpix} is too large")]
+    TooLarge {
+        width: u32,
+        height: u32,
+        bitpix: i128,
+    },
+    #[error("FITS file holds {available} bytes of image data; its header describes {expected}")]
+    Truncated { expected: u64, available: u64 },
+}
+
+/// Header keywords of a FITS file, mapped to their (unquoted) values
+struct FitsHeader {
+    values: HashMap<String, String>,
+    /// Size of the header in the file, in bytes
+    size: u64,
+}
+
+impl FitsHeader {
+    fn get(&self, keyword: &str) -> Option<&str> {
+        self.values.get(keyword).map(|s| s.as_str())
+    }
+
+    fn get_int(&self, keyword: &str) -> Result<Option<i128>, Box<dyn Error>> {
+        match self.get(keyword) {
+            None => Ok(None),
+            Some(v) => Ok(Some(v.parse::<i128>().or_else(|_| {
+                // Some writers use e.g. "32768.0" or "3.2768E4" for integer values
+                let f = v.replace(['D', 'd'], "E").parse::<f64>()?;
+                match f.fract() == 0.0 {
+                    true => Ok(f as i128),
+                    false => Err::<i128, Box<dyn Error>>(
+                        format!("FITS keyword {} is not an integer: {}", keyword, v).into(),
+                    ),
+                }
+            })?)),
+        }
+    }
+
+    fn get_float(&self, keyword: &str) -> Result<Option<f64>, Box<dyn Error>> {
+        match self.get(keyword) {
+            None => Ok(None),
+            Some(v) => Ok(Some(v.replace(['D', 'd'], "E").parse::<f64>().map_err(
+                |_| format!("FITS keyword {} is not a number: {}", keyword, v),
+            )?)),
+        }
+    }
+
+    fn require_int(&self, keyword: &str) -> Result<i128, Box<dyn Error>> {
+        self.get_int(keyword)?
+            .ok_or(format!("FITS header is missing required keyword {}", keyword).into())
+    }
+
+    /// Read the header from the start of a FITS file, consuming whole blocks
+    fn read<R: Read>(reader: &mut R) -> Result<FitsHeader, Box<dyn Error>> {
+        let mut values = HashMap::new();
+        let mut block = [0u8; BLOCK_SIZE];
+        let mut first = true;
+        let mut size = 0;
+        loop {
+            reader.read_exact(&mut block)?;
+            for (i, card) in block.chunks(CARD_SIZE).enumerate() {
+                if !card.is_ascii() {
+                    let card = size as usize / CARD_SIZE + i;
+                    return Err(FitsError::NotAscii { card }.into());
+                }
+                let card = std::str::from_utf8(card)?;
+                let keyword = card[0..8].trim_end();
+                if first {
+                    if keyword != "SIMPLE" {
+                        return Err("Not a FITS file: first keyword is not SIMPLE".into());
+                    }
+                    first = false;
+                }
+                if keyword == "END" {
+                    size += BLOCK_SIZE as u64;
+                    return Ok(FitsHeader { values, size });
+                }
+                if &card[8..10] != "= " {
+                    // Commentary card (COMMENT, HISTORY, blank)
+                    continue;
+                }
+                values.insert(keyword.to_string(), Self::parse_value(&card[10..]));
+            }
+            size += BLOCK_SIZE as u64;
+        }
+    }
+
+    /// Extract the value from the value/comment field of a card
+    fn parse_value(field: &str) -> String {
+        let field = field.trim_start();
+        match field.strip_prefix('\'') {
+            Some(rest) => {
+                // Quoted string; a doubled quote is a literal quote
+                let mut value = String::new();
+                let mut chars = rest.chars().peekable();
+                while let Some(c) = chars.next() {
+                    if c == '\'' {
+                        if chars.peek() == Some(&'\'') {
+                            chars.next();
+                        } else {
+                            break;
+                        }
+                    }
+                    value.push(c);
+                }
+                value.trim_end().to_string()
+            }
+            None => field.split('/').next().unwrap_or("").trim().to_string(),
+        }
+    }
+}
+
+/// Format a single 80-character header card
+fn format_card(keyword: &str, value: &CardValue, comment: &str) -> String {
+    let value = match value {
+        CardValue::Logical(b) => format!("{:>20}", if *b { "T" } else { "F" }),
+        CardValue::Integer(i) => format!("{:>20}", i),
+        CardValue::Float(f) => format!("{:>20}", format!("{:.15E}", f)),
+        CardValue::Text(s) => format!("{:<20}", format!("'{:<8}'", s.replace('\'', "''"))),
+    };
+    let mut card = format!("{:<8}= {} / {}", keyword, value, comment);
+    card.truncate(CARD_SIZE);
+    format!("{:<80}", card)
+}
+
+/// Layout of pixel type `T` in a FITS file: (BITPIX, BZERO)
+fn pixel_format<T: MonoPixel>() -> (i32, i128) {
+    let bits = (std::mem::size_of::<T>() * 8) as i32;
+    let signed = T::min_value() < T::zero();
+    let bzero = match (bits, signed) {
+        // FITS 8-bit data is unsigned; everything else is signed
+        (8, true) => -128,
+        (8, false) => 0,
+        (_, false) => 1_i128 << (bits - 1),
+        (_, true) => 0,
+    };
+    (bits, bzero)
+}
+
+/// Write the header of a FITS primary HDU holding a 2D image
+fn write_header<W: Write>(
+    writer: &mut W,
+    bitpix: i32,
+    width: u32,
+    height: u32,
+    bzero: i128,
+    cards: &[(&str, CardValue, &str)],
+) -> Result<(), Box<dyn Error>> {
+    let mut header = String::new();
+    header.push_str(&format_card(
+        "SIMPLE",
+        &CardValue::Logical(true),
+        "conforms to FITS standard",
+    ));
+    header.push_str(&format_card(
+        "BITPIX",
+        &CardValue::Integer(bitpix as i128),
+        "array data type",
+    ));
+    header.push_str(&format_card(
+        "NAXIS",
+        &CardValue::Integer(2),
+        "number of array dimensions",
+    ));
+    header.push_str(&format_card(
+        "NAXIS1",
+        &CardValue::Integer(width as i128),
+        "width",
+    ));
+    header.push_str(&format_card(
+        "NAXIS2",
+        &CardValue::Integer(height as i128),
+        "height",
+    ));
+    if bzero != 0 {
+        header.push_str(&format_card(
+            "BZERO",
+            &CardValue::Integer(bzero),
+            "offset data range to that of unsigned",
+        ));
+        header.push_str(&format_card(
+            "BSCALE",
+            &CardValue::Integer(1),
+            "default scaling factor",
+        ));
+    }
+    for (keyword, value, comment) in cards {
+        header.push_str(&format_card(keyword, value, comment));
+    }
+    header.push_str(&format!("{:<80}", "END"));
+    let padded = header.len().div_ceil(BLOCK_SIZE) * BLOCK_SIZE;
+    header.push_str(&" ".repeat(padded - header.len()));
+    writer.write_all(header.as_bytes())?;
+    Ok(())
+}
+
+/// Write the data of a FITS HDU, padded to a whole number of blocks
+fn write_data<W: Write>(writer: &mut W, mut data: Vec<u8>) -> Result<(), Box<dyn Error>> {
+    let padded = data.len().div_ceil(BLOCK_SIZE) * BLOCK_SIZE;
+    data.resize(padded, 0);
+    writer.write_all(&data)?;
+    Ok(())
+}
+
+/// Write a FITS primary HDU containing `frame` and the additional header cards
+fn write_fits<T, W>(
+    writer: &mut W,
+    frame: &FrameData<T>,
+    cards: &[(&str, CardValue, &str)],
+) -> Result<(), Box<dyn Error>>
+where
+    T: MonoPixel,
+    W: Write,
+{
+    let (bitpix, bzero) = pixel_format::<T>();
+    if bitpix > 64 {
+        return Err("Pixel type too large for FITS".into());
+    }
+    write_header(writer, bitpix, frame.width, frame.height, bzero, cards)?;
+
+    // Data is stored big-endian, row by row, in the same order as in memory
+    let nbytes = (bitpix / 8) as usize;
+    let mut data = Vec::with_capacity(frame.data.len() * nbytes);
+    for x in frame.data.iter() {
+        let v = x.to_i128().unwrap() - bzero;
+        data.extend_from_slice(&v.to_be_bytes()[16 - nbytes..]);
+    }
+    write_data(writer, data)
+}
+
+/// Read the data of a FITS image, after checking that the file holds all of it
+///
+/// # Arguments
+/// * `reader` - Reader positioned after the header
+/// * `header` - The header of the file
+/// * `len` - Length of the file, in bytes
+///
+/// # Returns
+/// The raw (big-endian) image data
+///
+fn read_data<R: Read>(
+    reader: &mut R,
+    header: &FitsHeader,
+    width: u32,
+    height: u32,
+    bitpix: i128,
+    len: u64,
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    let expected = (width as u64)
+        .checked_mul(height as u64)
+        .and_then(|n| n.checked_mul(bitpix.unsigned_abs() as u64 / 8))
+        .ok_or(FitsError::TooLarge {
+            width,
+            height,
+            bitpix,
+        })?;
+    let available = len.saturating_sub(header.size);
+    if expected > available {
+        return Err(FitsError::Truncated {
+            expected,
+            available,
+        }
+        .into());
+    }
+    let mut raw = vec![0u8; usize::try_from(expected)?];
+    reader.read_exact(&mut raw)?;
+    Ok(raw)
+}
+
+/// Read a FITS primary HDU as a frame with pixel type `T`
+///
+/// # Arguments
+/// * `reader` - Reader positioned at the start of the file
+/// * `len` - Length of the file, in bytes
+///
+fn read_fits<T, R>(reader: &mut R, len: u64) -> Result<(FrameData<T>, FitsHeader), Box<dyn Error>>
+where
+    T: MonoPixel,
+    R: Read,
+{
+    let header = FitsHeader::read(reader)?;
+    let bitpix = header.require_int("BITPIX")?;
+    let naxis = header.require_int("NAXIS")?;
+    if naxis != 2 {
+        return Err(format!("Only 2D FITS images are supported; NAXIS = {}", naxis).into());
+    }
+    let width = u32::try_from(header.require_int("NAXIS1")?)?;
+    let height = u32::try_from(header.require_int("NAXIS2")?)?;
+    let bzero = header.get_float("BZERO")?.unwrap_or(0.0);
+    let bscale = header.get_float("BSCALE")?.unwrap_or(1.0);
+
+    let nbytes = (bitpix.unsigned_abs() / 8) as usize;
+    let raw = read_data(reader, &header, width, height, bitpix, len)?;
+
+    let outofrange = |v: f64| format!("FITS pixel value {} does not fit in pixel type", v);
+    let data = match bitpix {
+        8 | 16 | 32 | 64 => {
+            let integral = bscale == 1.0 && bzero.fract() == 0.0;
+            // A fractional BZERO is applied as a float
+            let izero = match integral {
+                true => header.get_int("BZERO")?.unwrap_or(0),
+                false => 0,
+            };
+            raw.chunks_exact(nbytes)
+                .map(|b| {
+                    let mut bytes = [0u8; 16];
+                    bytes[16 - nbytes..].copy_from_slice(b);
+                    // Sign-extend everything except 8-bit data, which is unsigned
+                    let v = match bitpix {
+                        8 => i128::from_be_bytes(bytes),
+                        _ => i128::from_be_bytes(bytes) << (128 - nbytes * 8) >> (128 - nbytes * 8),
+                    };
+                    match integral {
+                        true => T::from(v + izero).ok_or(outofrange(v as f64)),
+                        false => {
+                            let p = (v as f64 * bscale + bzero).round();
+                            T::from(p).ok_or(outofrange(p))
+                        }
+                    }
+                })
+                .collect::<Result<Vec<T>, String>>()?
+        }
+        -32 => raw
+            .chunks_exact(4)
+            .map(|b| {
+                let p = (f32::from_be_bytes(b.try_into().unwrap()) as f64 * bscale + bzero).round();
+                T::from(p).ok_or(outofrange(p))
+            })
+            .collect::<Result<Vec<T>, String>>()?,
+        -64 => raw
+            .chunks_exact(8)
+            .map(|b| {
+                let p = (f64::from_be_bytes(b.try_into().unwrap()) * bscale + bzero).round();
+                T::from(p).ok_or(outofrange(p))
+            })
+            .collect::<Result<Vec<T>, String>>()?,
+        _ => return Err(format!("Invalid FITS BITPIX: {}", bitpix).into()),
+    };
+
+    Ok((
+        FrameData {
+            width,
+            height,
+            data,
+        },
+        header,
+    ))
+}
+
+/// Read a FITS primary HDU of any pixel format as physical (scaled) floating-point values
+///
+/// # Arguments
+/// * `reader` - Reader positioned at the start of the file
+/// * `len` - Length of the file, in bytes
+///
+fn read_fits_f32<R: Read>(reader: &mut R, len: u64) -> Result<FrameData<f32>, Box<dyn Error>> {
+    let header = FitsHeader::read(reader)?;
+    let bitpix = header.require_int("BITPIX")?;
+    let naxis = header.require_int("NAXIS")?;
+    if naxis != 2 {
+        return Err(format!("Only 2D FITS images are supported; NAXIS = {}", naxis).into());
+    }
+    let width = u32::try_from(header.require_int("NAXIS1")?)?;
+    let height = u32::try_from(header.require_int("NAXIS2")?)?;
+    let bzero = header.get_float("BZERO")?.unwrap_or(0.0);
+    let bscale = header.get_float("BSCALE")?.unwrap_or(1.0);
+    if ![8, 16, 32, 64, -32, -64].contains(&bitpix) {
+        return Err(format!("Invalid FITS BITPIX: {}", bitpix).into());
+    }
+
+    let nbytes = (bitpix.unsigned_abs() / 8) as usize;
+    let raw = read_data(reader, &header, width, height, bitpix, len)?;
+
+    let data = raw
+        .chunks_exact(nbytes)
+        .map(|b| {
+            let v = match bitpix {
+                8 => b[0] as f64,
+                16 => i16::from_be_bytes(b.try_into().unwrap()) as f64,
+                32 => i32::from_be_bytes(b.try_into().unwrap()) as f64,
+                64 => i64::from_be_bytes(b.try_into().unwrap()) as f64,
+                -32 => f32::from_be_bytes(b.try_into().unwrap()) as f64,
+                _ => f64::from_be_bytes(b.try_into().unwrap()),
+            };
+            (v * bscale + bzero) as f32
+        })
+        .collect();
+    Ok(FrameData {
+        width,
+        height,
+        data,
+    })
+}
+
+impl<T> FrameData<T>
+where
+    T: MonoPixel,
+{
+    /// Save the FrameData to a FITS file.
+    ///
+    /// # Arguments
+    /// `filename` - The name of the file to save the FITS to.
+    ///
+    /// # Returns
+    /// An empty Result if the save was successful, or an error if the save failed.
+    ///
+    pub fn save_to_fits(&self, filename: &str) -> Result<(), Box<dyn Error>> {
+        let mut writer = BufWriter::new(File::create(filename)?);
+        write_fits(&mut writer, self, &[])?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Load FrameData from the primary HDU of a FITS file.
+    ///
+    /// # Arguments
+    /// `filename` - The name of the FITS file.
+    ///
+    /// # Returns
+    /// The FrameData, or an error if the file could not be read or its
+    /// values do not fit in the pixel type.
+    ///
+    pub fn load_fits(filename: &str) -> Result<FrameData<T>, Box<dyn Error>> {
+        let file = File::open(filename)?;
+        let len = file.metadata()?.len();
+        Ok(read_fits(&mut BufReader::new(file), len)?.0)
+    }
+}
+
+impl FrameData<f32> {
+    /// Save floating-point FrameData to a FITS file, as 32-bit IEEE floats (BITPIX = -32).
+    ///
+    /// # Arguments
+    /// `filename` - The name of the file to save the FITS to.
+    ///
+    /// # Returns
+    /// An empty Result if the save was successful, or an error if the save failed.
+    ///
+    pub fn save_to_fits_f32(&self, filename: &str) -> Result<(), Box<dyn Error>> {
+        let mut writer = BufWriter::new(File::create(filename)?);
+        write_header(&mut writer, -32, self.width, self.height, 0, &[])?;
+        let data = self
+            .data
+            .iter()
+            .flat_map(|x| x.to_be_bytes())
+            .collect::<Vec<u8>>();
+        write_data(&mut writer, data)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Load floating-point FrameData from the primary HDU of a FITS file.
+    ///
+    /// Any pixel format is accepted; integer data are scaled by BSCALE and BZERO.
+    ///
+    /// # Arguments
+    /// `filename` - The name of the FITS file.
+    ///
+    /// # Returns
+    /// The FrameData, or an error if the file could not be read.
+    ///
+    pub fn load_fits_f32(filename: &str) -> Result<FrameData<f32>, Box<dyn Error>> {
+        let file = File::open(filename)?;
+        let len = file.metadata()?.len();
+        read_fits_f32(&mut BufReader::new(file), len)
+    }
+}
+
+impl<T> CameraFrame<T>
+where
+    T: MonoPixel,
+{
+    /// Save the CameraFrame to a FITS file.
+    ///
+    /// The exposure, center of integration and bit depth are stored in the
+    /// EXPTIME, DATE-AVG (with DATE-OBS at the start of the exposure) and BITDEPTH keywords.
+    ///
+    /// # Arguments
+    /// `filename` - The name of the file to save the FITS to.
+    ///
+    /// # Returns
+    /// An empty Result if the save was successful, or an error if the save failed.
+    ///
+    pub fn save_to_fits(&self, filename: &str) -> Result<(), Box<dyn Error>> {
+        let mut writer = BufWriter::new(File::create(filename)?);
+        self.write_fits(&mut writer)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Load a CameraFrame from a FITS file.
+    ///
+    /// The exposure, center of integration and bit depth are read from the
+    /// EXPTIME, DATE-AVG (or DATE-OBS) and BITDEPTH keywords; when absent they
+    /// default to zero, the Unix epoch, and the size of the pixel type respectively.
+    ///
+    /// # Arguments
+    /// `filename` - The name of the FITS file.
+    ///
+    /// # Returns
+    /// The CameraFrame, or an error if the file could not be read.
+    ///
+    pub fn load_fits(filename: &str) -> Result<CameraFrame<T>, Box<dyn Error>> {
+        let file = File::open(filename)?;
+        let len = file.metadata()?.len();
+        Self::read_fits(&mut BufReader::new(file), len)
+    }
+
+    /// Write the CameraFrame as a FITS primary HDU
+    pub(crate) fn write_fits<W: Write>(&self, writer: &mut W) -> Result<(), Box<dyn Error>> {
+        let start = self.center_of_integration
+            - chrono::Duration::microseconds((self.exposure * 0.5e6).round() as i64);
+        write_fits(
+            writer,
+            &self.data,
+            &[
+                (
+                    "EXPTIME",
+                    CardValue::Float(self.exposure),
+                    "exposure time [s]",
+                ),
+                (
+                    "DATE-OBS",
+                    CardValue::Text(start.format(DATE_FORMAT).to_string()),
+                    "start of exposure (UTC)",
+                ),
+                (
+                    "DATE-AVG",
+                    CardValue::Text(self.center_of_integration.format(DATE_FORMAT).to_string()),
+                    "center of integration (UTC)",
+                ),
+                (
+                    "BITDEPTH",
+                    CardValue::Integer(self.bit_depth as i128),
+                    "camera bit depth",
+                ),
+            ],
+        )
+    }
+
+    /// Read a CameraFrame from a FITS primary HDU, in a file of `len` bytes
+    pub(crate) fn read_fits<R: Read>(
+        reader: &mut R,
+        len: u64,
+    ) -> Result<CameraFrame<T>, Box<dyn Error>> {
+        let (data, header) = read_fits::<T, R>(reader, len)?;
+        let parse_date = |s: &str| {
+            chrono::NaiveDateTime::parse_from_str(s, DATE_FORMAT)
+                .or_else(|_| chrono::NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%M:%S"))
+                .map(|d| d.and_utc())
+                .map_err(|_| format!("Invalid FITS date: {}", s))
+        };
+        let exposure = header.get_float("EXPTIME")?.unwrap_or(0.0);
+        let center_of_integration = match (header.get("DATE-AVG"), header.get("DATE-OBS")) {
+            (Some(avg), _) => parse_date(avg)?,
+            (None, Some(obs)) => {
+                parse_date(obs)? + chrono::Duration::microseconds((exposure * 0.5e6).round() as i64)
+            }
+            (None, None) => chrono::DateTime::<chrono::Utc>::UNIX_EPOCH,
+        };
+        let bit_depth = match header.get_int("BITDEPTH")? {
+            Some(b) => u8::try_from(b)?,
+            None => (std::mem::size_of::<T>() * 8) as u8,
+        };
+        Ok(CameraFrame::create(
+            exposure,
+            center_of_integration,
+            bit_depth,
+            data,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn roundtrip<T: MonoPixel + PartialEq>(values: Vec<T>) {
+        let frame = FrameData::<T> {
+            width: values.len() as u32,
+            height: 1,
+            data: values,
+        };
+        let mut buf = Vec::new();
+        write_fits(&mut buf, &frame, &[]).unwrap();
+        assert_eq!(buf.len() % BLOCK_SIZE, 0);
+        let (frame2, _) = read_fits::<T, _>(&mut buf.as_slice(), buf.len() as u64).unwrap();
+        assert_eq!(frame2.width, frame.width);
+        assert_eq!(frame2.height, frame.height);
+        assert_eq!(frame2.data, frame.data);
+    }
+
+    #[test]
+    fn test_roundtrip_types() {
+        roundtrip::<u8>(vec![0, 1, 127, 128, 255]);
+        roundtrip::<i8>(vec![-128, -1, 0, 1, 127]);
+        roundtrip::<u16>(vec![0, 1, 32767, 32768, 65535]);
+        roundtrip::<i16>(vec![-32768, -1, 0, 1, 32767]);
+        roundtrip::<u32>(vec![0, 1, 0x7fffffff, 0x80000000, u32::MAX]);
+        roundtrip::<i32>(vec![i32::MIN, -1, 0, 1, i32::MAX]);
+        roundtrip::<u64>(vec![0, 1, u64::MAX / 2, u64::MAX / 2 + 1, u64::MAX]);
+    }
+
+    #[test]
+    fn test_u16_bzero() {
+        let frame = FrameData::<u16> {
+            width: 2,
+            height: 1,
+            data: vec![0, 65535],
+        };
+        let mut buf = Vec::new();
+        write_fits(&mut buf, &frame, &[]).unwrap();
+        let header = FitsHeader::read(&mut buf.as_slice()).unwrap();
+        assert_eq!(header.get_int("BITPIX").unwrap(), Some(16));
+        assert_eq!(header.get_int("BZERO").unwrap(), Some(32768));
+        // Stored big-endian as signed values offset by BZERO
+        assert_eq!(&buf[BLOCK_SIZE..BLOCK_SIZE + 4], &[0x80, 0x00, 0x7f, 0xff]);
+    }
+
+    #[test]
+    fn test_out_of_range() {
+        let frame = FrameData::<u16> {
+            width: 1,
+            height: 1,
+            data: vec![300],
+        };
+        let mut buf = Vec::new();
+        write_fits(&mut buf, &frame, &[]).unwrap();
+        let len = buf.len() as u64;
+        assert!(read_fits::<u8, _>(&mut buf.as_slice(), len).is_err());
+        let (frame2, _) = read_fits::<u32, _>(&mut buf.as_slice(), len).unwrap();
+        assert_eq!(frame2.data, vec![300]);
+    }
+
+    #[test]
+    fn test_malformed() {
+        let frame = FrameData::<u16> {
+            width: 2,
+            height: 1,
+            data: vec![0, 65535],
+        };
+        let mut buf = Vec::new();
+        write_fits(&mut buf, &frame, &[]).unwrap();
+        let card = |buf: &[u8], keyword: &str| {
+            buf.chunks(CARD_SIZE)
+                .position(|c| c.starts_with(format!("{:<8}=", keyword).as_bytes()))
+                .unwrap()
+                * CARD_SIZE
+        };
+        let read = |buf: &[u8]| read_fits::<u16, _>(&mut &buf[..], buf.len() as u64).map(|r| r.0);
+
+        // Non-ASCII bytes in a card
+        let mut bad = buf.clone();
+        bad[2 * CARD_SIZE + 20] = 0xc3;
+        let err = read(&bad).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<FitsError>(),
+            Some(FitsError::NotAscii { card: 2 })
+        ));
+
+        // Less data than the header describes
+        let err = read(&buf[..BLOCK_SIZE + 2]).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<FitsError>(),
+            Some(FitsError::Truncated {
+                expected: 4,
+                available: 2
+            })
+        ));
+
+        // An image too large to address, or to fit in the file
+        let mut bad = buf.clone();
+        for keyword in ["NAXIS1", "NAXIS2"] {
+            let i = card(&bad, keyword);
+            let c = format_card(keyword, &CardValue::Integer(4_000_000_000), "");
+            bad[i..i + CARD_SIZE].copy_from_slice(c.as_bytes());
+        }
+        let err = read(&bad).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<FitsError>(),
+            Some(FitsError::TooLarge { .. })
+        ));
+        let i = card(&bad, "NAXIS2");
+        let c = format_card("NAXIS2", &CardValue::Integer(1), "");
+        bad[i..i + CARD_SIZE].copy_from_slice(c.as_bytes());
+        let err = read(&bad).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<FitsError>(),
+            Some(FitsError::Truncated { .. })
+        ));
+
+        // A BZERO that is not a number
+        let mut bad = buf.clone();
+        let i = card(&bad, "BZERO");
+        let c = format_card("BZERO", &CardValue::Text("zero".to_string()), "");
+        bad[i..i + CARD_SIZE].copy_from_slice(c.as_bytes());
+        assert!(read(&bad).is_err());
+    }
+
+    #[test]
+    fn test_f32() {
+        let filename = std::env::temp_dir().join("viewer_test_fits_f32.fits");
+        let filename = filename.to_str().unwrap();
+        let frame = FrameData::<f32> {
+            width: 3,
+            height: 2,
+            data: vec![-1.5, 0.0, 0.25, 1.0e6, f32::MIN_POSITIVE, 3.0],
+        };
+        frame.save_to_fits_f32(filename).unwrap();
+        assert_eq!(
+            FrameData::<f32>::load_fits_f32(filename).unwrap().data,
+            frame.data
+        );
+        // Negative values do not fit when read as unsigned integers
+        assert!(FrameData::<u16>::load_fits(filename).is_err());
+
+        // Integer data is read as its physical value
+        let frame = FrameData::<u16> {
+            width: 2,
+            height: 1,
+            data: vec![0, 65535],
+        };
+        frame.save_to_fits(filename).unwrap();
+        let frame2 = FrameData::<f32>::load_fits_f32(filename).unwrap();
+        assert_eq!(frame2.data, vec![0.0, 65535.0]);
+        let _ = std::fs::remove_file(filename);
+    }
+
+    #[test]
+    fn test_cameraframe_fits() {
+        let time = chrono::Utc::now();
+        let frame = CameraFrame::<u16>::create(
+            0.25,
+            time,
+            12,
+            FrameData::<u16>::rand_norm(1000.0, 100.0, 64, 48),
+        );
+        let filename = std::env::temp_dir().join("viewer_test_cameraframe.fits");
+        let filename = filename.to_str().unwrap();
+        frame.save_to_fits(filename).unwrap();
+        let frame2 = CameraFrame::<u16>::load_fits(filename).unwrap();
+        let _ = std::fs::remove_file(filename);
+
+        assert_eq!(frame2.exposure, 0.25);
+        assert_eq!(frame2.bit_depth, 12);
+        assert!(
+            (frame2.center_of_integration - time)
+                .num_microseconds()
+                .unwrap()
+                .abs()
+                <= 1
+        );
+        assert_eq!(frame2.data.width, 64);
+        assert_eq!(frame2.data.height, 48);
+        assert_eq!(frame2.data.data, frame.data.data);
+    }
+}
@@ -1,11 +1,18 @@
 mod cameraframe_def;
+mod fits;
 mod framedata;
 mod mono_cast;
 mod mono_ops;
+mod mono_robust;
 mod mono_stats;
 mod pixel;
+mod roi;
+mod stack;
+mod tiff_file;
 mod to_file;
 
+/// Saturating conversion of pixel values
+pub use pixel::saturating_cast;
 /// Monochromatic pixel type
 pub use pixel::MonoPixel;
 /// Pixel types
@@ -21,10 +28,22 @@ pub use cameraframe_def::CameraFrame;
 //pub use cameraframe::CameraFrameU16;
 //pub use cameraframe::CameraFrameU32;
 pub use framedata::FrameData;
-
-pub struct ROI {
-    x: usize,
-    y: usize,
-    width: usize,
-    height: usize,
-}
+/// Robust statistics of a frame or region, or of plain values
+pub use mono_robust::mad;
+pub use mono_robust::median;
+pub use mono_robust::percentiles;
+pub use mono_robust::sigma_clipped;
+pub use mono_robust::ClippedStats;
+pub use mono_robust::RobustStats;
+pub use mono_robust::SigmaClip;
+pub use mono_robust::MAD_TO_SIGMA;
+/// Summary statistics of a frame or region, and of plain values
+pub use mono_stats::compensated_sum;
+pub use mono_stats::welford;
+pub use mono_stats::FrameStats;
+/// Region of interest
+pub use roi::Roi;
+/// How frames are combined when stacked
+pub use stack::CombineMethod;
+/// Errors from loading PNG files
+pub use to_file::PngError;
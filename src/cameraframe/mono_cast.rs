@@ -2,6 +2,7 @@
 //! This module contains functions that enable casting of FrameData between different pixel types.
 //!
 
+use super::saturating_cast;
 use super::FrameData;
 use super::MonoPixel;
 use super::RGBAPixel;
@@ -27,6 +28,15 @@ impl<T> FrameData<T>
 where
     T: MonoPixel,
 {
+    /// Convert the FrameData to single-precision floating point, e.g. for calibration
+    pub fn to_float(&self) -> FrameData<f32> {
+        FrameData::<f32> {
+            width: self.width,
+            height: self.height,
+            data: self.data.iter().map(|x| x.to_f32().unwrap()).collect(),
+        }
+    }
+
     /// Convert the FrameData to an RGBA FrameData.
     ///
     /// # Arguments
@@ -44,9 +54,10 @@ where
         gamma: f64,
         cmap: &ColorMap,
     ) -> FrameData<RGBAPixel> {
-        let maxcolor = 255_i64;
-        let minscale = minscale.to_i64().unwrap();
-        let maxscale = maxscale.to_i64().unwrap();
+        // 128-bit arithmetic, so that the full range of 64-bit pixels does not overflow
+        let maxcolor = 255_i128;
+        let minscale = saturating_cast::<T, i128>(minscale);
+        let maxscale = saturating_cast::<T, i128>(maxscale);
         let range = maxscale - minscale;
 
         if f64::abs(gamma - 1.0) < 0.02 {
@@ -57,7 +68,7 @@ where
                     .data
                     .iter()
                     .map(|x| {
-                        let idx = (((*x).to_i64().unwrap() - minscale) * maxcolor / range)
+                        let idx = ((saturating_cast::<T, i128>(*x) - minscale) * maxcolor / range)
                             .clamp(0, 255) as usize;
                         cmap[idx]
                     })
@@ -72,7 +83,8 @@ where
                     .data
                     .iter()
                     .map(|x| {
-                        let scaled = (((*x).to_i64().unwrap() - minscale) as f64 / range as f64)
+                        let scaled = ((saturating_cast::<T, i128>(*x) - minscale) as f64
+                            / range as f64)
                             .clamp(0.0, 1.0);
                         let scaled = f64::powf(scaled, invgamma);
                         let idx = (scaled * maxcolor as f64).clamp(0.0, 255.0) as usize;
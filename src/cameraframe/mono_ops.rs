@@ -1,4 +1,10 @@
 //! This module contains the implementation of the arithmetic operations for the `FrameData` struct.
+//!
+//! The operators overflow as the pixel type does: they panic in debug builds and wrap
+//! in release builds, so that subtracting a dark frame from a u16 frame fails wherever
+//! the dark is brighter.  The saturating, wrapping and widening variants make the
+//! behavior explicit: saturating clamps to the range of the pixel type, wrapping
+//! wraps around it, and widening computes in a wider pixel type that cannot overflow.
 
 use super::FrameData;
 use super::MonoPixel;
@@ -22,6 +28,113 @@ where
             data: vec![T::one(); (width * height) as usize],
         }
     }
+
+    /// Combine the pixels of two frames of the same size into a new frame
+    fn combine<T2, U>(&self, other: &FrameData<T2>, f: impl Fn(T, T2) -> U) -> FrameData<U>
+    where
+        T2: MonoPixel,
+        U: MonoPixel,
+    {
+        assert_eq!(self.width, other.width);
+        assert_eq!(self.height, other.height);
+
+        FrameData::<U> {
+            width: self.width,
+            height: self.height,
+            data: self
+                .data
+                .iter()
+                .zip(other.data.iter())
+                .map(|(a, b)| f(*a, *b))
+                .collect(),
+        }
+    }
+
+    /// Add another frame, clamping the sums to the range of the pixel type
+    pub fn saturating_add(&self, other: &FrameData<T>) -> FrameData<T> {
+        self.combine(other, |a, b| a.saturating_add(b))
+    }
+
+    /// Subtract another frame, clamping the differences to the range of the pixel
+    /// type (for unsigned pixels, to zero where the other frame is brighter)
+    pub fn saturating_sub(&self, other: &FrameData<T>) -> FrameData<T> {
+        self.combine(other, |a, b| a.saturating_sub(b))
+    }
+
+    /// Multiply by another frame, clamping the products to the range of the pixel type
+    pub fn saturating_mul(&self, other: &FrameData<T>) -> FrameData<T> {
+        self.combine(other, |a, b| a.saturating_mul(&b))
+    }
+
+    /// Add another frame, wrapping the sums around the range of the pixel type
+    pub fn wrapping_add(&self, other: &FrameData<T>) -> FrameData<T> {
+        self.combine(other, |a, b| a.wrapping_add(&b))
+    }
+
+    /// Subtract another frame, wrapping the differences around the range of the pixel type
+    pub fn wrapping_sub(&self, other: &FrameData<T>) -> FrameData<T> {
+        self.combine(other, |a, b| a.wrapping_sub(&b))
+    }
+
+    /// Multiply by another frame, wrapping the products around the range of the pixel type
+    pub fn wrapping_mul(&self, other: &FrameData<T>) -> FrameData<T> {
+        self.combine(other, |a, b| a.wrapping_mul(&b))
+    }
+
+    /// Add another frame, with the sums in a wider pixel type `W`
+    ///
+    /// # Panics
+    /// If a pixel value of either frame, or a sum, does not fit in `W`; for
+    /// example u16 frames may be added as u32 (or i32) without overflow
+    pub fn widening_add<T2, W>(&self, other: &FrameData<T2>) -> FrameData<W>
+    where
+        T2: MonoPixel,
+        W: MonoPixel,
+    {
+        self.combine(other, |a, b| {
+            W::from(a)
+                .unwrap()
+                .checked_add(&W::from(b).unwrap())
+                .unwrap()
+        })
+    }
+
+    /// Subtract another frame, with the differences in a wider pixel type `W`
+    ///
+    /// # Panics
+    /// If a pixel value of either frame, or a difference, does not fit in `W`;
+    /// for example a u16 dark frame may be subtracted from a u16 frame as i32,
+    /// keeping the negative differences
+    pub fn widening_sub<T2, W>(&self, other: &FrameData<T2>) -> FrameData<W>
+    where
+        T2: MonoPixel,
+        W: MonoPixel,
+    {
+        self.combine(other, |a, b| {
+            W::from(a)
+                .unwrap()
+                .checked_sub(&W::from(b).unwrap())
+                .unwrap()
+        })
+    }
+
+    /// Multiply by another frame, with the products in a wider pixel type `W`
+    ///
+    /// # Panics
+    /// If a pixel value of either frame, or a product, does not fit in `W`; for
+    /// example u16 frames may be multiplied as u32 without overflow
+    pub fn widening_mul<T2, W>(&self, other: &FrameData<T2>) -> FrameData<W>
+    where
+        T2: MonoPixel,
+        W: MonoPixel,
+    {
+        self.combine(other, |a, b| {
+            W::from(a)
+                .unwrap()
+                .checked_mul(&W::from(b).unwrap())
+                .unwrap()
+        })
+    }
 }
 
 impl<T> std::ops::Shl<usize> for &FrameData<T>
@@ -245,4 +358,94 @@ mod test {
         assert_eq!(frame3.height, 3);
         assert_eq!(frame3.data, vec![2; 9]);
     }
+
+    #[test]
+    fn test_saturating_sub() {
+        // A dark frame brighter than the light frame in some pixels
+        let light = FrameData::<u16> {
+            width: 2,
+            height: 2,
+            data: vec![100, 5, 65535, 0],
+        };
+        let dark = FrameData::<u16> {
+            width: 2,
+            height: 2,
+            data: vec![10, 10, 0, 65535],
+        };
+        assert_eq!(light.saturating_sub(&dark).data, vec![90, 0, 65535, 0]);
+        assert_eq!(light.wrapping_sub(&dark).data, vec![90, 65531, 65535, 1]);
+        assert_eq!(
+            light.widening_sub::<u16, i32>(&dark).data,
+            vec![90, -5, 65535, -65535]
+        );
+        assert_eq!(
+            light.saturating_add(&dark).data,
+            vec![110, 15, 65535, 65535]
+        );
+    }
+
+    /// Saturating, wrapping and widening arithmetic at the extreme values of a pixel type
+    fn check_extremes<T: MonoPixel>() {
+        let (lo, hi, one) = (T::min_value(), T::max_value(), T::one());
+        let a = FrameData::<T> {
+            width: 3,
+            height: 1,
+            data: vec![lo, hi, hi],
+        };
+        let b = FrameData::<T> {
+            width: 3,
+            height: 1,
+            data: vec![one, one, hi],
+        };
+        assert_eq!(a.saturating_add(&b).data, vec![lo + one, hi, hi]);
+        assert_eq!(a.saturating_sub(&b).data, vec![lo, hi - one, T::zero()]);
+        assert_eq!(a.saturating_mul(&b).data, vec![lo, hi, hi]);
+        // Twice the highest value wraps to one less than it unsigned, and to -2 signed
+        let twice = match lo < T::zero() {
+            true => T::zero() - one - one,
+            false => hi - one,
+        };
+        assert_eq!(a.wrapping_add(&b).data, vec![lo + one, lo, twice]);
+        assert_eq!(a.wrapping_sub(&b).data, vec![hi, hi - one, T::zero()]);
+        assert_eq!(a.wrapping_mul(&b).data, vec![lo, hi, one]);
+
+        // Widened to i128, for the types whose sums fit in it
+        if let Some(wide) = hi.to_i128().and_then(|h| h.checked_mul(2)) {
+            let sum = a.widening_add::<T, i128>(&b);
+            assert_eq!(sum.data[2], wide);
+            let diff = b.widening_sub::<T, i128>(&a);
+            assert_eq!(diff.data[0], 1 - lo.to_i128().unwrap());
+        }
+    }
+
+    #[test]
+    fn test_extreme_values() {
+        check_extremes::<u8>();
+        check_extremes::<u16>();
+        check_extremes::<u32>();
+        check_extremes::<u64>();
+        check_extremes::<u128>();
+        check_extremes::<usize>();
+        check_extremes::<i8>();
+        check_extremes::<i16>();
+        check_extremes::<i32>();
+        check_extremes::<i64>();
+        check_extremes::<i128>();
+        check_extremes::<isize>();
+    }
+
+    #[test]
+    fn test_widening_types() {
+        let frame1 = &FrameData::<u16>::ones(2, 2) * 65535_u16;
+        let frame2 = &FrameData::<u8>::ones(2, 2) * 255_u8;
+        assert_eq!(frame1.widening_add::<u8, u32>(&frame2).data, vec![65790; 4]);
+        assert_eq!(
+            frame1.widening_mul::<u8, u32>(&frame2).data,
+            vec![16711425; 4]
+        );
+        assert_eq!(
+            frame2.widening_sub::<u16, i32>(&frame1).data,
+            vec![-65280; 4]
+        );
+    }
 }
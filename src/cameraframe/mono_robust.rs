@@ -0,0 +1,380 @@
+//!
+//! Robust statistics on FrameData with monochrome pixel values.
+//!
+//! Medians, percentiles and sigma-clipped statistics are insensitive to the
+//! outliers (hot pixels, cosmic rays, stars) that dominate the mean and standard
+//! deviation.  Each takes an optional mask the size of the frame: pixels where
+//! the mask is non-zero, such as those flagged in a bad pixel map, are ignored.
+//!
+//! Statistics of a region of interest are those of `frame.roi(&roi)`, masked by
+//! `mask.roi(&roi)`.
+//!
+//! The same statistics of plain values (e.g. of the pixels of a box, or of one
+//! pixel across a stack of frames) are found with the functions `median`, `mad`,
+//! `percentiles` and `sigma_clipped`.
+//!
+
+use super::FrameData;
+use super::MonoPixel;
+
+/// Scale from the median absolute deviation to the standard deviation of a normal distribution
+pub const MAD_TO_SIGMA: f64 = 1.4826;
+
+/// How outliers are rejected by sigma clipping
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SigmaClip {
+    /// Values further than this many standard deviations from the median are rejected
+    pub nsigma: f64,
+    /// Maximum number of rejection passes; clipping stops early when none are rejected
+    pub iterations: usize,
+}
+
+impl Default for SigmaClip {
+    fn default() -> Self {
+        Self {
+            nsigma: 3.0,
+            iterations: 5,
+        }
+    }
+}
+
+/// Statistics of the values left after sigma clipping
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ClippedStats {
+    pub mean: f64,
+    pub median: f64,
+    /// Standard deviation
+    pub sigma: f64,
+    /// Number of values kept
+    pub npixels: usize,
+    /// Number of values rejected
+    pub nrejected: usize,
+    /// Number of rejection passes made
+    pub iterations: usize,
+}
+
+/// Robust summary statistics of the pixels in a frame (or region of a frame)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RobustStats {
+    pub median: f64,
+    /// Median absolute deviation from the median
+    pub mad: f64,
+    /// Sigma-clipped statistics
+    pub clipped: ClippedStats,
+    /// Number of pixels not masked
+    pub npixels: usize,
+}
+
+impl RobustStats {
+    /// Standard deviation estimated from the median absolute deviation
+    pub fn sigma_mad(&self) -> f64 {
+        MAD_TO_SIGMA * self.mad
+    }
+}
+
+impl<T> FrameData<T>
+where
+    T: MonoPixel,
+{
+    /// Values of the pixels that are not masked, or None if the mask is not the size of the frame
+    fn unmasked(&self, mask: Option<&FrameData<u8>>) -> Option<Vec<f64>> {
+        let values = self.data.iter().map(|x| x.to_f64().unwrap());
+        match mask {
+            None => Some(values.collect()),
+            Some(m) if m.width == self.width && m.height == self.height => Some(
+                values
+                    .zip(m.data.iter())
+                    .filter(|(_, m)| **m == 0)
+                    .map(|(x, _)| x)
+                    .collect(),
+            ),
+            Some(_) => None,
+        }
+    }
+
+    /// Calculate the median of the pixel values.
+    ///
+    /// # Arguments
+    /// * `mask` - Pixels where the mask is non-zero are ignored
+    ///
+    /// # Returns
+    /// The median (the mean of the middle two values for an even number of
+    /// pixels), or None if no pixels are unmasked or the mask is not the size of the frame
+    ///
+    pub fn median(&self, mask: Option<&FrameData<u8>>) -> Option<f64> {
+        let mut values = self.unmasked(mask)?;
+        median(&mut values)
+    }
+
+    /// Calculate the median absolute deviation of the pixel values from their median.
+    ///
+    /// # Arguments
+    /// * `mask` - Pixels where the mask is non-zero are ignored
+    ///
+    /// # Returns
+    /// The median absolute deviation, or None if no pixels are unmasked or the
+    /// mask is not the size of the frame
+    ///
+    pub fn mad(&self, mask: Option<&FrameData<u8>>) -> Option<f64> {
+        let mut values = self.unmasked(mask)?;
+        mad(&mut values)
+    }
+
+    /// Calculate percentiles of the pixel values, interpolating between neighboring values.
+    ///
+    /// # Arguments
+    /// * `pcts` - The percentiles, from 0 to 100
+    /// * `mask` - Pixels where the mask is non-zero are ignored
+    ///
+    /// # Returns
+    /// The value at each percentile, or None if no pixels are unmasked or the
+    /// mask is not the size of the frame
+    ///
+    pub fn percentiles(&self, pcts: &[f64], mask: Option<&FrameData<u8>>) -> Option<Vec<f64>> {
+        let mut values = self.unmasked(mask)?;
+        percentiles(&mut values, pcts)
+    }
+
+    /// Calculate the statistics of the pixel values after sigma clipping.
+    ///
+    /// Values further from the median than `clip.nsigma` standard deviations are
+    /// rejected, and the median and standard deviation recomputed from the
+    /// rest, until none are rejected or `clip.iterations` passes have been made.
+    ///
+    /// # Arguments
+    /// * `clip` - The rejection threshold and maximum number of passes
+    /// * `mask` - Pixels where the mask is non-zero are ignored
+    ///
+    /// # Returns
+    /// The statistics of the values kept, or None if no pixels are unmasked or
+    /// the mask is not the size of the frame
+    ///
+    pub fn sigma_clipped(
+        &self,
+        clip: &SigmaClip,
+        mask: Option<&FrameData<u8>>,
+    ) -> Option<ClippedStats> {
+        let mut values = self.unmasked(mask)?;
+        sigma_clipped(&mut values, clip)
+    }
+
+    /// Calculate robust summary statistics of the pixel values.
+    ///
+    /// # Arguments
+    /// * `clip` - Sigma clipping of the clipped statistics
+    /// * `mask` - Pixels where the mask is non-zero are ignored
+    ///
+    /// # Returns
+    /// The median, median absolute deviation and sigma-clipped statistics, or
+    /// None if no pixels are unmasked or the mask is not the size of the frame
+    ///
+    pub fn robust_stats(
+        &self,
+        clip: &SigmaClip,
+        mask: Option<&FrameData<u8>>,
+    ) -> Option<RobustStats> {
+        let mut values = self.unmasked(mask)?;
+        let npixels = values.len();
+        let median = median(&mut values)?;
+        let mad = mad(&mut values)?;
+        let clipped = sigma_clipped(&mut values, clip)?;
+        Some(RobustStats {
+            median,
+            mad,
+            clipped,
+            npixels,
+        })
+    }
+}
+
+/// Median of values
+///
+/// # Returns
+/// The median (the mean of the middle two values for an even number of
+/// values), or None if there are no values.  The values are reordered.
+///
+pub fn median(values: &mut [f64]) -> Option<f64> {
+    let n = values.len();
+    if n == 0 {
+        return None;
+    }
+    let (lower, mid, _) = values.select_nth_unstable_by(n / 2, |a, b| a.total_cmp(b));
+    let mid = *mid;
+    match n % 2 {
+        1 => Some(mid),
+        // The other middle value is the largest of those below
+        _ => Some((lower.iter().copied().fold(f64::MIN, f64::max) + mid) / 2.0),
+    }
+}
+
+/// Median absolute deviation of values from their median
+///
+/// # Returns
+/// The median absolute deviation, or None if there are no values.  The values are reordered.
+///
+pub fn mad(values: &mut [f64]) -> Option<f64> {
+    let center = median(values)?;
+    let mut deviations = values
+        .iter()
+        .map(|x| (x - center).abs())
+        .collect::<Vec<f64>>();
+    median(&mut deviations)
+}
+
+/// Percentiles of values, interpolating between neighboring values
+///
+/// # Arguments
+/// * `values` - The values, which are sorted
+/// * `pcts` - The percentiles, from 0 to 100
+///
+/// # Returns
+/// The value at each percentile, or None if there are no values
+///
+pub fn percentiles(values: &mut [f64], pcts: &[f64]) -> Option<Vec<f64>> {
+    if values.is_empty() {
+        return None;
+    }
+    values.sort_by(|a, b| a.total_cmp(b));
+    let last = (values.len() - 1) as f64;
+    Some(
+        pcts.iter()
+            .map(|pct| {
+                let pos = (pct / 100.0 * last).clamp(0.0, last);
+                let i = pos.floor() as usize;
+                match values.get(i + 1) {
+                    Some(next) => values[i] + (next - values[i]) * (pos - i as f64),
+                    None => values[i],
+                }
+            })
+            .collect(),
+    )
+}
+
+/// Sigma-clipped statistics of values
+///
+/// Values further from the median than `clip.nsigma` standard deviations are
+/// rejected, and the median and standard deviation recomputed from the rest,
+/// until none are rejected, a pass would reject them all, or `clip.iterations`
+/// passes have been made.
+///
+/// # Returns
+/// The statistics of the values kept, or None if there are no values.  The
+/// values are reordered, with those kept first.
+///
+pub fn sigma_clipped(values: &mut [f64], clip: &SigmaClip) -> Option<ClippedStats> {
+    let mean_and_sigma = |values: &[f64]| {
+        let n = values.len() as f64;
+        let mean = values.iter().sum::<f64>() / n;
+        let var = values.iter().map(|x| (x - mean) * (x - mean)).sum::<f64>() / n;
+        (mean, var.sqrt())
+    };
+    let mut kept = values.len();
+    let mut iterations = 0;
+    while iterations < clip.iterations {
+        let center = median(&mut values[..kept])?;
+        let (_, sigma) = mean_and_sigma(&values[..kept]);
+        iterations += 1;
+        // Move the values to keep to the front
+        let mut n = 0;
+        for i in 0..kept {
+            if (values[i] - center).abs() <= clip.nsigma * sigma {
+                values.swap(i, n);
+                n += 1;
+            }
+        }
+        if n == kept || n == 0 {
+            break;
+        }
+        kept = n;
+    }
+    let median = median(&mut values[..kept])?;
+    let (mean, sigma) = mean_and_sigma(&values[..kept]);
+    Some(ClippedStats {
+        mean,
+        median,
+        sigma,
+        npixels: kept,
+        nrejected: values.len() - kept,
+        iterations,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cameraframe::Roi;
+
+    #[test]
+    fn test_median_percentiles() {
+        let frame = FrameData::<i16> {
+            width: 3,
+            height: 2,
+            data: vec![5, -1, 3, 100, 2, 4],
+        };
+        assert_eq!(frame.median(None), Some(3.5));
+        // Deviations from 3.5 are 1.5, 4.5, 0.5, 96.5, 1.5, 0.5
+        assert_eq!(frame.mad(None), Some(1.5));
+        assert_eq!(
+            frame.percentiles(&[0.0, 50.0, 100.0, 10.0], None),
+            Some(vec![-1.0, 3.5, 100.0, 0.5])
+        );
+
+        // Masking the outlier and one other pixel
+        let mask = FrameData::<u8> {
+            width: 3,
+            height: 2,
+            data: vec![0, 1, 0, 4, 0, 0],
+        };
+        assert_eq!(frame.median(Some(&mask)), Some(3.5));
+        assert_eq!(frame.percentiles(&[100.0], Some(&mask)), Some(vec![5.0]));
+        // All masked, or a mask of the wrong size
+        assert_eq!(frame.median(Some(&FrameData::ones(3, 2))), None);
+        assert_eq!(frame.median(Some(&FrameData::zeros(2, 3))), None);
+    }
+
+    #[test]
+    fn test_sigma_clipped() {
+        // Noise of 10, with 1% of pixels saturated
+        let mut frame = FrameData::<u16>::rand_norm(1000.0, 10.0, 100, 100);
+        for i in (0..10000).step_by(100) {
+            frame.data[i] = 65535;
+        }
+        let (mean, var) = frame.mean_and_var();
+        assert!(mean > 1500.0 && var.sqrt() > 5000.0);
+
+        let stats = frame.robust_stats(&SigmaClip::default(), None).unwrap();
+        assert_eq!(stats.npixels, 10000);
+        // The noise is truncated to integers, lowering the values by half a count
+        assert!((stats.median - 999.5).abs() < 1.0);
+        assert!((stats.sigma_mad() - 10.0).abs() < 1.0);
+        let clipped = stats.clipped;
+        assert!((clipped.mean - 999.5).abs() < 1.0);
+        assert!((clipped.sigma - 10.0).abs() < 1.0);
+        assert!(clipped.nrejected >= 100 && clipped.nrejected < 150);
+        assert_eq!(clipped.npixels + clipped.nrejected, 10000);
+        assert!(clipped.iterations > 1 && clipped.iterations <= 5);
+
+        // Masking the saturated pixels instead, in a region of interest
+        let mask = FrameData::<u8> {
+            width: 100,
+            height: 100,
+            data: frame.data.iter().map(|x| (*x == 65535) as u8).collect(),
+        };
+        let roi = Roi {
+            x: 0,
+            y: 0,
+            width: 50,
+            height: 100,
+        };
+        let region = frame.roi(&roi).unwrap();
+        let once = SigmaClip {
+            nsigma: 3.0,
+            iterations: 1,
+        };
+        let stats = region
+            .sigma_clipped(&once, Some(&mask.roi(&roi).unwrap()))
+            .unwrap();
+        assert_eq!(stats.npixels + stats.nrejected, 5000 - 100);
+        assert!((stats.mean - 999.5).abs() < 1.0);
+    }
+}
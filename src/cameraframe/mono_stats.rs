@@ -1,10 +1,34 @@
 //!
 //! This module contains functions for calculating statistics on FrameData with monochrome pixel values.
 //!
+//! Sums are accumulated in f64 with compensated (Kahan-Babuska) summation, and the
+//! variance with Welford's algorithm, so that they neither overflow for the widest
+//! pixel types nor lose precision over large frames.
+//!
 
 use super::FrameData;
 use super::MonoPixel;
 
+/// Summary statistics of the pixels in a frame (or region of a frame)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FrameStats<T>
+where
+    T: MonoPixel,
+{
+    /// Mean pixel value
+    pub mean: f64,
+    /// Standard deviation of the pixel values
+    pub sigma: f64,
+    /// Minimum pixel value
+    pub min: T,
+    /// Maximum pixel value
+    pub max: T,
+    /// Sum of the pixel values
+    pub sum: f64,
+    /// Number of pixels
+    pub npixels: usize,
+}
+
 impl<T> FrameData<T>
 where
     T: MonoPixel,
@@ -14,7 +38,7 @@ where
     /// # Returns
     /// The mean of the data in the FrameData.
     pub fn mean(&self) -> f64 {
-        self.data.iter().fold(0, |acc, x| acc + x.to_i64().unwrap()) as f64 / self.data.len() as f64
+        self.sum() / self.data.len() as f64
     }
 
     /// Calculate the mean and variance of the data in the FrameData.
@@ -23,13 +47,7 @@ where
     /// A tuple containing the mean and variance of the data in the FrameData.
     ///
     pub fn mean_and_var(&self) -> (f64, f64) {
-        let (sum, sumsq) = self.data.iter().fold((0, 0), |acc, x| {
-            let (sum, sum_sq) = acc;
-            let x = x.to_i64().unwrap();
-            (sum + x, sum_sq + x * x)
-        });
-        let mean = sum as f64 / self.data.len() as f64;
-        (mean, sumsq as f64 / self.data.len() as f64 - mean * mean)
+        welford(self.data.iter().map(|x| x.to_f64().unwrap()))
     }
 
     /// Calculate the sum of the data in the FrameData.
@@ -37,8 +55,8 @@ where
     /// # Returns
     /// The sum of the data in the FrameData.
     ///
-    pub fn sum(&self) -> i64 {
-        self.data.iter().fold(0, |acc, x| acc + x.to_i64().unwrap())
+    pub fn sum(&self) -> f64 {
+        compensated_sum(self.data.iter().map(|x| x.to_f64().unwrap()))
     }
 
     /// Calculate the sum of the squares of the data in the FrameData.
@@ -46,11 +64,11 @@ where
     /// # Returns
     /// The sum of the squares of the data in the FrameData.
     ///
-    pub fn sumsq(&self) -> i64 {
-        self.data.iter().fold(0, |acc, x| {
-            let x = x.to_i64().unwrap();
-            acc + x * x
-        })
+    pub fn sumsq(&self) -> f64 {
+        compensated_sum(self.data.iter().map(|x| {
+            let x = x.to_f64().unwrap();
+            x * x
+        }))
     }
 
     /// Calculate the maximum value of the data in the FrameData.
@@ -89,6 +107,24 @@ where
         (min, max)
     }
 
+    /// Calculate summary statistics of the data in the FrameData.
+    ///
+    /// # Returns
+    /// The mean, standard deviation, minimum, maximum, sum and number of pixels.
+    ///
+    pub fn stats(&self) -> FrameStats<T> {
+        let (mean, var) = self.mean_and_var();
+        let (min, max) = self.minmax();
+        FrameStats {
+            mean,
+            sigma: var.max(0.0).sqrt(),
+            min,
+            max,
+            sum: self.sum(),
+            npixels: self.data.len(),
+        }
+    }
+
     /// Used only for testing:
     ///
     /// Generate a FrameData with random values drawn from a normal distribution.
@@ -120,6 +156,41 @@ where
     }
 }
 
+/// Mean and variance of values, with Welford's algorithm: update the mean and
+/// the sum of squared deviations from it, so that a large mean does not cancel
+/// out the variance
+///
+/// # Returns
+/// The mean and (population) variance, or NaN for both if there are no values
+///
+pub fn welford(values: impl Iterator<Item = f64>) -> (f64, f64) {
+    let (n, mean, m2) = values.fold((0.0, 0.0, 0.0), |(n, mean, m2), x| {
+        let n = n + 1.0;
+        let delta = x - mean;
+        let mean = mean + delta / n;
+        (n, mean, m2 + delta * (x - mean))
+    });
+    match n > 0.0 {
+        true => (mean, m2 / n),
+        false => (f64::NAN, f64::NAN),
+    }
+}
+
+/// Sum of values, compensating for the rounding error of each addition
+/// (the Kahan-Babuska, or Neumaier, algorithm)
+pub fn compensated_sum(values: impl Iterator<Item = f64>) -> f64 {
+    let (sum, compensation) = values.fold((0.0_f64, 0.0), |(sum, c), x| {
+        let t = sum + x;
+        // Recover the low-order bits lost from whichever term is smaller
+        let c = match sum.abs() >= x.abs() {
+            true => c + ((sum - t) + x),
+            false => c + ((x - t) + sum),
+        };
+        (t, c)
+    });
+    sum + compensation
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -146,6 +217,22 @@ mod tests {
         assert!((var - 6.666666666666667).abs() < 1e-6);
     }
 
+    #[test]
+    fn test_stats() {
+        let frame = FrameData::<u8> {
+            width: 3,
+            height: 3,
+            data: vec![1, 2, 3, 4, 5, 6, 7, 8, 9],
+        };
+        let stats = frame.stats();
+        assert_eq!(stats.mean, 5.0);
+        assert!((stats.sigma - 6.666666666666667_f64.sqrt()).abs() < 1e-6);
+        assert_eq!(stats.min, 1);
+        assert_eq!(stats.max, 9);
+        assert_eq!(stats.sum, 45.0);
+        assert_eq!(stats.npixels, 9);
+    }
+
     #[test]
     fn test_large_mean_and_var() {
         let frame = FrameData::<u16>::rand_norm(1000.0, 100.0, 1000, 1000);
@@ -154,4 +241,68 @@ mod tests {
         assert!((mean - 1000.0).abs() < 5.0);
         assert!((var - 10000.0).abs() < 40.0);
     }
+
+    /// Statistics of a frame holding the lowest and highest values of a pixel type,
+    /// which overflow the squares and sums of the pixel type itself
+    fn check_extremes<T: MonoPixel>() {
+        let (lo, hi) = (T::min_value(), T::max_value());
+        let frame = FrameData::<T> {
+            width: 2,
+            height: 2,
+            data: vec![lo, hi, hi, lo],
+        };
+        let (flo, fhi) = (lo.to_f64().unwrap(), hi.to_f64().unwrap());
+        let close = |a: f64, b: f64| (a - b).abs() <= 1e-12 * b.abs().max(1.0);
+        assert!(close(frame.sum(), 2.0 * (flo + fhi)));
+        assert!(close(frame.sumsq(), 2.0 * (flo * flo + fhi * fhi)));
+        let (mean, var) = frame.mean_and_var();
+        assert!(close(mean, (flo + fhi) / 2.0));
+        assert!(close(var, (fhi - flo) * (fhi - flo) / 4.0));
+
+        let stats = frame.stats();
+        assert_eq!((stats.min, stats.max), (lo, hi));
+        assert!(close(stats.sigma, (fhi - flo) / 2.0));
+
+        // A constant frame at the highest value has no variance
+        let frame = FrameData::<T> {
+            width: 3,
+            height: 1,
+            data: vec![hi; 3],
+        };
+        let (mean, var) = frame.mean_and_var();
+        assert!(close(mean, fhi));
+        assert_eq!(var, 0.0);
+    }
+
+    #[test]
+    fn test_extreme_values() {
+        check_extremes::<u8>();
+        check_extremes::<u16>();
+        check_extremes::<u32>();
+        check_extremes::<u64>();
+        check_extremes::<u128>();
+        check_extremes::<usize>();
+        check_extremes::<i8>();
+        check_extremes::<i16>();
+        check_extremes::<i32>();
+        check_extremes::<i64>();
+        check_extremes::<i128>();
+        check_extremes::<isize>();
+    }
+
+    #[test]
+    fn test_compensated_sum() {
+        // Small values added to a large one are lost by naive summation
+        let values = std::iter::once(1e16).chain(std::iter::repeat_n(1.0, 1000));
+        assert_eq!(compensated_sum(values.clone()), 1e16 + 1000.0);
+        assert_ne!(values.sum::<f64>(), 1e16 + 1000.0);
+
+        // The variance is found without cancellation around a large mean
+        let frame = FrameData::<u64> {
+            width: 4,
+            height: 1,
+            data: vec![1 << 40, (1 << 40) + 1, (1 << 40) + 2, (1 << 40) + 3],
+        };
+        assert_eq!(frame.mean_and_var(), ((1u64 << 40) as f64 + 1.5, 1.25));
+    }
 }
@@ -3,8 +3,34 @@ pub trait Pixel: Sized + Clone + Copy + std::fmt::Debug + Send + Sync + 'static
 
 /// A trait for pixels that are monochromatic.
 /// These are generally pixels of type u8, u16, u32, etc...
-pub trait MonoPixel: Pixel + num_traits::PrimInt {}
-impl<T> MonoPixel for T where T: Pixel + num_traits::PrimInt {}
+/// All support saturating and wrapping arithmetic, for frame arithmetic that must not overflow.
+pub trait MonoPixel:
+    Pixel
+    + num_traits::PrimInt
+    + num_traits::SaturatingMul
+    + num_traits::WrappingAdd
+    + num_traits::WrappingSub
+    + num_traits::WrappingMul
+{
+}
+impl<T> MonoPixel for T where
+    T: Pixel
+        + num_traits::PrimInt
+        + num_traits::SaturatingMul
+        + num_traits::WrappingAdd
+        + num_traits::WrappingSub
+        + num_traits::WrappingMul
+{
+}
+
+/// Convert a monochromatic pixel value to another integer type, saturating at
+/// the ends of its range instead of failing, e.g. to show 64-bit values in the GUI
+pub fn saturating_cast<T: MonoPixel, U: num_traits::PrimInt>(v: T) -> U {
+    U::from(v).unwrap_or(match v < T::zero() {
+        true => U::min_value(),
+        false => U::max_value(),
+    })
+}
 
 /// A pixel with red, green, and blue channels.
 /// Each channel is an 8-bit unsigned integer.
@@ -32,8 +58,14 @@ pub struct RGBAPixel {
     pub a: u8,
 }
 
-/// Implement the Pixel trait for any type that implements num_traits::PrimInt and std::fmt::Debug.
-impl<T> Pixel for T where T: num_traits::PrimInt + std::fmt::Debug + Send + Sync + 'static {}
+/// Implement the Pixel trait for the primitive integer types, which are also monochromatic
+/// pixels, and for the floating-point types used for calibration and stacking results.
+macro_rules! impl_pixel {
+    ($($t:ty),*) => {
+        $(impl Pixel for $t {})*
+    };
+}
+impl_pixel!(u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize, f32, f64);
 
 /// Implement the pixel trait for the RGB pixel type.
 impl Pixel for RGBPixel {}
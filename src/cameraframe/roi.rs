@@ -0,0 +1,111 @@
+//!
+//! Rectangular regions of interest within a frame
+//!
+
+use super::FrameData;
+use super::Pixel;
+
+/// A rectangular region of interest, in pixel coordinates
+///
+/// The top-left corner (`x`, `y`) is inclusive, and the region extends
+/// `width` columns to the right and `height` rows down.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Roi {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Roi {
+    /// Clip the region so that it lies within a frame of the given size
+    ///
+    /// # Arguments
+    /// * `width` - Width of the frame
+    /// * `height` - Height of the frame
+    ///
+    /// # Returns
+    /// The clipped region, or None if no part of the region lies within the frame
+    ///
+    pub fn clip(&self, width: u32, height: u32) -> Option<Roi> {
+        let x1 = self.x.saturating_add(self.width).min(width);
+        let y1 = self.y.saturating_add(self.height).min(height);
+        if self.x >= x1 || self.y >= y1 {
+            return None;
+        }
+        Some(Roi {
+            x: self.x,
+            y: self.y,
+            width: x1 - self.x,
+            height: y1 - self.y,
+        })
+    }
+
+    /// Number of pixels in the region
+    pub fn npixels(&self) -> usize {
+        self.width as usize * self.height as usize
+    }
+}
+
+impl<T> FrameData<T>
+where
+    T: Pixel,
+{
+    /// Copy a region of interest out of the frame
+    ///
+    /// # Arguments
+    /// * `roi` - The region of interest; it is clipped to the frame
+    ///
+    /// # Returns
+    /// A new FrameData containing the region, or None if the region does not overlap the frame
+    ///
+    pub fn roi(&self, roi: &Roi) -> Option<FrameData<T>> {
+        let roi = roi.clip(self.width, self.height)?;
+        Some(self.subregion(roi.x, roi.y, roi.x + roi.width, roi.y + roi.height))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clip() {
+        let roi = Roi {
+            x: 8,
+            y: 2,
+            width: 10,
+            height: 4,
+        };
+        assert_eq!(
+            roi.clip(12, 5),
+            Some(Roi {
+                x: 8,
+                y: 2,
+                width: 4,
+                height: 3
+            })
+        );
+        assert_eq!(roi.clip(8, 8), None);
+    }
+
+    #[test]
+    fn test_roi() {
+        let frame = FrameData::<u16> {
+            width: 4,
+            height: 3,
+            data: (0..12).collect(),
+        };
+        let region = frame
+            .roi(&Roi {
+                x: 1,
+                y: 1,
+                width: 10,
+                height: 10,
+            })
+            .unwrap();
+        assert_eq!(region.width, 3);
+        assert_eq!(region.height, 2);
+        assert_eq!(region.data, vec![5, 6, 7, 9, 10, 11]);
+    }
+}
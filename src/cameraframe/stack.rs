@@ -0,0 +1,167 @@
+//!
+//! Pixel-by-pixel combination of frames, e.g. to build master calibration frames
+//!
+
+use super::median;
+use super::sigma_clipped;
+use super::FrameData;
+use super::Pixel;
+use super::SigmaClip;
+
+use std::error::Error;
+
+/// How the values of a pixel in a stack of frames are combined
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CombineMethod {
+    Mean,
+    Median,
+    /// Mean of the values within `sigma` standard deviations of the median,
+    /// repeated until no more values are rejected or for at most `iterations`
+    SigmaClippedMean {
+        sigma: f64,
+        iterations: usize,
+    },
+}
+
+impl CombineMethod {
+    /// Combine method from its name ("Mean", "Median" or "Sigma Clip", case-insensitive)
+    ///
+    /// "Sigma Clip" rejects values beyond 3 sigma, in up to 5 iterations
+    pub fn from_string(name: &str) -> Option<CombineMethod> {
+        match name.to_lowercase().as_str() {
+            "mean" => Some(CombineMethod::Mean),
+            "median" => Some(CombineMethod::Median),
+            "sigma clip" => Some(CombineMethod::SigmaClippedMean {
+                sigma: 3.0,
+                iterations: 5,
+            }),
+            _ => None,
+        }
+    }
+
+    /// Name of the combine method, as accepted by `from_string`
+    pub fn name(&self) -> &'static str {
+        match self {
+            CombineMethod::Mean => "Mean",
+            CombineMethod::Median => "Median",
+            CombineMethod::SigmaClippedMean { .. } => "Sigma Clip",
+        }
+    }
+
+    /// Combine the values of one pixel; `values` is reordered
+    fn combine(&self, values: &mut [f64]) -> f64 {
+        match *self {
+            CombineMethod::Mean => mean(values),
+            CombineMethod::Median => median(values).unwrap_or(f64::NAN),
+            CombineMethod::SigmaClippedMean { sigma, iterations } => {
+                let clip = SigmaClip {
+                    nsigma: sigma,
+                    iterations,
+                };
+                sigma_clipped(values, &clip).map_or(f64::NAN, |s| s.mean)
+            }
+        }
+    }
+}
+
+fn mean(values: &[f64]) -> f64 {
+    values.iter().sum::<f64>() / values.len() as f64
+}
+
+impl<T> FrameData<T>
+where
+    T: Pixel + num_traits::ToPrimitive,
+{
+    /// Combine a stack of frames pixel by pixel.
+    ///
+    /// Values are accumulated in double precision, so large stacks of integer
+    /// frames cannot overflow.
+    ///
+    /// # Arguments
+    /// * `frames` - The frames to combine; all must have the same size
+    /// * `method` - How the values of each pixel are combined
+    ///
+    /// # Returns
+    /// The combined frame, or an error if there are no frames or their sizes differ.
+    ///
+    pub fn stack(
+        frames: &[FrameData<T>],
+        method: CombineMethod,
+    ) -> Result<FrameData<f32>, Box<dyn Error>> {
+        let first = frames.first().ok_or("No frames to stack")?;
+        if frames
+            .iter()
+            .any(|f| f.width != first.width || f.height != first.height)
+        {
+            return Err("Frames to stack differ in size".into());
+        }
+        let mut values = vec![0.0; frames.len()];
+        let data = (0..first.data.len())
+            .map(|i| {
+                values
+                    .iter_mut()
+                    .zip(frames.iter())
+                    .for_each(|(v, f)| *v = f.data[i].to_f64().unwrap());
+                method.combine(&mut values) as f32
+            })
+            .collect();
+        Ok(FrameData {
+            width: first.width,
+            height: first.height,
+            data,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frames(values: &[u16]) -> Vec<FrameData<u16>> {
+        values
+            .iter()
+            .map(|v| FrameData {
+                width: 2,
+                height: 1,
+                data: vec![*v, 10],
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_combine() {
+        let stack = frames(&[10, 12, 11, 13, 10, 12, 11, 1000]);
+        let mean = FrameData::stack(&stack, CombineMethod::Mean).unwrap();
+        assert_eq!(mean.data, vec![134.875, 10.0]);
+        let median = FrameData::stack(&stack, CombineMethod::Median).unwrap();
+        assert_eq!(median.data, vec![11.5, 10.0]);
+        let clipped =
+            FrameData::stack(&stack, CombineMethod::from_string("Sigma Clip").unwrap()).unwrap();
+        assert_eq!(clipped.data, vec![11.285714, 10.0]);
+    }
+
+    #[test]
+    fn test_no_overflow() {
+        let stack = frames(&[65535; 100]);
+        let mean = FrameData::stack(&stack, CombineMethod::Mean).unwrap();
+        assert_eq!(mean.data[0], 65535.0);
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(FrameData::<u16>::stack(&[], CombineMethod::Mean).is_err());
+        let mut stack = frames(&[1, 2]);
+        stack.push(FrameData::<u16>::zeros(1, 2));
+        assert!(FrameData::stack(&stack, CombineMethod::Median).is_err());
+        // Floating-point frames can be stacked too
+        let floats = vec![FrameData::<f32> {
+            width: 1,
+            height: 1,
+            data: vec![0.5],
+        }];
+        assert_eq!(
+            FrameData::stack(&floats, CombineMethod::Mean).unwrap().data,
+            vec![0.5]
+        );
+    }
+}
@@ -0,0 +1,500 @@
+//!
+//! Reading and writing of TIFF files, including multi-page TIFFs holding a
+//! sequence of camera frames.
+//!
+//! Per-frame metadata of a sequence is stored in the ImageDescription tag of
+//! each page as space-separated `key=value` pairs, with the standard DateTime
+//! tag also set (to one-second resolution) for the benefit of other readers.
+//!
+
+use super::CameraFrame;
+use super::FrameData;
+use super::MonoPixel;
+use super::RGBAPixel;
+use super::RGBPixel;
+
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Seek, Write};
+
+use tiff::decoder::{Decoder, DecodingResult};
+use tiff::encoder::{colortype, TiffEncoder, TiffValue};
+use tiff::tags::Tag;
+use tiff::ColorType;
+
+/// Format of the center of integration in the ImageDescription tag
+const DATE_FORMAT: &str = "%Y-%m-%dT%H:%M:%S%.6fZ";
+
+/// Write one page with the given color type, and optional description
+fn write_page<C, W>(
+    encoder: &mut TiffEncoder<W>,
+    width: u32,
+    height: u32,
+    data: &[C::Inner],
+    description: Option<(&str, &str)>,
+) -> Result<(), Box<dyn Error>>
+where
+    C: colortype::ColorType,
+    [C::Inner]: TiffValue,
+    W: Write + Seek,
+{
+    let mut image = encoder.new_image::<C>(width, height)?;
+    if let Some((description, datetime)) = description {
+        image
+            .encoder()
+            .write_tag(Tag::ImageDescription, description)?;
+        image.encoder().write_tag(Tag::DateTime, datetime)?;
+    }
+    image.write_data(data)?;
+    Ok(())
+}
+
+/// Write a monochrome page, choosing the TIFF sample format from the pixel type
+fn write_mono_page<T, W>(
+    encoder: &mut TiffEncoder<W>,
+    frame: &FrameData<T>,
+    description: Option<(&str, &str)>,
+) -> Result<(), Box<dyn Error>>
+where
+    T: MonoPixel,
+    W: Write + Seek,
+{
+    let (w, h) = (frame.width, frame.height);
+    let signed = T::min_value() < T::zero();
+    macro_rules! page {
+        ($ct:ty, $conv:ident) => {
+            write_page::<$ct, W>(
+                encoder,
+                w,
+                h,
+                &frame
+                    .data
+                    .iter()
+                    .map(|x| x.$conv().unwrap())
+                    .collect::<Vec<_>>(),
+                description,
+            )
+        };
+    }
+    match (std::mem::size_of::<T>(), signed) {
+        (1, false) => page!(colortype::Gray8, to_u8),
+        (1, true) => page!(colortype::GrayI8, to_i8),
+        (2, false) => page!(colortype::Gray16, to_u16),
+        (2, true) => page!(colortype::GrayI16, to_i16),
+        (4, false) => page!(colortype::Gray32, to_u32),
+        (4, true) => page!(colortype::GrayI32, to_i32),
+        (8, false) => page!(colortype::Gray64, to_u64),
+        (8, true) => page!(colortype::GrayI64, to_i64),
+        _ => Err("Pixel type not supported by TIFF".into()),
+    }
+}
+
+/// Convert decoded samples to pixel type `T`
+///
+/// Fails if any value does not fit in `T`
+fn convert_samples<T, S>(samples: Vec<S>) -> Result<Vec<T>, Box<dyn Error>>
+where
+    T: MonoPixel,
+    S: num_traits::ToPrimitive + Copy + std::fmt::Debug,
+{
+    samples
+        .into_iter()
+        .map(|s| {
+            T::from(s).ok_or(format!("TIFF pixel value {:?} does not fit in pixel type", s).into())
+        })
+        .collect()
+}
+
+/// Read the current page of a decoder as a monochrome frame
+fn read_mono_page<T, R>(decoder: &mut Decoder<R>) -> Result<FrameData<T>, Box<dyn Error>>
+where
+    T: MonoPixel,
+    R: Read + Seek,
+{
+    let (width, height) = decoder.dimensions()?;
+    match decoder.colortype()? {
+        ColorType::Gray(_) => {}
+        c => return Err(format!("Expected grayscale TIFF, found {:?}", c).into()),
+    }
+    let data = match decoder.read_image()? {
+        DecodingResult::U8(v) => convert_samples(v)?,
+        DecodingResult::U16(v) => convert_samples(v)?,
+        DecodingResult::U32(v) => convert_samples(v)?,
+        DecodingResult::U64(v) => convert_samples(v)?,
+        DecodingResult::I8(v) => convert_samples(v)?,
+        DecodingResult::I16(v) => convert_samples(v)?,
+        DecodingResult::I32(v) => convert_samples(v)?,
+        DecodingResult::I64(v) => convert_samples(v)?,
+        _ => return Err("Floating-point TIFF images are not supported".into()),
+    };
+    Ok(FrameData {
+        width,
+        height,
+        data,
+    })
+}
+
+/// Read the first page of an 8-bit color TIFF, checking the number of channels
+fn read_color<R: Read + Seek>(
+    reader: R,
+    expected: ColorType,
+) -> Result<(u32, u32, Vec<u8>), Box<dyn Error>> {
+    let mut decoder = Decoder::new(reader)?;
+    let (width, height) = decoder.dimensions()?;
+    let colortype = decoder.colortype()?;
+    if colortype != expected {
+        return Err(format!("Expected {:?} TIFF, found {:?}", expected, colortype).into());
+    }
+    match decoder.read_image()? {
+        DecodingResult::U8(v) => Ok((width, height, v)),
+        _ => Err("Unexpected TIFF sample format".into()),
+    }
+}
+
+impl<T> FrameData<T>
+where
+    T: MonoPixel,
+{
+    /// Save the FrameData to a grayscale TIFF file.
+    ///
+    /// # Arguments
+    /// `filename` - The name of the file to save the TIFF to.
+    ///
+    /// # Returns
+    /// An empty Result if the save was successful, or an error if the save failed.
+    ///
+    pub fn save_to_tiff(&self, filename: &str) -> Result<(), Box<dyn Error>> {
+        let mut writer = BufWriter::new(File::create(filename)?);
+        write_mono_page(&mut TiffEncoder::new(&mut writer)?, self, None)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Load FrameData from the first page of a grayscale TIFF file.
+    ///
+    /// # Arguments
+    /// `filename` - The name of the TIFF file.
+    ///
+    /// # Returns
+    /// The FrameData, or an error if the file could not be read, is not
+    /// grayscale, or has values that do not fit in the pixel type.
+    ///
+    pub fn load_tiff(filename: &str) -> Result<FrameData<T>, Box<dyn Error>> {
+        let mut decoder = Decoder::new(BufReader::new(File::open(filename)?))?;
+        read_mono_page(&mut decoder)
+    }
+}
+
+impl FrameData<RGBPixel> {
+    /// Save the FrameData to an 8-bit RGB TIFF file.
+    ///
+    /// # Arguments
+    /// `filename` - The name of the file to save the TIFF to.
+    ///
+    /// # Returns
+    /// An empty Result if the save was successful, or an error if the save failed.
+    ///
+    pub fn save_to_tiff(&self, filename: &str) -> Result<(), Box<dyn Error>> {
+        let mut writer = BufWriter::new(File::create(filename)?);
+        let data = self
+            .data
+            .iter()
+            .flat_map(|p| [p.r, p.g, p.b])
+            .collect::<Vec<u8>>();
+        write_page::<colortype::RGB8, _>(
+            &mut TiffEncoder::new(&mut writer)?,
+            self.width,
+            self.height,
+            &data,
+            None,
+        )?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Load FrameData from an 8-bit RGB TIFF file.
+    ///
+    /// # Arguments
+    /// `filename` - The name of the TIFF file.
+    ///
+    /// # Returns
+    /// The FrameData, or an error if the file could not be read or is not 8-bit RGB.
+    ///
+    pub fn load_tiff(filename: &str) -> Result<FrameData<RGBPixel>, Box<dyn Error>> {
+        let (width, height, data) =
+            read_color(BufReader::new(File::open(filename)?), ColorType::RGB(8))?;
+        Ok(FrameData {
+            width,
+            height,
+            data: data
+                .chunks_exact(3)
+                .map(|c| RGBPixel {
+                    r: c[0],
+                    g: c[1],
+                    b: c[2],
+                })
+                .collect(),
+        })
+    }
+}
+
+impl FrameData<RGBAPixel> {
+    /// Save the FrameData to an 8-bit RGBA TIFF file.
+    ///
+    /// # Arguments
+    /// `filename` - The name of the file to save the TIFF to.
+    ///
+    /// # Returns
+    /// An empty Result if the save was successful, or an error if the save failed.
+    ///
+    pub fn save_to_tiff(&self, filename: &str) -> Result<(), Box<dyn Error>> {
+        let mut writer = BufWriter::new(File::create(filename)?);
+        let data = self
+            .data
+            .iter()
+            .flat_map(|p| [p.r, p.g, p.b, p.a])
+            .collect::<Vec<u8>>();
+        write_page::<colortype::RGBA8, _>(
+            &mut TiffEncoder::new(&mut writer)?,
+            self.width,
+            self.height,
+            &data,
+            None,
+        )?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Load FrameData from an 8-bit RGBA TIFF file.
+    ///
+    /// # Arguments
+    /// `filename` - The name of the TIFF file.
+    ///
+    /// # Returns
+    /// The FrameData, or an error if the file could not be read or is not 8-bit RGBA.
+    ///
+    pub fn load_tiff(filename: &str) -> Result<FrameData<RGBAPixel>, Box<dyn Error>> {
+        let (width, height, data) =
+            read_color(BufReader::new(File::open(filename)?), ColorType::RGBA(8))?;
+        Ok(FrameData {
+            width,
+            height,
+            data: data
+                .chunks_exact(4)
+                .map(|c| RGBAPixel {
+                    r: c[0],
+                    g: c[1],
+                    b: c[2],
+                    a: c[3],
+                })
+                .collect(),
+        })
+    }
+}
+
+impl<T> CameraFrame<T>
+where
+    T: MonoPixel,
+{
+    /// Save a sequence of CameraFrames to a multi-page TIFF file, one frame per page.
+    ///
+    /// # Arguments
+    /// `frames` - The frames to save.
+    /// `filename` - The name of the file to save the TIFF to.
+    ///
+    /// # Returns
+    /// An empty Result if the save was successful, or an error if the save failed.
+    ///
+    pub fn save_sequence_to_tiff(
+        frames: &[CameraFrame<T>],
+        filename: &str,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut writer = BufWriter::new(File::create(filename)?);
+        Self::write_tiff_sequence(&mut TiffEncoder::new(&mut writer)?, frames)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Load a sequence of CameraFrames from a multi-page grayscale TIFF file.
+    ///
+    /// Exposure, center of integration and bit depth are read from each page's
+    /// ImageDescription when written by `save_sequence_to_tiff`; otherwise the
+    /// DateTime tag is used for the center of integration, and the exposure and
+    /// bit depth default to zero and the size of the pixel type.
+    ///
+    /// # Arguments
+    /// `filename` - The name of the TIFF file.
+    ///
+    /// # Returns
+    /// The frames, in page order, or an error if the file could not be read.
+    ///
+    pub fn load_sequence_from_tiff(filename: &str) -> Result<Vec<CameraFrame<T>>, Box<dyn Error>> {
+        Self::read_tiff_sequence(BufReader::new(File::open(filename)?))
+    }
+
+    fn write_tiff_sequence<W: Write + Seek>(
+        encoder: &mut TiffEncoder<W>,
+        frames: &[CameraFrame<T>],
+    ) -> Result<(), Box<dyn Error>> {
+        for frame in frames {
+            let description = format!(
+                "exposure={} center_of_integration={} bit_depth={}",
+                frame.exposure,
+                frame.center_of_integration.format(DATE_FORMAT),
+                frame.bit_depth
+            );
+            let datetime = frame
+                .center_of_integration
+                .format("%Y:%m:%d %H:%M:%S")
+                .to_string();
+            write_mono_page(
+                encoder,
+                &frame.data,
+                Some((description.as_str(), datetime.as_str())),
+            )?;
+        }
+        Ok(())
+    }
+
+    fn read_tiff_sequence<R: Read + Seek>(
+        reader: R,
+    ) -> Result<Vec<CameraFrame<T>>, Box<dyn Error>> {
+        let mut decoder = Decoder::new(reader)?;
+        let mut frames = Vec::new();
+        loop {
+            let mut exposure = 0.0;
+            let mut center_of_integration = chrono::DateTime::<chrono::Utc>::UNIX_EPOCH;
+            let mut bit_depth = (std::mem::size_of::<T>() * 8) as u8;
+
+            if let Some(datetime) = decoder.find_tag(Tag::DateTime)? {
+                if let Ok(t) = chrono::NaiveDateTime::parse_from_str(
+                    datetime.into_string()?.trim_end_matches('\0'),
+                    "%Y:%m:%d %H:%M:%S",
+                ) {
+                    center_of_integration = t.and_utc();
+                }
+            }
+            if let Some(description) = decoder.find_tag(Tag::ImageDescription)? {
+                for item in description.into_string()?.split_whitespace() {
+                    match item.split_once('=') {
+                        Some(("exposure", v)) => exposure = v.parse()?,
+                        Some(("center_of_integration", v)) => {
+                            center_of_integration =
+                                chrono::DateTime::parse_from_rfc3339(v)?.to_utc()
+                        }
+                        Some(("bit_depth", v)) => bit_depth = v.parse()?,
+                        _ => {}
+                    }
+                }
+            }
+
+            let data = read_mono_page(&mut decoder)?;
+            frames.push(CameraFrame::create(
+                exposure,
+                center_of_integration,
+                bit_depth,
+                data,
+            ));
+            if !decoder.more_images() {
+                break;
+            }
+            decoder.next_image()?;
+        }
+        Ok(frames)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn roundtrip<T: MonoPixel + PartialEq>(values: Vec<T>) {
+        let frame = FrameData::<T> {
+            width: values.len() as u32,
+            height: 1,
+            data: values,
+        };
+        let mut buf = Cursor::new(Vec::new());
+        write_mono_page(&mut TiffEncoder::new(&mut buf).unwrap(), &frame, None).unwrap();
+        buf.set_position(0);
+        let frame2 = read_mono_page::<T, _>(&mut Decoder::new(buf).unwrap()).unwrap();
+        assert_eq!(frame2.width, frame.width);
+        assert_eq!(frame2.height, frame.height);
+        assert_eq!(frame2.data, frame.data);
+    }
+
+    #[test]
+    fn test_roundtrip_types() {
+        roundtrip::<u8>(vec![0, 1, 128, 255]);
+        roundtrip::<u16>(vec![0, 1, 32768, 65535]);
+        roundtrip::<u32>(vec![0, 1, 0x80000000, u32::MAX]);
+        roundtrip::<i16>(vec![i16::MIN, -1, 0, i16::MAX]);
+    }
+
+    #[test]
+    fn test_rgba_tiff() {
+        let frame = FrameData::<RGBAPixel> {
+            width: 2,
+            height: 1,
+            data: vec![
+                RGBAPixel {
+                    r: 1,
+                    g: 2,
+                    b: 3,
+                    a: 4,
+                },
+                RGBAPixel {
+                    r: 5,
+                    g: 6,
+                    b: 7,
+                    a: 8,
+                },
+            ],
+        };
+        let filename = std::env::temp_dir().join("viewer_test_rgba.tiff");
+        let filename = filename.to_str().unwrap();
+        frame.save_to_tiff(filename).unwrap();
+        let frame2 = FrameData::<RGBAPixel>::load_tiff(filename).unwrap();
+        assert!(FrameData::<RGBPixel>::load_tiff(filename).is_err());
+        assert!(FrameData::<u8>::load_tiff(filename).is_err());
+        let _ = std::fs::remove_file(filename);
+        assert_eq!(frame2.width, 2);
+        assert_eq!(frame2.data[1].g, 6);
+        assert_eq!(frame2.data[1].a, 8);
+    }
+
+    #[test]
+    fn test_sequence() {
+        let t0 = chrono::Utc::now();
+        let frames = (0..3)
+            .map(|i| {
+                CameraFrame::<u16>::create(
+                    0.01 * (i + 1) as f64,
+                    t0 + chrono::Duration::milliseconds(33 * i),
+                    12,
+                    FrameData::<u16>::rand_norm(1000.0, 100.0, 16, 8),
+                )
+            })
+            .collect::<Vec<_>>();
+        let mut buf = Cursor::new(Vec::new());
+        CameraFrame::write_tiff_sequence(&mut TiffEncoder::new(&mut buf).unwrap(), &frames)
+            .unwrap();
+        buf.set_position(0);
+        let frames2 = CameraFrame::<u16>::read_tiff_sequence(buf).unwrap();
+
+        assert_eq!(frames2.len(), 3);
+        for (a, b) in frames.iter().zip(frames2.iter()) {
+            assert_eq!(a.exposure, b.exposure);
+            assert_eq!(a.bit_depth, b.bit_depth);
+            assert!(
+                (a.center_of_integration - b.center_of_integration)
+                    .num_microseconds()
+                    .unwrap()
+                    .abs()
+                    <= 1
+            );
+            assert_eq!(a.data.data, b.data.data);
+        }
+    }
+}
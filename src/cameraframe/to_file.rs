@@ -4,6 +4,45 @@ use super::RGBAPixel;
 use super::RGBPixel;
 
 use std::fs::File;
+use std::io::BufReader;
+
+/// Errors from loading a PNG file into a FrameData
+#[derive(Debug, thiserror::Error)]
+pub enum PngError {
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("PNG decoding error: {0}")]
+    Decoding(#[from] png::DecodingError),
+    #[error("PNG color type is {found:?}; expected {expected:?}")]
+    ColorType {
+        found: png::ColorType,
+        expected: png::ColorType,
+    },
+    #[error("PNG bit depth {found:?} does not fit in pixel type with maximum value {maxval}")]
+    BitDepth { found: png::BitDepth, maxval: u64 },
+}
+
+/// Decode a PNG file, checking its color type
+///
+/// # Returns
+/// The image info and raw (big-endian, for 16-bit) sample data
+fn read_png(
+    filename: &str,
+    colortype: png::ColorType,
+) -> Result<(png::OutputInfo, Vec<u8>), PngError> {
+    let decoder = png::Decoder::new(BufReader::new(File::open(filename)?));
+    let mut reader = decoder.read_info()?;
+    let mut buf = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buf)?;
+    if info.color_type != colortype {
+        return Err(PngError::ColorType {
+            found: info.color_type,
+            expected: colortype,
+        });
+    }
+    buf.truncate(info.buffer_size());
+    Ok((info, buf))
+}
 
 impl<T> FrameData<T>
 where
@@ -41,6 +80,35 @@ where
         })?;
         Ok(())
     }
+
+    /// Load FrameData from a grayscale PNG file.
+    ///
+    /// # Arguments
+    /// `filename` - The name of the PNG file.
+    ///
+    /// # Returns
+    /// The FrameData, or an error if the file could not be read, is not grayscale,
+    /// or has a bit depth (8 or 16) larger than the pixel type can hold.
+    ///
+    pub fn load_png(filename: &str) -> Result<FrameData<T>, PngError> {
+        let (info, buf) = read_png(filename, png::ColorType::Grayscale)?;
+        let maxval = T::max_value().to_u64().unwrap_or(u64::MAX);
+        let data = match info.bit_depth {
+            png::BitDepth::Eight if maxval >= 255 => {
+                buf.iter().map(|x| T::from(*x).unwrap()).collect()
+            }
+            png::BitDepth::Sixteen if maxval >= 65535 => buf
+                .chunks_exact(2)
+                .map(|x| T::from(u16::from_be_bytes([x[0], x[1]])).unwrap())
+                .collect(),
+            found => return Err(PngError::BitDepth { found, maxval }),
+        };
+        Ok(FrameData {
+            width: info.width,
+            height: info.height,
+            data,
+        })
+    }
 }
 
 impl FrameData<RGBAPixel> {
@@ -65,6 +133,37 @@ impl FrameData<RGBAPixel> {
         })?;
         Ok(())
     }
+
+    /// Load FrameData from an 8-bit RGBA PNG file.
+    ///
+    /// # Arguments
+    /// `filename` - The name of the PNG file.
+    ///
+    /// # Returns
+    /// The FrameData, or an error if the file could not be read or is not 8-bit RGBA.
+    ///
+    pub fn load_png(filename: &str) -> Result<FrameData<RGBAPixel>, PngError> {
+        let (info, buf) = read_png(filename, png::ColorType::Rgba)?;
+        if info.bit_depth != png::BitDepth::Eight {
+            return Err(PngError::BitDepth {
+                found: info.bit_depth,
+                maxval: 255,
+            });
+        }
+        Ok(FrameData {
+            width: info.width,
+            height: info.height,
+            data: buf
+                .chunks_exact(4)
+                .map(|c| RGBAPixel {
+                    r: c[0],
+                    g: c[1],
+                    b: c[2],
+                    a: c[3],
+                })
+                .collect(),
+        })
+    }
 }
 
 impl FrameData<RGBPixel> {
@@ -89,6 +188,36 @@ impl FrameData<RGBPixel> {
         })?;
         Ok(())
     }
+
+    /// Load FrameData from an 8-bit RGB PNG file.
+    ///
+    /// # Arguments
+    /// `filename` - The name of the PNG file.
+    ///
+    /// # Returns
+    /// The FrameData, or an error if the file could not be read or is not 8-bit RGB.
+    ///
+    pub fn load_png(filename: &str) -> Result<FrameData<RGBPixel>, PngError> {
+        let (info, buf) = read_png(filename, png::ColorType::Rgb)?;
+        if info.bit_depth != png::BitDepth::Eight {
+            return Err(PngError::BitDepth {
+                found: info.bit_depth,
+                maxval: 255,
+            });
+        }
+        Ok(FrameData {
+            width: info.width,
+            height: info.height,
+            data: buf
+                .chunks_exact(3)
+                .map(|c| RGBPixel {
+                    r: c[0],
+                    g: c[1],
+                    b: c[2],
+                })
+                .collect(),
+        })
+    }
 }
 
 #[cfg(test)]
@@ -116,13 +245,64 @@ mod tests {
     #[test]
     fn test_save_to_png() {
         let data = test_data();
-        let filename = "test.png";
+        let filename = std::env::temp_dir().join("viewer_test_save.png");
+        let filename = filename.to_str().unwrap();
         let _ = std::fs::remove_file(filename);
         let data2 = &data / 256;
         let data3: FrameData<u8> = (&data2).into();
         let data4 = data3.to_rgba(0, 255, 1.0, crate::colormap::parula());
         data4.save_to_png(filename).unwrap();
         assert!(std::fs::metadata(filename).is_ok());
-        //let _ = std::fs::remove_file(filename);
+        let _ = std::fs::remove_file(filename);
+    }
+
+    #[test]
+    fn test_load_png() {
+        let data = test_data();
+        let filename = std::env::temp_dir().join("viewer_test_load16.png");
+        let filename = filename.to_str().unwrap();
+        data.save_to_png(filename).unwrap();
+        let data2 = FrameData::<u16>::load_png(filename).unwrap();
+        assert_eq!(data2.width, 256);
+        assert_eq!(data2.height, 256);
+        assert_eq!(data2.data, data.data);
+
+        // 16-bit data can be loaded into a wider type, but not a narrower one
+        let data3 = FrameData::<u32>::load_png(filename).unwrap();
+        assert_eq!(data3.data[1000], data.data[1000] as u32);
+        assert!(matches!(
+            FrameData::<u8>::load_png(filename),
+            Err(PngError::BitDepth { .. })
+        ));
+        assert!(matches!(
+            FrameData::<RGBAPixel>::load_png(filename),
+            Err(PngError::ColorType { .. })
+        ));
+        let _ = std::fs::remove_file(filename);
+    }
+
+    #[test]
+    fn test_load_png_color() {
+        let data: FrameData<u8> = (&(&test_data() / 256)).into();
+        let rgba = data.to_rgba(0, 255, 1.0, crate::colormap::parula());
+        let filename = std::env::temp_dir().join("viewer_test_load_rgba.png");
+        let filename = filename.to_str().unwrap();
+        rgba.save_to_png(filename).unwrap();
+        let rgba2 = FrameData::<RGBAPixel>::load_png(filename).unwrap();
+        assert_eq!(rgba2.width, rgba.width);
+        assert!(rgba2
+            .data
+            .iter()
+            .zip(rgba.data.iter())
+            .all(|(a, b)| a.r == b.r && a.g == b.g && a.b == b.b && a.a == b.a));
+        assert!(matches!(
+            FrameData::<RGBPixel>::load_png(filename),
+            Err(PngError::ColorType { .. })
+        ));
+        assert!(matches!(
+            FrameData::<u16>::load_png(filename),
+            Err(PngError::ColorType { .. })
+        ));
+        let _ = std::fs::remove_file(filename);
     }
 }
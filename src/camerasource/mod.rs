@@ -0,0 +1,122 @@
+//!
+//! Camera sources
+//!
+//! A camera source is anything that produces `CameraFrame`s: a real camera,
+//! a simulator, or a recording being played back.  Sources are started with a
+//! callback that is invoked (typically from a source-owned thread) every time
+//! a frame is available.
+//!
+
+use crate::cameraframe::CameraFrame;
+use crate::cameraframe::MonoPixel;
+use crate::playbacksource::PlaybackControl;
+use crate::playbacksource::PlaybackSource;
+use crate::simsource::Psf;
+use crate::simsource::SimConfig;
+use crate::simsource::SimSource;
+
+use std::error::Error;
+use std::sync::Arc;
+
+/// Callback invoked by a camera source when a new frame is available
+pub type FrameCallback<T> = Box<dyn Fn(CameraFrame<T>) + Send + 'static>;
+
+/// A source of camera frames with pixel type `T`
+pub trait CameraSource<T>: Send
+where
+    T: MonoPixel,
+{
+    /// Start producing frames.
+    ///
+    /// # Arguments
+    /// * `onframe` - Function called with each new frame
+    ///
+    /// # Returns
+    /// An empty Result if the source was started, or an error if it could not be started
+    /// (e.g., it is already running, or the hardware is not available)
+    ///
+    fn start(&mut self, onframe: FrameCallback<T>) -> Result<(), Box<dyn Error>>;
+
+    /// Stop producing frames.
+    ///
+    /// Blocks until the source has stopped and no further frames will be delivered.
+    /// Stopping a source that is not running does nothing.
+    fn stop(&mut self);
+
+    /// Is the source currently producing frames?
+    fn is_running(&self) -> bool;
+
+    /// Human-readable description of the source
+    fn describe(&self) -> String;
+
+    /// Handle for pausing, stepping and seeking, for sources that play back stored frames
+    fn playback_control(&self) -> Option<Arc<PlaybackControl>> {
+        None
+    }
+}
+
+/// Names of the available camera sources, as accepted by `from_string`
+pub const SOURCE_NAMES: [&str; 2] = [
+    "sim[:gaussian|moffat|airy]",
+    "playback:<recording file or image directory>",
+];
+
+/// Create a camera source by name
+///
+/// # Arguments
+/// * `name` - Name of the source (case-insensitive); see `SOURCE_NAMES`.
+///   Sources that take an argument separate it from the name with a colon;
+///   for the simulator, it is the point spread function of the stars
+///
+/// # Returns
+/// The camera source, or None if the name is not recognized
+///
+pub fn from_string(name: &str) -> Option<Box<dyn CameraSource<u16>>> {
+    let (name, arg) = name.split_once(':').unwrap_or((name, ""));
+    match (name.to_lowercase().as_str(), arg) {
+        ("sim" | "simulated", "") => Some(Box::new(SimSource::new())),
+        ("sim" | "simulated", psf) => {
+            let mut config = SimConfig::default();
+            config.scene.psf = Psf::from_string(psf)?;
+            Some(Box::new(SimSource::with_config(config)))
+        }
+        ("playback" | "play", path) if !path.is_empty() => {
+            Some(Box::new(PlaybackSource::new(path)))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    #[test]
+    fn test_sim_start_stop() {
+        assert!(from_string("nonexistent").is_none());
+        assert!(from_string("sim:airy").is_some());
+        assert!(from_string("sim:square").is_none());
+        let mut source = from_string("sim").unwrap();
+        assert!(!source.is_running());
+
+        let (tx, rx) = mpsc::channel();
+        let tx = std::sync::Mutex::new(tx);
+        source
+            .start(Box::new(move |frame: CameraFrame<u16>| {
+                let _ = tx.lock().unwrap().send(frame.data.width);
+            }))
+            .unwrap();
+        assert!(source.is_running());
+        let width = rx.recv_timeout(std::time::Duration::from_secs(5)).unwrap();
+        assert_eq!(width, 1024);
+
+        source.stop();
+        assert!(!source.is_running());
+        // Drain anything sent before stopping; the channel must then be closed
+        while rx.try_recv().is_ok() {}
+        assert!(rx
+            .recv_timeout(std::time::Duration::from_millis(100))
+            .is_err());
+    }
+}
@@ -1,5 +1,32 @@
+use crate::cameraframe::saturating_cast;
+use crate::cameraframe::CombineMethod;
+use crate::cameraframe::FrameData;
+use crate::cameraframe::FrameStats;
 use crate::cameraframe::MonoPixel;
+use crate::cameraframe::RobustStats;
+use crate::cameraframe::Roi;
+use crate::imgproc::BackgroundConfig;
+use crate::imgproc::Calibration;
+use crate::imgproc::DetectionConfig;
+use crate::imgproc::HistogramBinning;
+use crate::imgproc::HistogramConfig;
+use crate::imgproc::ImageQueue;
+use crate::imgproc::MasterKind;
+use crate::imgproc::PipelineMetrics;
+use crate::imgproc::PipelineRates;
 use crate::imgproc::ProcResult;
+use crate::imgproc::Profile;
+use crate::imgproc::ProfileLine;
+use crate::imgproc::PsfConfig;
+use crate::imgproc::PsfFit;
+use crate::imgproc::PsfModel;
+use crate::imgproc::QueuePolicy;
+use crate::imgproc::ScaleMode;
+use crate::imgproc::Source;
+use crate::playbacksource::PlaybackControl;
+use crate::recording::Recorder;
+use crate::recording::RecordingLimits;
+use std::collections::VecDeque;
 use std::error::Error;
 
 use slint::Image;
@@ -9,23 +36,56 @@ use slint::SharedPixelBuffer;
 
 slint::include_modules!();
 
-use std::sync::{Arc, RwLock};
+use std::sync::{Arc, Mutex, RwLock};
+
+/// Line of a plot: (color, width, points)
+type PlotLine = (slint::Color, f32, slint::ModelRc<(f32, f32)>);
+
+/// Number of sources listed in the sources table
+const SOURCE_ROWS: usize = 10;
+/// Number of point spread function fits in the focus trend
+const FOCUS_TREND_POINTS: usize = 300;
+
+/// Focus trend: (frame time, FWHM along x, FWHM along y)
+type FocusTrend = VecDeque<(chrono::DateTime<chrono::Utc>, f64, f64)>;
 
 #[derive(Clone)]
 pub struct GuiParams {
     pub gamma: f64,
-    pub auto_scale: bool,
+    pub scale_mode: ScaleMode,
     pub scale_range: (i32, i32),
     pub colorscale: String,
+    pub roi: Option<Roi>,
+    pub calibrate: bool,
+    pub histogram: HistogramConfig,
+    pub profile: Option<ProfileLine>,
+    pub detection: Option<DetectionConfig>,
+    pub psf: Option<PsfConfig>,
+    /// Star selected for the point spread function fit, in frame coordinates
+    pub psf_star: Option<(f64, f64)>,
+    pub background: Option<BackgroundConfig>,
+    pub subtract_background: bool,
+    /// Median, MAD and sigma-clipped statistics, ignoring bad pixels
+    pub robust_stats: bool,
 }
 
 impl Default for GuiParams {
     fn default() -> Self {
         Self {
             gamma: 1.0,
-            auto_scale: false,
+            scale_mode: ScaleMode::Manual,
             scale_range: (0, 65535),
             colorscale: "parula".to_string(),
+            roi: None,
+            calibrate: false,
+            histogram: HistogramConfig::default(),
+            profile: None,
+            detection: None,
+            psf: None,
+            psf_star: None,
+            background: None,
+            subtract_background: false,
+            robust_stats: false,
         }
     }
 }
@@ -33,6 +93,11 @@ impl Default for GuiParams {
 pub struct Gui {
     pub ui: AppWindow,
     pub params: Arc<RwLock<GuiParams>>,
+    timers: Vec<slint::Timer>,
+    /// Last line profile displayed, for export
+    profile: Arc<Mutex<Option<Profile>>>,
+    /// Widths of the recent point spread function fits
+    focus_trend: Arc<Mutex<FocusTrend>>,
 }
 
 impl Gui {
@@ -40,14 +105,207 @@ impl Gui {
         self.params.clone()
     }
 
-    pub fn processed_callback<T>(&self) -> Box<dyn Fn(ProcResult<T>) + Send + 'static>
+    /// Rows of the statistics table: (name, full-frame value, ROI value),
+    /// starting with where the regions are, and followed by the robust
+    /// statistics if they were computed
+    fn stats_rows<T>(
+        data: &FrameData<T>,
+        roi: &Option<Roi>,
+        stats: &FrameStats<T>,
+        roistats: &Option<FrameStats<T>>,
+        robust: &Option<RobustStats>,
+        roirobust: &Option<RobustStats>,
+    ) -> Vec<StatsRow>
+    where
+        T: MonoPixel,
+    {
+        let text = |s: &FrameStats<T>| {
+            vec![
+                format!("{:.2}", s.mean),
+                format!("{:.2}", s.sigma),
+                format!("{}", saturating_cast::<T, i64>(s.min)),
+                format!("{}", saturating_cast::<T, i64>(s.max)),
+                format!("{}", s.sum),
+                format!("{}", s.npixels),
+            ]
+        };
+        let robust_text = |s: &RobustStats| {
+            vec![
+                format!("{:.2}", s.median),
+                format!("{:.2}", s.sigma_mad()),
+                format!("{:.2}", s.clipped.mean),
+                format!("{:.2}", s.clipped.sigma),
+            ]
+        };
+        // The region of interest, as clipped to the frame
+        let region = |x: u32, y: u32, width: u32, height: u32| {
+            vec![format!("{}, {}", x, y), format!("{} x {}", width, height)]
+        };
+        let mut names = vec!["Origin", "Size"];
+        let mut frame = region(0, 0, data.width, data.height);
+        let mut roi = roi.map(|r| region(r.x, r.y, r.width, r.height));
+        names.extend(["Mean", "1-Sigma", "Min", "Max", "Sum", "Pixels"]);
+        frame.extend(text(stats));
+        roi = roi.zip(roistats.as_ref()).map(|(mut t, s)| {
+            t.extend(text(s));
+            t
+        });
+        if let Some(r) = robust {
+            names.extend(["Median", "MAD Sigma", "Clipped Mean", "Clipped Sigma"]);
+            frame.extend(robust_text(r));
+            roi = roi.zip(roirobust.as_ref()).map(|(mut t, r)| {
+                t.extend(robust_text(r));
+                t
+            });
+        }
+        names
+            .iter()
+            .enumerate()
+            .map(|(i, name)| StatsRow {
+                name: (*name).into(),
+                frame: frame[i].as_str().into(),
+                roi: roi.as_ref().map_or("--", |r| r[i].as_str()).into(),
+            })
+            .collect()
+    }
+
+    /// Rows of the sources table, for the brightest sources
+    fn source_rows(sources: &[Source]) -> Vec<SourceRow> {
+        sources
+            .iter()
+            .take(SOURCE_ROWS)
+            .enumerate()
+            .map(|(i, s)| SourceRow {
+                id: format!("{}", i + 1).into(),
+                x: format!("{:.2}", s.gauss_x).into(),
+                y: format!("{:.2}", s.gauss_y).into(),
+                flux: format!("{:.0}", s.flux).into(),
+                peak: format!("{:.0}", s.peak).into(),
+                fwhm: format!("{:.2}", s.fwhm).into(),
+            })
+            .collect()
+    }
+
+    /// Rows of the point spread function table: (name, value)
+    fn psf_rows(fit: &Option<PsfFit>) -> Vec<PsfRow> {
+        let text = |f: &PsfFit| {
+            [
+                f.model.name().to_string(),
+                format!("({:.2}, {:.2})", f.x, f.y),
+                format!("{:.2}", f.fwhm()),
+                format!("{:.2} / {:.2}", f.fwhm_x, f.fwhm_y),
+                format!("{:.2} / {:.2}", f.fwhm_major, f.fwhm_minor),
+                format!("{:.3}", f.ellipticity),
+                format!("{:.1}°", f.angle),
+                format!("{:.0}", f.peak),
+                format!("{:.1}", f.background),
+                f.beta.map_or("--".to_string(), |b| format!("{:.2}", b)),
+                format!("{:.2}", f.residual_rms),
+                f.strehl.map_or("--".to_string(), |s| format!("{:.3}", s)),
+            ]
+        };
+        let values = fit.as_ref().map(text);
+        [
+            "Model",
+            "Center",
+            "FWHM",
+            "FWHM X / Y",
+            "FWHM Major / Minor",
+            "Ellipticity",
+            "Angle",
+            "Peak",
+            "Background",
+            "Beta",
+            "Residual RMS",
+            "Strehl",
+        ]
+        .iter()
+        .enumerate()
+        .map(|(i, name)| PsfRow {
+            name: (*name).into(),
+            value: values.as_ref().map_or("--", |v| v[i].as_str()).into(),
+        })
+        .collect()
+    }
+
+    /// Plot of the focus trend: FWHM along x (red) and y (blue) against seconds
+    /// before the latest fit, and the x and y ranges as (max, min)
+    fn focus_plot(trend: &FocusTrend) -> (Vec<PlotLine>, (f32, f32), (f32, f32)) {
+        let Some((latest, _, _)) = trend.back() else {
+            return (vec![], (0.0, -1.0), (1.0, 0.0));
+        };
+        let seconds =
+            |t: &chrono::DateTime<chrono::Utc>| (*t - *latest).num_milliseconds() as f32 / 1000.0;
+        let xmin = seconds(&trend[0].0).min(-1.0);
+        let ymax = trend
+            .iter()
+            .fold(0.0_f64, |m, (_, fx, fy)| m.max(*fx).max(*fy))
+            .max(1.0)
+            .ceil() as f32;
+        let line = |color: slint::Color, points: Vec<(f32, f32)>| -> PlotLine {
+            (
+                color,
+                1.5_f32,
+                slint::ModelRc::new(slint::VecModel::from(points)),
+            )
+        };
+        let lines = vec![
+            line(
+                slint::Color::from_argb_u8(255, 255, 0, 0),
+                trend
+                    .iter()
+                    .map(|(t, fx, _)| (seconds(t), *fx as f32))
+                    .collect(),
+            ),
+            line(
+                slint::Color::from_argb_u8(255, 0, 96, 192),
+                trend
+                    .iter()
+                    .map(|(t, _, fy)| (seconds(t), *fy as f32))
+                    .collect(),
+            ),
+        ];
+        (lines, (0.0, xmin), (ymax, 0.0))
+    }
+
+    /// Format the pipeline rates for display
+    fn rates_text(rates: &PipelineRates) -> (String, String, String) {
+        let hz = |r: Option<f64>| match r {
+            Some(r) => format!("{:.1}", r),
+            None => "--".to_string(),
+        };
+        (
+            format!("{} fps", hz(rates.acquisition)),
+            format!("{} / {} fps", hz(rates.processing), hz(rates.display)),
+            match rates.latency {
+                Some(l) => format!("{:.1} ms", l * 1.0e3),
+                None => "-- ms".to_string(),
+            },
+        )
+    }
+
+    /// Callback to display the output of the image processor
+    ///
+    /// # Arguments
+    /// * `metrics` - Pipeline measurements; frames are recorded as displayed when
+    ///   they are drawn, and the current rates are shown
+    ///
+    pub fn processed_callback<T>(
+        &self,
+        metrics: Arc<PipelineMetrics>,
+    ) -> Box<dyn Fn(ProcResult<T>) + Send + 'static>
     where
         T: MonoPixel + 'static,
     {
         let ui_handle: slint::Weak<AppWindow> = self.ui.as_weak().clone();
+        let profile = self.profile.clone();
+        let focus_trend = self.focus_trend.clone();
 
         Box::new(move |result: ProcResult<T>| {
             let ui_handle = ui_handle.clone();
+            let metrics = metrics.clone();
+            let profile = profile.clone();
+            let focus_trend = focus_trend.clone();
 
             // GUI is single threaded, so we must populate the image in the GUI thread
             let _ = slint::invoke_from_event_loop(move || {
@@ -55,18 +313,27 @@ impl Gui {
 
                 let global = ui.global::<Shared>();
 
-                // Create the histogram points
-                let histxrange = (65536.0, 0.0);
-                let maxhist = *result.histogram.1.iter().max().unwrap() as f64;
-                let maxhist = f64::powf(2.0, f64::log2(maxhist).ceil());
+                // Create the histogram points, with counts on a log scale if requested
+                let hist = &result.histogram;
+                let (histmin, histmax) = hist.range();
+                let histxrange = (histmax as f32, histmin as f32);
+                let logy = global.get_histlogy();
+                let yvalue = |count: u64| match logy {
+                    true => (count as f64 + 1.0).log10(),
+                    false => count as f64,
+                };
+                let maxhist = yvalue(*hist.counts.iter().max().unwrap()).max(1.0);
+                let maxhist = match logy {
+                    true => maxhist.ceil(),
+                    false => f64::powf(2.0, f64::log2(maxhist).ceil()),
+                };
                 let histyrange = (maxhist as f32, 0.0_f32);
                 let histpoints = slint::VecModel::from_slice(
-                    &result
-                        .histogram
-                        .0
+                    &hist
+                        .centers()
                         .iter()
-                        .zip(result.histogram.1.iter())
-                        .map(|(x, y)| (*x as f32, *y as f32))
+                        .zip(hist.counts.iter())
+                        .map(|(x, y)| (*x as f32, yvalue(*y) as f32))
                         .collect::<Vec<(f32, f32)>>(),
                 );
                 let histline = slint::VecModel::from_slice(&[(
@@ -79,6 +346,15 @@ impl Gui {
                 global.set_histyrange(histyrange);
                 global.set_histdata(histline);
 
+                // Plot the line profile
+                if let Some(p) = result.profile.as_ref() {
+                    let (lines, xrange, yrange) = Self::profile_plot(p);
+                    global.set_profilexrange(xrange);
+                    global.set_profileyrange(yrange);
+                    global.set_profiledata(slint::ModelRc::new(slint::VecModel::from(lines)));
+                }
+                *profile.lock().unwrap() = result.profile.clone();
+
                 // Create the image
                 ui.set_camframe_height(result.displayimage.height as i32);
                 ui.set_camframe_width(result.displayimage.width as i32);
@@ -96,18 +372,420 @@ impl Gui {
                 )));
 
                 global.set_fcrange((result.fcrange.1, result.fcrange.0));
+                // Show the limits chosen automatically, ready to be edited
+                if global.get_scalemode() != "Manual" {
+                    global.set_scalemin(result.fcrange.0);
+                    global.set_scalemax(result.fcrange.1);
+                }
+
+                metrics.record_displayed(result.rawframe.center_of_integration);
+                let (fpstext, ratetext, latencytext) = Self::rates_text(&metrics.rates());
+                ui.set_fpstext(slint::SharedString::from(fpstext));
+                ui.set_ratetext(slint::SharedString::from(ratetext));
+                ui.set_latencytext(slint::SharedString::from(latencytext));
 
-                let xpix = ui.get_xpix() as u32;
-                let ypix = ui.get_ypix() as u32;
-                ui.set_valatpix(result.rawframe.data.at(xpix, ypix).to_i32().unwrap());
+                // The mouseover pixel may be outside a frame whose size just changed
+                let data = &result.frame().data;
+                let xpix = (ui.get_xpix().max(0) as u32).min(data.width.saturating_sub(1));
+                let ypix = (ui.get_ypix().max(0) as u32).min(data.height.saturating_sub(1));
+                ui.set_valatpix(saturating_cast(data.at(xpix, ypix)));
 
-                let (mean, var) = result.rawframe.data.mean_and_var();
-                ui.set_meantext(slint::SharedString::from(format!("{:.2}", mean)));
-                ui.set_vartext(slint::SharedString::from(format!("{:.2}", var.sqrt())));
+                let rows = Self::stats_rows(
+                    data,
+                    &result.roi,
+                    &result.stats,
+                    &result.roistats,
+                    &result.robust,
+                    &result.roirobust,
+                );
+                ui.set_stats_rows(slint::ModelRc::new(slint::VecModel::from(rows)));
+
+                // Mark the detected sources, and list the brightest
+                let markers = result
+                    .sources
+                    .iter()
+                    .map(|s| SourceMarker {
+                        x: s.gauss_x as f32,
+                        y: s.gauss_y as f32,
+                        radius: s.fwhm as f32,
+                    })
+                    .collect::<Vec<_>>();
+                ui.set_source_markers(slint::ModelRc::new(slint::VecModel::from(markers)));
+                let rows = Self::source_rows(&result.sources);
+                ui.set_source_rows(slint::ModelRc::new(slint::VecModel::from(rows)));
+                ui.set_sourcestext(slint::SharedString::from(format!(
+                    "{} sources",
+                    result.sources.len()
+                )));
+
+                // Background level and noise: (level, noise, range of levels)
+                let status = match result.background {
+                    Some(bg) => [
+                        format!("{:.1}", bg.level),
+                        format!("{:.2}", bg.noise),
+                        format!("{:.1} to {:.1}", bg.min_level, bg.max_level),
+                    ],
+                    None => ["--".to_string(), "--".to_string(), "--".to_string()],
+                }
+                .map(slint::SharedString::from)
+                .to_vec();
+                ui.set_background_status(slint::ModelRc::new(slint::VecModel::from(status)));
+
+                // Show the point spread function fit, and add it to the focus trend
+                let rows = Self::psf_rows(&result.psf);
+                ui.set_psf_rows(slint::ModelRc::new(slint::VecModel::from(rows)));
+                // Slint takes the anonymous region struct with fields in alphabetical order
+                ui.set_psf_region(result.psf.map_or((0, 0, 0, 0), |f| {
+                    let r = f.region;
+                    (r.height as i32, r.width as i32, r.x as i32, r.y as i32)
+                }));
+                let mut trend = focus_trend.lock().unwrap();
+                if let Some(fit) = result.psf.as_ref() {
+                    if trend.len() == FOCUS_TREND_POINTS {
+                        trend.pop_front();
+                    }
+                    trend.push_back((
+                        result.rawframe.center_of_integration,
+                        fit.fwhm_x,
+                        fit.fwhm_y,
+                    ));
+                }
+                let (lines, xrange, yrange) = Self::focus_plot(&trend);
+                global.set_focusxrange(xrange);
+                global.set_focusyrange(yrange);
+                global.set_focusdata(slint::ModelRc::new(slint::VecModel::from(lines)));
             });
         })
     }
 
+    /// Plot of a line profile: the line (if any samples are in the frame) and the x and y ranges
+    ///
+    /// Ranges are (max, min), the order in which Slint passes the anonymous range struct
+    fn profile_plot(profile: &Profile) -> (Vec<PlotLine>, (f32, f32), (f32, f32)) {
+        let points = profile
+            .samples
+            .iter()
+            .map(|s| (s.distance as f32, s.value as f32))
+            .collect::<Vec<(f32, f32)>>();
+        let xmax = points.last().map_or(1.0, |p| p.0.max(1.0));
+        let (ymin, ymax) = points.iter().fold((f32::MAX, f32::MIN), |(lo, hi), p| {
+            (lo.min(p.1), hi.max(p.1))
+        });
+        let (ymin, ymax) = match ymax > ymin {
+            true => (ymin, ymax),
+            false if points.is_empty() => (0.0, 1.0),
+            false => (ymin - 1.0, ymax + 1.0),
+        };
+        let lines = match points.is_empty() {
+            true => vec![],
+            false => vec![(
+                slint::Color::from_argb_u8(255, 0, 96, 192),
+                1.5_f32,
+                slint::ModelRc::new(slint::VecModel::from(points)),
+            )],
+        };
+        (lines, (xmax, 0.0), (ymax, ymin))
+    }
+
+    /// Show which camera source the frames come from in the window title
+    ///
+    /// # Arguments
+    /// * `description` - Description of the camera source
+    ///
+    pub fn show_source(&self, description: &str) {
+        self.ui
+            .set_source_description(slint::SharedString::from(description));
+    }
+
+    /// Display the counters of an image queue, and let the user change its policy
+    ///
+    /// # Arguments
+    /// * `queue` - The image queue feeding the processing chain
+    ///
+    pub fn watch_queue<T>(&mut self, queue: Arc<ImageQueue<T>>)
+    where
+        T: MonoPixel + 'static,
+    {
+        let (policy, _) = queue.policy();
+        self.ui
+            .set_queue_policy(slint::SharedString::from(policy.name()));
+
+        self.ui.on_queue_policy_changed({
+            let queue = queue.clone();
+            move |name: slint::SharedString| {
+                if let Some(policy) = QueuePolicy::from_string(name.as_str()) {
+                    let (_, capacity) = queue.policy();
+                    queue.set_policy(policy, capacity);
+                }
+            }
+        });
+
+        // Counters are polled rather than pushed with each frame, so that
+        // drops are visible even when nothing is getting through
+        let timer = slint::Timer::default();
+        let ui_handle = self.ui.as_weak();
+        timer.start(
+            slint::TimerMode::Repeated,
+            std::time::Duration::from_millis(500),
+            move || {
+                if let Some(ui) = ui_handle.upgrade() {
+                    let stats = queue.stats();
+                    ui.set_droppedtext(slint::SharedString::from(format!(
+                        "{} of {}",
+                        stats.dropped, stats.received
+                    )));
+                }
+            },
+        );
+        self.timers.push(timer);
+    }
+
+    /// Let the user start and stop a recording, and display its progress
+    ///
+    /// # Arguments
+    /// * `recorder` - The recorder fed with the frames from the camera source
+    ///
+    pub fn watch_recorder(&mut self, recorder: Arc<Recorder>) {
+        self.ui.on_start_recording({
+            let recorder = recorder.clone();
+            let ui_handle = self.ui.as_weak();
+            move |filename: slint::SharedString, max_mb: i32, max_seconds: i32| {
+                let limits = RecordingLimits {
+                    max_bytes: (max_mb > 0).then_some(max_mb as u64 * 1024 * 1024),
+                    max_duration: (max_seconds > 0).then_some(max_seconds as f64),
+                };
+                if let Err(e) = recorder.start(filename.as_str(), limits) {
+                    if let Some(ui) = ui_handle.upgrade() {
+                        ui.set_recordingtext(slint::SharedString::from(format!("Error: {}", e)));
+                    }
+                }
+            }
+        });
+        self.ui.on_stop_recording({
+            let recorder = recorder.clone();
+            move || {
+                recorder.stop();
+            }
+        });
+
+        let timer = slint::Timer::default();
+        let ui_handle = self.ui.as_weak();
+        timer.start(
+            slint::TimerMode::Repeated,
+            std::time::Duration::from_millis(500),
+            move || {
+                if let Some(ui) = ui_handle.upgrade() {
+                    let status = recorder.status();
+                    ui.set_recording(status.recording);
+                    if status.filename.is_empty() {
+                        return;
+                    }
+                    let text = match status.error {
+                        Some(e) => format!("Error: {}", e),
+                        None => format!(
+                            "{} frames ({} dropped), {:.1} MB, {:.1} s",
+                            status.frames,
+                            status.dropped,
+                            status.bytes as f64 / (1024.0 * 1024.0),
+                            status.duration
+                        ),
+                    };
+                    ui.set_recordingtext(slint::SharedString::from(text));
+                }
+            },
+        );
+        self.timers.push(timer);
+    }
+
+    /// Let the user capture, load, save and clear master calibration frames
+    /// and the bad pixel map, and display which are in use
+    ///
+    /// Masters are stored as "bias.fits", "dark.fits" and "flat.fits", and the
+    /// bad pixel map as "badpixels.fits", in the directory entered in the GUI
+    ///
+    /// # Arguments
+    /// * `calibration` - Calibration of the processing chain
+    ///
+    pub fn watch_calibration(&mut self, calibration: Arc<RwLock<Calibration>>) {
+        let filename = |dir: &str, name: &str| {
+            std::path::Path::new(dir)
+                .join(format!("{}.fits", name))
+                .to_string_lossy()
+                .to_string()
+        };
+        // Show the outcome of a load or save
+        let report = {
+            let ui_handle = self.ui.as_weak();
+            move |result: Result<String, Box<dyn Error>>| {
+                if let Some(ui) = ui_handle.upgrade() {
+                    let text = match result {
+                        Ok(text) => text,
+                        Err(e) => format!("Error: {}", e),
+                    };
+                    ui.set_calibrationtext(slint::SharedString::from(text));
+                }
+            }
+        };
+
+        self.ui.on_calibration_capture({
+            let calibration = calibration.clone();
+            move |kind: slint::SharedString, count: i32, method: slint::SharedString| {
+                if let (Some(kind), Some(method)) = (
+                    MasterKind::from_string(kind.as_str()),
+                    CombineMethod::from_string(method.as_str()),
+                ) {
+                    calibration
+                        .write()
+                        .unwrap()
+                        .start_capture(kind, count.max(1) as usize, method);
+                }
+            }
+        });
+        self.ui.on_calibration_load({
+            let calibration = calibration.clone();
+            let report = report.clone();
+            move |kind: slint::SharedString, dir: slint::SharedString| {
+                if let Some(kind) = MasterKind::from_string(kind.as_str()) {
+                    let filename = filename(dir.as_str(), &kind.name().to_lowercase());
+                    let result = calibration.write().unwrap().load(kind, &filename);
+                    report(result.map(|_| format!("Loaded {}", filename)));
+                }
+            }
+        });
+        self.ui.on_calibration_save({
+            let calibration = calibration.clone();
+            let report = report.clone();
+            move |kind: slint::SharedString, dir: slint::SharedString| {
+                if let Some(kind) = MasterKind::from_string(kind.as_str()) {
+                    let filename = filename(dir.as_str(), &kind.name().to_lowercase());
+                    let result = std::fs::create_dir_all(dir.as_str())
+                        .map_err(|e| e.into())
+                        .and_then(|_| calibration.read().unwrap().save(kind, &filename));
+                    report(result.map(|_| format!("Saved {}", filename)));
+                }
+            }
+        });
+        self.ui.on_calibration_clear({
+            let calibration = calibration.clone();
+            move |kind: slint::SharedString| {
+                if let Some(kind) = MasterKind::from_string(kind.as_str()) {
+                    calibration.write().unwrap().clear(kind);
+                }
+            }
+        });
+        self.ui.on_badpixels_load({
+            let calibration = calibration.clone();
+            let report = report.clone();
+            move |dir: slint::SharedString| {
+                let filename = filename(dir.as_str(), "badpixels");
+                let result = calibration.write().unwrap().load_badpixels(&filename);
+                report(result.map(|_| format!("Loaded {}", filename)));
+            }
+        });
+        self.ui.on_badpixels_save({
+            let calibration = calibration.clone();
+            move |dir: slint::SharedString| {
+                let filename = filename(dir.as_str(), "badpixels");
+                let result = std::fs::create_dir_all(dir.as_str())
+                    .map_err(|e| e.into())
+                    .and_then(|_| calibration.read().unwrap().save_badpixels(&filename));
+                report(result.map(|_| format!("Saved {}", filename)));
+            }
+        });
+        self.ui.on_badpixels_clear({
+            let calibration = calibration.clone();
+            move || calibration.write().unwrap().clear_badpixels()
+        });
+
+        let timer = slint::Timer::default();
+        let ui_handle = self.ui.as_weak();
+        timer.start(
+            slint::TimerMode::Repeated,
+            std::time::Duration::from_millis(500),
+            move || {
+                if let Some(ui) = ui_handle.upgrade() {
+                    let cal = calibration.read().unwrap();
+                    let capture = cal.capture_status();
+                    let status = MasterKind::ALL
+                        .iter()
+                        .map(|kind| match (capture, cal.master(*kind)) {
+                            (Some(c), _) if c.kind == *kind => {
+                                format!("Capturing {} / {}", c.captured, c.count)
+                            }
+                            (_, Some(m)) => format!("{} x {}", m.width, m.height),
+                            (_, None) => "None".to_string(),
+                        })
+                        .map(slint::SharedString::from)
+                        .collect::<Vec<_>>();
+                    ui.set_calibration_status(slint::ModelRc::new(slint::VecModel::from(status)));
+                    let badpixels = match cal.badpixels() {
+                        Some(map) => format!("{} pixels", map.count()),
+                        None => "None".to_string(),
+                    };
+                    ui.set_badpixels_status(slint::SharedString::from(badpixels));
+                    if let Some(e) = cal.capture_error() {
+                        ui.set_calibrationtext(slint::SharedString::from(format!("Error: {}", e)));
+                    }
+                }
+            },
+        );
+        self.timers.push(timer);
+    }
+
+    /// Show the playback controls and connect them to a playback source
+    ///
+    /// # Arguments
+    /// * `control` - Control handle of the playback source
+    ///
+    pub fn watch_playback(&mut self, control: Arc<PlaybackControl>) {
+        self.ui.set_playback_enabled(true);
+        self.ui.on_playback_pause({
+            let control = control.clone();
+            move |paused: bool| control.set_paused(paused)
+        });
+        self.ui.on_playback_step({
+            let control = control.clone();
+            move || control.step()
+        });
+        self.ui.on_playback_seek({
+            let control = control.clone();
+            move |position: i32| control.seek(position.max(0) as usize)
+        });
+        self.ui.on_playback_loop({
+            let control = control.clone();
+            move |looping: bool| control.set_looping(looping)
+        });
+        self.ui.on_playback_speed_changed({
+            let control = control.clone();
+            move |speed: slint::SharedString| {
+                if let Ok(speed) = speed.trim_end_matches('x').parse::<f64>() {
+                    control.set_speed(speed);
+                }
+            }
+        });
+
+        let timer = slint::Timer::default();
+        let ui_handle = self.ui.as_weak();
+        timer.start(
+            slint::TimerMode::Repeated,
+            std::time::Duration::from_millis(200),
+            move || {
+                if let Some(ui) = ui_handle.upgrade() {
+                    let status = control.status();
+                    ui.set_playback_paused(status.paused);
+                    ui.set_playback_nframes(status.nframes as i32);
+                    ui.set_playback_position(status.position.saturating_sub(1) as f32);
+                    let text = match status.error {
+                        Some(e) => format!("Error: {}", e),
+                        None => format!("Frame {} of {}", status.position, status.nframes),
+                    };
+                    ui.set_playbacktext(slint::SharedString::from(text));
+                }
+            },
+        );
+        self.timers.push(timer);
+    }
+
     fn update_colorbar(ui: &AppWindow) {
         let cmap = crate::colormap::from_string(ui.global::<Shared>().get_colormap().as_str())
             .unwrap_or(crate::colormap::grayscale());
@@ -157,6 +835,18 @@ impl Gui {
             },
         );
 
+        // Slint takes the anonymous line struct with fields in alphabetical order
+        ui.global::<Shared>()
+            .on_row_line(|y: i32, frame_width: i32| -> (f32, f32, f32, f32) {
+                let l = ProfileLine::row(y.max(0) as u32, frame_width.max(0) as u32, 1);
+                (l.x0 as f32, l.x1 as f32, l.y0 as f32, l.y1 as f32)
+            });
+        ui.global::<Shared>()
+            .on_column_line(|x: i32, frame_height: i32| -> (f32, f32, f32, f32) {
+                let l = ProfileLine::column(x.max(0) as u32, frame_height.max(0) as u32, 1);
+                (l.x0 as f32, l.x1 as f32, l.y0 as f32, l.y1 as f32)
+            });
+
         // take a list of points and x,y ranges and return an SVG path as a string
         // This is very inelegant, but it works
         ui.global::<Shared>().on_linetosvg(
@@ -201,11 +891,99 @@ impl Gui {
                 let mut p = params.write().unwrap();
                 p.colorscale = String::from(globals.get_colormap().as_str());
                 p.gamma = globals.get_gamma() as f64;
-                p.auto_scale = globals.get_autoscale();
+                p.scale_mode = ScaleMode::from_string(globals.get_scalemode().as_str())
+                    .unwrap_or(ScaleMode::Manual);
+                p.scale_range = (globals.get_scalemin(), globals.get_scalemax());
+                p.calibrate = globals.get_calibrate();
+                p.histogram = HistogramConfig {
+                    nbins: globals.get_histbins().parse().unwrap_or(256),
+                    binning: HistogramBinning::from_string(globals.get_histbinning().as_str())
+                        .unwrap_or(HistogramBinning::Linear),
+                };
+                // Slint passes the anonymous ROI struct with fields in alphabetical order
+                let (height, width, x, y) = globals.get_roi();
+                p.roi = match width > 0 && height > 0 {
+                    true => Some(Roi {
+                        x: x.max(0) as u32,
+                        y: y.max(0) as u32,
+                        width: width as u32,
+                        height: height as u32,
+                    }),
+                    false => None,
+                };
+                // Slint passes the anonymous line struct with fields in alphabetical order
+                let (x0, x1, y0, y1) = globals.get_profileline();
+                p.profile = match x0 != x1 || y0 != y1 {
+                    true => Some(ProfileLine {
+                        x0: x0 as f64,
+                        y0: y0 as f64,
+                        x1: x1 as f64,
+                        y1: y1 as f64,
+                        width: globals.get_profilewidth().max(1) as u32,
+                    }),
+                    false => None,
+                };
+                p.robust_stats = globals.get_robuststats();
+                // Subtracting the background requires estimating it
+                p.subtract_background = globals.get_bgsubtract();
+                p.background =
+                    (globals.get_bgestimate() || p.subtract_background).then(|| BackgroundConfig {
+                        box_size: globals.get_bgbox().parse().unwrap_or(64),
+                        ..Default::default()
+                    });
+                // A star selected with the mouse, if any, is fit instead of the region of interest
+                p.psf = match globals.get_psf() {
+                    true => Some(PsfConfig {
+                        model: PsfModel::from_string(globals.get_psfmodel().as_str())
+                            .unwrap_or(PsfModel::Gaussian),
+                        lambda_over_d: globals.get_psflambdad().trim().parse().ok(),
+                    }),
+                    false => None,
+                };
+                let (x, y) = globals.get_psfstar();
+                p.psf_star = (x >= 0.0 && y >= 0.0).then_some((x as f64, y as f64));
+                p.detection = match globals.get_detect() {
+                    true => Some(DetectionConfig {
+                        nsigma: globals.get_detectsigma() as f64,
+                        ..Default::default()
+                    }),
+                    false => None,
+                };
             }
         });
 
-        let gui = Self { ui, params };
+        let profile = Arc::new(Mutex::new(None::<Profile>));
+        ui.on_export_profile({
+            let ui_handle = ui.as_weak();
+            let profile = profile.clone();
+            move |filename: slint::SharedString| {
+                let result = match profile.lock().unwrap().as_ref() {
+                    Some(p) => p.save_csv(filename.as_str()),
+                    None => Err("No line profile to export".into()),
+                };
+                let text = match result {
+                    Ok(_) => format!("Saved {}", filename),
+                    Err(e) => format!("Error: {}", e),
+                };
+                ui_handle
+                    .unwrap()
+                    .set_profiletext(slint::SharedString::from(text));
+            }
+        });
+
+        let focus_trend = Arc::new(Mutex::new(FocusTrend::new()));
+        ui.on_clear_focus_trend({
+            let focus_trend = focus_trend.clone();
+            move || focus_trend.lock().unwrap().clear()
+        });
+
+        let gui = Self {
+            ui,
+            params,
+            timers: Vec::new(),
+            profile,
+            focus_trend,
+        };
         Ok(gui)
     }
 
@@ -0,0 +1,294 @@
+//!
+//! Estimation of a smoothly varying background, and of its noise
+//!
+//! The frame is divided into a mesh of boxes.  In each box the background is the
+//! median of the pixels left after iteratively rejecting outliers (such as stars)
+//! around the median, and the noise is found in the same way from the differences
+//! of neighboring pixels, so that it excludes any gradient across the box.  The mesh is
+//! median filtered, to reject boxes dominated by bright objects, and interpolated
+//! bilinearly between box centers to give background and noise maps of the frame.
+//!
+
+use crate::cameraframe::median;
+use crate::cameraframe::sigma_clipped;
+use crate::cameraframe::ClippedStats;
+use crate::cameraframe::FrameData;
+use crate::cameraframe::MonoPixel;
+use crate::cameraframe::SigmaClip;
+
+/// How the background is estimated
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BackgroundConfig {
+    /// Width and height of the boxes of the mesh
+    pub box_size: u32,
+    /// Width and height, in boxes, of the median filter applied to the mesh
+    pub filter_size: u32,
+    /// Pixels further than this many standard deviations from the median are rejected
+    pub nsigma: f64,
+    /// Maximum number of rejection passes
+    pub iterations: usize,
+}
+
+impl Default for BackgroundConfig {
+    fn default() -> Self {
+        Self {
+            box_size: 64,
+            filter_size: 3,
+            nsigma: 3.0,
+            iterations: 5,
+        }
+    }
+}
+
+/// Summary of a background estimate, over the boxes of the mesh
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct BackgroundStats {
+    /// Median background level
+    pub level: f64,
+    /// Median noise (standard deviation about the background)
+    pub noise: f64,
+    /// Lowest and highest background levels
+    pub min_level: f64,
+    pub max_level: f64,
+}
+
+/// Background and noise maps of a frame
+#[derive(Clone, Debug)]
+pub struct Background {
+    pub level: FrameData<f32>,
+    pub noise: FrameData<f32>,
+    pub stats: BackgroundStats,
+}
+
+impl Background {
+    /// Estimate the background of a frame
+    pub fn estimate<T: MonoPixel>(data: &FrameData<T>, config: &BackgroundConfig) -> Background {
+        let (width, height) = (data.width as usize, data.height as usize);
+        let box_size = (config.box_size as usize).max(1);
+        let clip = SigmaClip {
+            nsigma: config.nsigma,
+            iterations: config.iterations,
+        };
+        let (nx, ny) = (width.div_ceil(box_size), height.div_ceil(box_size));
+
+        let mut level = Vec::with_capacity(nx * ny);
+        let mut noise = Vec::with_capacity(nx * ny);
+        for by in 0..ny {
+            for bx in 0..nx {
+                let rows = (by * box_size..((by + 1) * box_size).min(height))
+                    .map(|y| {
+                        (bx * box_size..((bx + 1) * box_size).min(width))
+                            .map(|x| data.data[y * width + x].to_f64().unwrap())
+                            .collect::<Vec<f64>>()
+                    })
+                    .collect::<Vec<_>>();
+                let mut values = rows.concat();
+                let (median, sigma) = clipped_median(&mut values, &clip);
+                // Differences of neighboring pixels have sqrt(2) times the noise,
+                // and are only offset by a gradient across the box
+                let mut differences = rows
+                    .iter()
+                    .flat_map(|row| row.windows(2).map(|w| w[1] - w[0]))
+                    .collect::<Vec<f64>>();
+                let sigma = match differences.is_empty() {
+                    true => sigma,
+                    false => clipped_median(&mut differences, &clip).1 / std::f64::consts::SQRT_2,
+                };
+                level.push(median);
+                noise.push(sigma);
+            }
+        }
+        let filter = config.filter_size as usize;
+        let level = median_filter(&level, nx, ny, filter);
+        let noise = median_filter(&noise, nx, ny, filter);
+
+        let stats = match level.is_empty() {
+            true => BackgroundStats::default(),
+            false => BackgroundStats {
+                level: median(&mut level.clone()).unwrap_or_default(),
+                noise: median(&mut noise.clone()).unwrap_or_default(),
+                min_level: level.iter().copied().fold(f64::MAX, f64::min),
+                max_level: level.iter().copied().fold(f64::MIN, f64::max),
+            },
+        };
+        Background {
+            level: interpolate(&level, nx, ny, data.width, data.height, box_size),
+            noise: interpolate(&noise, nx, ny, data.width, data.height, box_size),
+            stats,
+        }
+    }
+
+    /// Subtract the background from a frame
+    ///
+    /// # Arguments
+    /// * `data` - The frame the background was estimated from
+    /// * `pedestal` - Value added after subtracting, so that the noise around a
+    ///   background of zero is not lost for unsigned pixels; the median
+    ///   background level keeps the frame in its original range
+    ///
+    /// # Returns
+    /// The subtracted frame, with values rounded and clamped to the range of the pixel type
+    ///
+    pub fn subtract<T: MonoPixel>(&self, data: &FrameData<T>, pedestal: f64) -> FrameData<T> {
+        let (tmin, tmax) = (
+            T::min_value().to_f64().unwrap(),
+            T::max_value().to_f64().unwrap(),
+        );
+        FrameData {
+            width: data.width,
+            height: data.height,
+            data: data
+                .data
+                .iter()
+                .zip(self.level.data.iter())
+                .map(|(v, b)| {
+                    let v = v.to_f64().unwrap() - *b as f64 + pedestal;
+                    T::from(v.round().clamp(tmin, tmax)).unwrap()
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Median and standard deviation of the values of a box, after sigma clipping
+fn clipped_median(values: &mut [f64], clip: &SigmaClip) -> (f64, f64) {
+    match sigma_clipped(values, clip) {
+        Some(ClippedStats { median, sigma, .. }) => (median, sigma),
+        None => (0.0, 0.0),
+    }
+}
+
+/// Median of each value and its neighbors, in a square of `size` values on a `nx` by `ny` grid
+///
+/// The square is shrunk near the edges of the grid to stay centered on the value,
+/// so that a gradient across the grid is preserved
+fn median_filter(values: &[f64], nx: usize, ny: usize, size: usize) -> Vec<f64> {
+    (0..ny)
+        .flat_map(|y| (0..nx).map(move |x| (x, y)))
+        .map(|(x, y)| {
+            let hx = (size / 2).min(x).min(nx - 1 - x);
+            let hy = (size / 2).min(y).min(ny - 1 - y);
+            let mut window = (y - hy..=y + hy)
+                .flat_map(|wy| (x - hx..=x + hx).map(move |wx| values[wy * nx + wx]))
+                .collect::<Vec<f64>>();
+            median(&mut window).unwrap_or_default()
+        })
+        .collect()
+}
+
+/// Interpolate mesh values bilinearly between box centers, over a frame of
+/// `width` by `height` pixels
+fn interpolate(
+    mesh: &[f64],
+    nx: usize,
+    ny: usize,
+    width: u32,
+    height: u32,
+    box_size: usize,
+) -> FrameData<f32> {
+    // For each pixel along an axis: the two boxes either side and the weight of the second
+    let weights = |n: usize, size: usize| {
+        if n < 2 {
+            return vec![(0, 0, 0.0); size];
+        }
+        let centers = (0..n)
+            .map(|i| (i * box_size + ((i + 1) * box_size).min(size)) as f64 / 2.0)
+            .collect::<Vec<f64>>();
+        (0..size)
+            .map(|p| {
+                let c = p as f64 + 0.5;
+                // Beyond the outermost centers, the outermost two boxes are extrapolated
+                let i = centers
+                    .iter()
+                    .take_while(|x| **x <= c)
+                    .count()
+                    .clamp(1, n - 1);
+                (
+                    i - 1,
+                    i,
+                    (c - centers[i - 1]) / (centers[i] - centers[i - 1]),
+                )
+            })
+            .collect::<Vec<_>>()
+    };
+    let wx = weights(nx, width as usize);
+    let wy = weights(ny, height as usize);
+    FrameData {
+        width,
+        height,
+        data: wy
+            .iter()
+            .flat_map(|(y0, y1, ty)| {
+                wx.iter().map(move |(x0, x1, tx)| {
+                    let v = |x: usize, y: usize| mesh[y * nx + x];
+                    let top = v(*x0, *y0) * (1.0 - tx) + v(*x1, *y0) * tx;
+                    let bottom = v(*x0, *y1) * (1.0 - tx) + v(*x1, *y1) * tx;
+                    (top * (1.0 - ty) + bottom * ty) as f32
+                })
+            })
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Noise of 5 on a gradient of 1000 + 2 x + y, with a few bright stars
+    fn gradient() -> FrameData<u16> {
+        let mut frame = FrameData::<u16>::rand_norm(100.0, 5.0, 256, 192);
+        for y in 0..192_u32 {
+            for x in 0..256_u32 {
+                let i = (y * 256 + x) as usize;
+                frame.data[i] += (900 + 2 * x + y) as u16;
+            }
+        }
+        for (x, y) in [(40, 40), (100, 150), (200, 90), (201, 91), (202, 90)] {
+            frame.data[y * 256 + x] = 60000;
+        }
+        frame
+    }
+
+    #[test]
+    fn test_estimate() {
+        let frame = gradient();
+        let config = BackgroundConfig {
+            box_size: 32,
+            ..Default::default()
+        };
+        let bg = Background::estimate(&frame, &config);
+        assert_eq!((bg.level.width, bg.level.height), (256, 192));
+        // The noise is truncated to integers, lowering the background by half a count
+        for (x, y) in [
+            (40, 40),
+            (100, 150),
+            (128, 96),
+            (201, 91),
+            (16, 16),
+            (240, 176),
+        ] {
+            let truth = 1000.0 + 2.0 * x as f64 + y as f64;
+            assert!((bg.level.at(x, y) as f64 - truth).abs() < 2.0);
+            assert!((bg.noise.at(x, y) - 5.0).abs() < 0.5);
+        }
+        assert!((bg.stats.noise - 5.0).abs() < 0.3);
+        assert!(bg.stats.min_level < 1070.0 && bg.stats.max_level > 1600.0);
+    }
+
+    #[test]
+    fn test_subtract() {
+        let frame = gradient();
+        let bg = Background::estimate(&frame, &BackgroundConfig::default());
+        let flat = bg.subtract(&frame, 500.0);
+        // Flat at both sides of the frame, away from the stars
+        let mean = |x0: u32, x1: u32| {
+            let region = flat.subregion(x0, 128, x1, 192);
+            region.data.iter().map(|v| *v as f64).sum::<f64>() / region.data.len() as f64
+        };
+        assert!((mean(0, 64) - 500.0).abs() < 2.0);
+        assert!((mean(192, 256) - 500.0).abs() < 2.0);
+        // Values below zero are clamped
+        let dark = bg.subtract(&frame, 0.0);
+        assert!(dark.data.contains(&0));
+    }
+}
@@ -0,0 +1,346 @@
+//!
+//! Bad pixel detection and interpolation
+//!
+//! Bad pixels are found from the per-pixel mean and variance over a sequence
+//! of frames: in darks, pixels with an outlying mean are hot and pixels with an
+//! outlying variance are noisy; in flats, pixels with too little response are
+//! dead.  Flagged pixels are replaced by the median of their valid neighbors.
+//!
+
+use crate::cameraframe::mad;
+use crate::cameraframe::median;
+use crate::cameraframe::welford;
+use crate::cameraframe::FrameData;
+use crate::cameraframe::MonoPixel;
+use crate::cameraframe::Pixel;
+use crate::cameraframe::MAD_TO_SIGMA;
+
+use std::error::Error;
+
+/// Thresholds used to flag bad pixels
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BadPixelThresholds {
+    /// Dark pixels whose mean is more than this many (robust) standard deviations
+    /// above the median are hot
+    pub hot_sigma: f64,
+    /// Dark pixels whose variance is more than this many (robust) standard deviations
+    /// above the median variance are noisy
+    pub noisy_sigma: f64,
+    /// Flat pixels whose mean is below this fraction of the median are dead
+    pub dead_fraction: f64,
+}
+
+impl Default for BadPixelThresholds {
+    fn default() -> Self {
+        Self {
+            hot_sigma: 6.0,
+            noisy_sigma: 10.0,
+            dead_fraction: 0.2,
+        }
+    }
+}
+
+/// Map of bad pixels, as a mask of flags per pixel (0 for good pixels)
+#[derive(Clone, Debug)]
+pub struct BadPixelMap {
+    mask: FrameData<u8>,
+}
+
+impl BadPixelMap {
+    /// Flag of pixels with an excessive dark signal
+    pub const HOT: u8 = 1;
+    /// Flag of pixels with an excessive dark noise
+    pub const NOISY: u8 = 2;
+    /// Flag of pixels with little or no response to light
+    pub const DEAD: u8 = 4;
+    /// Flags found from darks
+    pub const DARK_FLAGS: u8 = Self::HOT | Self::NOISY;
+    /// Flags found from flats
+    pub const FLAT_FLAGS: u8 = Self::DEAD;
+
+    /// Flags of each pixel
+    pub fn mask(&self) -> &FrameData<u8> {
+        &self.mask
+    }
+
+    pub fn width(&self) -> u32 {
+        self.mask.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.mask.height
+    }
+
+    /// Number of bad pixels
+    pub fn count(&self) -> usize {
+        self.mask.data.iter().filter(|f| **f != 0).count()
+    }
+
+    /// Whether a pixel is flagged as bad
+    pub fn is_bad(&self, x: u32, y: u32) -> bool {
+        self.mask.at(x, y) != 0
+    }
+
+    /// Find hot and noisy pixels in a sequence of dark frames
+    ///
+    /// # Returns
+    /// The map, or an error if there are no frames or their sizes differ
+    ///
+    pub fn from_darks<T: Pixel + num_traits::ToPrimitive>(
+        frames: &[FrameData<T>],
+        thresholds: &BadPixelThresholds,
+    ) -> Result<Self, Box<dyn Error>> {
+        let Moments {
+            width,
+            height,
+            means,
+            vars,
+        } = moments(frames)?;
+        let hot = outlier_limit(&means, thresholds.hot_sigma);
+        let noisy = outlier_limit(&vars, thresholds.noisy_sigma);
+        let data = means
+            .iter()
+            .zip(vars.iter())
+            .map(|(m, v)| {
+                let mut flags = 0;
+                if *m > hot {
+                    flags |= Self::HOT;
+                }
+                if *v > noisy {
+                    flags |= Self::NOISY;
+                }
+                flags
+            })
+            .collect();
+        Ok(Self {
+            mask: FrameData {
+                width,
+                height,
+                data,
+            },
+        })
+    }
+
+    /// Find dead pixels in a sequence of flat frames, from which the bias and
+    /// dark have been subtracted
+    ///
+    /// # Returns
+    /// The map, or an error if there are no frames or their sizes differ
+    ///
+    pub fn from_flats<T: Pixel + num_traits::ToPrimitive>(
+        frames: &[FrameData<T>],
+        thresholds: &BadPixelThresholds,
+    ) -> Result<Self, Box<dyn Error>> {
+        let Moments {
+            width,
+            height,
+            means,
+            ..
+        } = moments(frames)?;
+        let limit = median(&mut means.clone()).unwrap_or(0.0) * thresholds.dead_fraction;
+        let data = means
+            .iter()
+            .map(|m| if *m < limit { Self::DEAD } else { 0 })
+            .collect();
+        Ok(Self {
+            mask: FrameData {
+                width,
+                height,
+                data,
+            },
+        })
+    }
+
+    /// Replace the flags in `flags` by those of another map of the same size,
+    /// keeping the other flags
+    ///
+    /// Maps of a different size replace this one entirely.
+    pub fn update(&mut self, other: &BadPixelMap, flags: u8) {
+        if other.width() != self.width() || other.height() != self.height() {
+            *self = other.clone();
+            return;
+        }
+        self.mask
+            .data
+            .iter_mut()
+            .zip(other.mask.data.iter())
+            .for_each(|(f, o)| *f = (*f & !flags) | (*o & flags));
+    }
+
+    /// Load a map from a FITS file of flags
+    pub fn load(filename: &str) -> Result<Self, Box<dyn Error>> {
+        Ok(Self {
+            mask: FrameData::<u8>::load_fits(filename)?,
+        })
+    }
+
+    /// Save the map to a FITS file of flags
+    pub fn save(&self, filename: &str) -> Result<(), Box<dyn Error>> {
+        self.mask.save_to_fits(filename)
+    }
+
+    /// Replace bad pixels in a frame of the same size by the median of their
+    /// valid neighbors
+    ///
+    /// The neighborhood grows from 3x3 up to 7x7 pixels until a valid neighbor
+    /// is found; pixels with none are left as they are.
+    pub fn apply<T: MonoPixel>(&self, frame: &mut FrameData<T>) {
+        if frame.width != self.width() || frame.height != self.height() {
+            return;
+        }
+        let source = frame.clone();
+        for y in 0..frame.height {
+            for x in 0..frame.width {
+                if !self.is_bad(x, y) {
+                    continue;
+                }
+                if let Some(v) = (1..=3).find_map(|r| self.neighbor_median(&source, x, y, r)) {
+                    frame.data[(y * frame.width + x) as usize] = v;
+                }
+            }
+        }
+    }
+
+    /// Median of the valid pixels within `radius` of (x, y)
+    fn neighbor_median<T: MonoPixel>(
+        &self,
+        frame: &FrameData<T>,
+        x: u32,
+        y: u32,
+        radius: u32,
+    ) -> Option<T> {
+        let (x0, y0) = (x.saturating_sub(radius), y.saturating_sub(radius));
+        let (x1, y1) = (
+            (x + radius + 1).min(frame.width),
+            (y + radius + 1).min(frame.height),
+        );
+        let values = frame.subregion(x0, y0, x1, y1);
+        let flags = self.mask.subregion(x0, y0, x1, y1);
+        let mut valid = values
+            .data
+            .iter()
+            .zip(flags.data.iter())
+            .filter(|(_, f)| **f == 0)
+            .map(|(v, _)| v.to_f64().unwrap())
+            .collect::<Vec<f64>>();
+        T::from(median(&mut valid)?.round())
+    }
+}
+
+/// Per-pixel mean and variance of a sequence of frames
+struct Moments {
+    width: u32,
+    height: u32,
+    means: Vec<f64>,
+    vars: Vec<f64>,
+}
+
+fn moments<T: Pixel + num_traits::ToPrimitive>(
+    frames: &[FrameData<T>],
+) -> Result<Moments, Box<dyn Error>> {
+    let first = frames.first().ok_or("No frames to find bad pixels in")?;
+    if frames
+        .iter()
+        .any(|f| f.width != first.width || f.height != first.height)
+    {
+        return Err("Frames to find bad pixels in differ in size".into());
+    }
+    let (means, vars) = (0..first.data.len())
+        .map(|i| welford(frames.iter().map(|f| f.data[i].to_f64().unwrap())))
+        .unzip();
+    Ok(Moments {
+        width: first.width,
+        height: first.height,
+        means,
+        vars,
+    })
+}
+
+/// Values above this are more than `nsigma` robust standard deviations above the median
+fn outlier_limit(values: &[f64], nsigma: f64) -> f64 {
+    let mut values = values.to_vec();
+    match (median(&mut values), mad(&mut values)) {
+        (Some(med), Some(mad)) => med + nsigma * MAD_TO_SIGMA * mad,
+        _ => f64::INFINITY,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_and_apply() {
+        let thresholds = BadPixelThresholds::default();
+        let darks = (0..20)
+            .map(|i| {
+                let mut f = FrameData::<u16>::rand_norm(1000.0, 10.0, 16, 16);
+                // Hot pixel at (3, 4), noisy pixel at (10, 10)
+                f.data[4 * 16 + 3] = 5000;
+                f.data[10 * 16 + 10] = if i % 2 == 0 { 800 } else { 1200 };
+                f
+            })
+            .collect::<Vec<_>>();
+        let mut map = BadPixelMap::from_darks(&darks, &thresholds).unwrap();
+        assert_eq!(map.mask().at(3, 4), BadPixelMap::HOT);
+        assert_eq!(map.mask().at(10, 10), BadPixelMap::NOISY);
+        assert_eq!(map.count(), 2);
+
+        let flats = (0..4)
+            .map(|_| {
+                let mut f = FrameData::<u16>::rand_norm(20000.0, 100.0, 16, 16);
+                // Dead column 0, dead pixel at (3, 4) too
+                (0..16).for_each(|y| f.data[y * 16] = 1000);
+                f.data[4 * 16 + 3] = 1000;
+                f
+            })
+            .collect::<Vec<_>>();
+        let dead = BadPixelMap::from_flats(&flats, &thresholds).unwrap();
+        assert_eq!(dead.count(), 17);
+        map.update(&dead, BadPixelMap::FLAT_FLAGS);
+        assert_eq!(map.mask().at(3, 4), BadPixelMap::HOT | BadPixelMap::DEAD);
+        assert_eq!(map.count(), 18);
+
+        let mut frame = FrameData::<u16> {
+            width: 16,
+            height: 16,
+            data: (0..256).map(|i| (i % 16) as u16 * 10).collect(),
+        };
+        frame.data[4 * 16 + 3] = 60000;
+        map.apply(&mut frame);
+        // Neighbors in columns 2 to 4 have values 20, 30 and 40
+        assert_eq!(frame.at(3, 4), 30);
+        // Column 0 is replaced by its valid neighbors in column 1
+        assert_eq!(frame.at(0, 7), 10);
+        assert_eq!(frame.at(10, 10), 100);
+    }
+
+    #[test]
+    fn test_moments_large_mean() {
+        // Values of 1e9 +/- 1, whose squares cancel out in a one-pass variance
+        let frames =
+            [1.0e9 - 1.0, 1.0e9 + 1.0, 1.0e9 - 1.0, 1.0e9 + 1.0].map(|v| FrameData::<f64> {
+                width: 1,
+                height: 1,
+                data: vec![v],
+            });
+        let m = moments(&frames).unwrap();
+        assert_eq!(m.means, vec![1.0e9]);
+        assert!((m.vars[0] - 1.0).abs() < 1.0e-6);
+    }
+
+    #[test]
+    fn test_save_load() {
+        let filename = std::env::temp_dir().join("viewer_test_badpixels.fits");
+        let filename = filename.to_str().unwrap();
+        let mut map = BadPixelMap {
+            mask: FrameData::zeros(4, 3),
+        };
+        map.mask.data[5] = BadPixelMap::HOT;
+        map.save(filename).unwrap();
+        let loaded = BadPixelMap::load(filename).unwrap();
+        assert_eq!(loaded.mask().data, map.mask().data);
+        assert!(loaded.is_bad(1, 1));
+        let _ = std::fs::remove_file(filename);
+    }
+}
@@ -0,0 +1,535 @@
+//!
+//! Dark frame and flat field calibration
+//!
+//! A calibrated frame is
+//!
+//! ```text
+//! (raw - bias - dark * exposure) / flat + pedestal
+//! ```
+//!
+//! where the master bias is in ADU, the master dark is the dark signal rate in
+//! ADU per second (bias removed, so it can be scaled to any exposure), and the
+//! master flat is normalized to a mean of one.  The pedestal is the mean of the
+//! master bias, so that the noise around a signal of zero is not clipped for
+//! unsigned pixels.  Masters are stored as 32-bit
+//! floating-point FITS files, and can be built in the viewer by capturing a
+//! sequence of frames and stacking them.
+//!
+//! Bad pixels found while capturing darks and flats are then replaced by the
+//! median of their neighbors; the bad pixel map can be saved and loaded too.
+//!
+
+use super::badpixels::BadPixelMap;
+use super::badpixels::BadPixelThresholds;
+use crate::cameraframe::compensated_sum;
+use crate::cameraframe::CameraFrame;
+use crate::cameraframe::CombineMethod;
+use crate::cameraframe::FrameData;
+use crate::cameraframe::MonoPixel;
+
+use std::error::Error;
+
+/// Flat field values below this are considered to have no response and are not divided by
+const MIN_FLAT: f32 = 1.0e-3;
+
+/// Type of master calibration frame
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MasterKind {
+    Bias,
+    Dark,
+    Flat,
+}
+
+impl MasterKind {
+    pub const ALL: [MasterKind; 3] = [MasterKind::Bias, MasterKind::Dark, MasterKind::Flat];
+
+    /// Master kind from its name ("Bias", "Dark" or "Flat", case-insensitive)
+    pub fn from_string(name: &str) -> Option<MasterKind> {
+        match name.to_lowercase().as_str() {
+            "bias" => Some(MasterKind::Bias),
+            "dark" => Some(MasterKind::Dark),
+            "flat" => Some(MasterKind::Flat),
+            _ => None,
+        }
+    }
+
+    /// Name of the master kind, as accepted by `from_string`
+    pub fn name(&self) -> &'static str {
+        match self {
+            MasterKind::Bias => "Bias",
+            MasterKind::Dark => "Dark",
+            MasterKind::Flat => "Flat",
+        }
+    }
+}
+
+/// Progress of a master frame capture
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CaptureStatus {
+    pub kind: MasterKind,
+    /// Frames captured so far
+    pub captured: usize,
+    /// Frames to capture
+    pub count: usize,
+}
+
+/// Frames being collected to build a master
+struct Capture {
+    kind: MasterKind,
+    count: usize,
+    method: CombineMethod,
+    frames: Vec<FrameData<f32>>,
+    exposure: f64,
+}
+
+/// Master calibration frames, and the capture of new ones
+#[derive(Default)]
+pub struct Calibration {
+    bias: Option<FrameData<f32>>,
+    dark: Option<FrameData<f32>>,
+    flat: Option<FrameData<f32>>,
+    badpixels: Option<BadPixelMap>,
+    thresholds: BadPixelThresholds,
+    capture: Option<Capture>,
+    /// Mean of the master bias, added back to calibrated frames
+    pedestal: f64,
+    /// Why the last capture failed to build a master, if it did
+    capture_error: Option<String>,
+}
+
+impl Calibration {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The master frame of a kind, if one is loaded
+    pub fn master(&self, kind: MasterKind) -> Option<&FrameData<f32>> {
+        match kind {
+            MasterKind::Bias => self.bias.as_ref(),
+            MasterKind::Dark => self.dark.as_ref(),
+            MasterKind::Flat => self.flat.as_ref(),
+        }
+    }
+
+    fn master_mut(&mut self, kind: MasterKind) -> &mut Option<FrameData<f32>> {
+        match kind {
+            MasterKind::Bias => &mut self.bias,
+            MasterKind::Dark => &mut self.dark,
+            MasterKind::Flat => &mut self.flat,
+        }
+    }
+
+    /// Set a master frame
+    ///
+    /// Flats are normalized to a mean of one
+    ///
+    /// # Arguments
+    /// * `kind` - Kind of master
+    /// * `data` - The master: bias in ADU, dark rate in ADU per second, or flat in any units
+    ///
+    pub fn set_master(&mut self, kind: MasterKind, mut data: FrameData<f32>) {
+        let mean = compensated_sum(data.data.iter().map(|x| *x as f64)) / data.data.len() as f64;
+        match kind {
+            MasterKind::Bias => self.pedestal = mean,
+            MasterKind::Flat if mean > 0.0 => data
+                .data
+                .iter_mut()
+                .for_each(|x| *x = (*x as f64 / mean) as f32),
+            _ => {}
+        }
+        *self.master_mut(kind) = Some(data);
+    }
+
+    /// Remove a master frame
+    pub fn clear(&mut self, kind: MasterKind) {
+        *self.master_mut(kind) = None;
+    }
+
+    /// Load a master frame from a FITS file
+    pub fn load(&mut self, kind: MasterKind, filename: &str) -> Result<(), Box<dyn Error>> {
+        self.set_master(kind, FrameData::<f32>::load_fits_f32(filename)?);
+        Ok(())
+    }
+
+    /// Save a master frame to a FITS file
+    pub fn save(&self, kind: MasterKind, filename: &str) -> Result<(), Box<dyn Error>> {
+        self.master(kind)
+            .ok_or(format!("No master {} to save", kind.name().to_lowercase()))?
+            .save_to_fits_f32(filename)
+    }
+
+    /// The bad pixel map, if one has been found or loaded
+    pub fn badpixels(&self) -> Option<&BadPixelMap> {
+        self.badpixels.as_ref()
+    }
+
+    /// Remove the bad pixel map
+    pub fn clear_badpixels(&mut self) {
+        self.badpixels = None;
+    }
+
+    /// Load a bad pixel map from a FITS file
+    pub fn load_badpixels(&mut self, filename: &str) -> Result<(), Box<dyn Error>> {
+        self.badpixels = Some(BadPixelMap::load(filename)?);
+        Ok(())
+    }
+
+    /// Save the bad pixel map to a FITS file
+    pub fn save_badpixels(&self, filename: &str) -> Result<(), Box<dyn Error>> {
+        self.badpixels
+            .as_ref()
+            .ok_or("No bad pixel map to save")?
+            .save(filename)
+    }
+
+    /// Start capturing frames to build a new master, replacing any capture in progress
+    ///
+    /// # Arguments
+    /// * `kind` - Kind of master to build
+    /// * `count` - Number of frames to combine
+    /// * `method` - How the frames are combined
+    ///
+    pub fn start_capture(&mut self, kind: MasterKind, count: usize, method: CombineMethod) {
+        self.capture = Some(Capture {
+            kind,
+            count: count.max(1),
+            method,
+            frames: Vec::new(),
+            exposure: 0.0,
+        });
+        self.capture_error = None;
+    }
+
+    /// Progress of the capture in progress, if any
+    pub fn capture_status(&self) -> Option<CaptureStatus> {
+        self.capture.as_ref().map(|c| CaptureStatus {
+            kind: c.kind,
+            captured: c.frames.len(),
+            count: c.count,
+        })
+    }
+
+    /// Why the last capture failed to build its master or find bad pixels, if it did
+    pub fn capture_error(&self) -> Option<&str> {
+        self.capture_error.as_deref()
+    }
+
+    /// Add a raw frame to the capture in progress, if any
+    ///
+    /// When enough frames have been captured, the master is built and set,
+    /// and the bad pixels found in darks or flats update the bad pixel map;
+    /// if that fails, the error is kept for `capture_error`.
+    /// If the frame size changes during a capture, the capture restarts.
+    pub fn add_frame<T: MonoPixel>(&mut self, frame: &CameraFrame<T>) {
+        let Some(capture) = self.capture.as_mut() else {
+            return;
+        };
+        if capture
+            .frames
+            .first()
+            .is_some_and(|f| f.width != frame.data.width || f.height != frame.data.height)
+        {
+            capture.frames.clear();
+            capture.exposure = 0.0;
+        }
+        capture.frames.push(frame.data.to_float());
+        capture.exposure += frame.exposure;
+        if capture.frames.len() < capture.count {
+            return;
+        }
+        let capture = self.capture.take().unwrap();
+        let kind = capture.kind;
+        if let Err(e) = self.finish_capture(capture) {
+            self.capture_error = Some(format!(
+                "Cannot build master {}: {}",
+                kind.name().to_lowercase(),
+                e
+            ));
+        }
+    }
+
+    /// Build and set the master from a complete capture, and update the bad
+    /// pixel map from darks or flats; nothing changes if either fails
+    fn finish_capture(&mut self, capture: Capture) -> Result<(), Box<dyn Error>> {
+        let exposure = capture.exposure / capture.frames.len() as f64;
+        let found = match capture.kind {
+            MasterKind::Bias => None,
+            MasterKind::Dark => Some((
+                BadPixelMap::from_darks(&capture.frames, &self.thresholds)?,
+                BadPixelMap::DARK_FLAGS,
+            )),
+            // Dead pixels are found from the response to light alone, without the bias and dark
+            MasterKind::Flat => {
+                let flats = capture
+                    .frames
+                    .iter()
+                    .map(|f| self.build_master(MasterKind::Flat, f.clone(), exposure))
+                    .collect::<Vec<_>>();
+                Some((
+                    BadPixelMap::from_flats(&flats, &self.thresholds)?,
+                    BadPixelMap::FLAT_FLAGS,
+                ))
+            }
+        };
+        let master = FrameData::stack(&capture.frames, capture.method)?;
+        if let Some((found, flags)) = found {
+            match self.badpixels.as_mut() {
+                Some(map) => map.update(&found, flags),
+                None => self.badpixels = Some(found),
+            }
+        }
+        let master = self.build_master(capture.kind, master, exposure);
+        self.set_master(capture.kind, master);
+        Ok(())
+    }
+
+    /// Build a master from stacked frames, removing the current bias (and dark, for flats)
+    fn build_master(
+        &self,
+        kind: MasterKind,
+        stacked: FrameData<f32>,
+        exposure: f64,
+    ) -> FrameData<f32> {
+        let (width, height) = (stacked.width, stacked.height);
+        let mut data = stacked
+            .data
+            .into_iter()
+            .map(|x| x as f64)
+            .collect::<Vec<f64>>();
+        let matches = |m: &&FrameData<f32>| m.width == width && m.height == height;
+
+        if kind != MasterKind::Bias {
+            if let Some(bias) = self.bias.as_ref().filter(matches) {
+                data.iter_mut()
+                    .zip(bias.data.iter())
+                    .for_each(|(d, b)| *d -= *b as f64);
+            }
+        }
+        match kind {
+            // Darks taken with no exposure time cannot be scaled, so are kept as they are
+            MasterKind::Dark if exposure > 0.0 => data.iter_mut().for_each(|d| *d /= exposure),
+            MasterKind::Flat => {
+                if let Some(dark) = self.dark.as_ref().filter(matches) {
+                    data.iter_mut()
+                        .zip(dark.data.iter())
+                        .for_each(|(d, r)| *d -= *r as f64 * exposure);
+                }
+            }
+            _ => {}
+        }
+        FrameData {
+            width,
+            height,
+            data: data.into_iter().map(|x| x as f32).collect(),
+        }
+    }
+
+    /// Values of a master, if it is loaded and applies to frames of the given size
+    fn matching(&self, kind: MasterKind, width: u32, height: u32) -> Option<&[f32]> {
+        self.master(kind)
+            .filter(|m| m.width == width && m.height == height)
+            .map(|m| m.data.as_slice())
+    }
+
+    /// Calibrate a frame
+    ///
+    /// Masters whose size differs from the frame are ignored.  The mean of the
+    /// master bias is added back as a pedestal, then calibrated values are
+    /// rounded and clamped to the range of the pixel type, and bad pixels are
+    /// interpolated.
+    ///
+    /// # Returns
+    /// The calibrated frame, or None if no master or bad pixel map applies to the frame
+    ///
+    pub fn apply<T: MonoPixel>(&self, frame: &CameraFrame<T>) -> Option<CameraFrame<T>> {
+        let (width, height) = (frame.data.width, frame.data.height);
+        let bias = self.matching(MasterKind::Bias, width, height);
+        let dark = self.matching(MasterKind::Dark, width, height);
+        let flat = self.matching(MasterKind::Flat, width, height);
+        let badpixels = self
+            .badpixels
+            .as_ref()
+            .filter(|m| m.width() == width && m.height() == height);
+        if bias.is_none() && dark.is_none() && flat.is_none() && badpixels.is_none() {
+            return None;
+        }
+        let exposure = frame.exposure as f32;
+        let pedestal = bias.map_or(0.0, |_| self.pedestal as f32);
+        let (min, max) = (
+            T::min_value().to_f32().unwrap(),
+            T::max_value().to_f32().unwrap(),
+        );
+
+        let data = frame
+            .data
+            .data
+            .iter()
+            .enumerate()
+            .map(|(i, x)| {
+                let mut v = x.to_f32().unwrap();
+                if let Some(bias) = bias {
+                    v -= bias[i];
+                }
+                if let Some(dark) = dark {
+                    v -= dark[i] * exposure;
+                }
+                if let Some(flat) = flat {
+                    if flat[i] > MIN_FLAT {
+                        v /= flat[i];
+                    }
+                }
+                v += pedestal;
+                T::from(v.round().clamp(min, max)).unwrap()
+            })
+            .collect();
+        let mut data = FrameData {
+            width,
+            height,
+            data,
+        };
+        if let Some(badpixels) = badpixels {
+            badpixels.apply(&mut data);
+        }
+        Some(CameraFrame::create(
+            frame.exposure,
+            frame.center_of_integration,
+            frame.bit_depth,
+            data,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(exposure: f64, data: Vec<u16>) -> CameraFrame<u16> {
+        CameraFrame::create(
+            exposure,
+            chrono::Utc::now(),
+            16,
+            FrameData {
+                width: 2,
+                height: 2,
+                data,
+            },
+        )
+    }
+
+    #[test]
+    fn test_capture_and_apply() {
+        let mut cal = Calibration::new();
+        assert!(cal.apply(&frame(1.0, vec![1, 2, 3, 4])).is_none());
+
+        // Bias of 100; dark current of 10 and 20 ADU/s; flat response of 0.5 and 1.5
+        cal.start_capture(MasterKind::Bias, 2, CombineMethod::Mean);
+        cal.add_frame(&frame(0.0, vec![99, 101, 100, 100]));
+        assert_eq!(
+            cal.capture_status(),
+            Some(CaptureStatus {
+                kind: MasterKind::Bias,
+                captured: 1,
+                count: 2
+            })
+        );
+        cal.add_frame(&frame(0.0, vec![101, 99, 100, 100]));
+        assert!(cal.capture_status().is_none());
+        assert_eq!(cal.master(MasterKind::Bias).unwrap().data, vec![100.0; 4]);
+
+        cal.start_capture(MasterKind::Dark, 1, CombineMethod::Mean);
+        cal.add_frame(&frame(2.0, vec![120, 140, 120, 140]));
+        assert_eq!(
+            cal.master(MasterKind::Dark).unwrap().data,
+            vec![10.0, 20.0, 10.0, 20.0]
+        );
+
+        cal.start_capture(MasterKind::Flat, 1, CombineMethod::Mean);
+        cal.add_frame(&frame(1.0, vec![610, 1620, 610, 1620]));
+        assert_eq!(
+            cal.master(MasterKind::Flat).unwrap().data,
+            vec![0.5, 1.5, 0.5, 1.5]
+        );
+        // A flat response of 0.5 is low, but not dead
+        assert_eq!(cal.badpixels().unwrap().count(), 0);
+
+        // 3 s exposure of a uniform 600 ADU source, on the pedestal of 100 ADU
+        let raw = frame(3.0, vec![430, 1060, 430, 1060]);
+        let calibrated = cal.apply(&raw).unwrap();
+        assert_eq!(calibrated.data.data, vec![700; 4]);
+        assert_eq!(calibrated.exposure, 3.0);
+
+        // Masters that do not match the frame size are ignored
+        let big = CameraFrame::create(
+            1.0,
+            raw.center_of_integration,
+            16,
+            FrameData::<u16>::ones(3, 3),
+        );
+        assert!(cal.apply(&big).is_none());
+
+        cal.clear(MasterKind::Flat);
+        assert_eq!(
+            cal.apply(&raw).unwrap().data.data,
+            vec![400, 1000, 400, 1000]
+        );
+    }
+
+    #[test]
+    fn test_negative_residuals() {
+        // Frames reading below the bias keep their noise on the pedestal of 1000 ADU
+        let mut cal = Calibration::new();
+        cal.set_master(
+            MasterKind::Bias,
+            FrameData {
+                width: 2,
+                height: 2,
+                data: vec![1000.0, 1010.0, 990.0, 1000.0],
+            },
+        );
+        let calibrated = cal.apply(&frame(1.0, vec![995, 1005, 1000, 1000])).unwrap();
+        assert_eq!(calibrated.data.data, vec![995, 995, 1010, 1000]);
+    }
+
+    #[test]
+    fn test_dead_pixels_with_bias() {
+        // Bias of 1000 ADU, and flats of 4000 ADU with a dead pixel reading only the bias
+        let mut cal = Calibration::new();
+        cal.set_master(
+            MasterKind::Bias,
+            FrameData {
+                width: 2,
+                height: 2,
+                data: vec![1000.0; 4],
+            },
+        );
+        cal.start_capture(MasterKind::Flat, 2, CombineMethod::Mean);
+        for _ in 0..2 {
+            cal.add_frame(&frame(1.0, vec![4000, 4000, 1000, 4000]));
+        }
+        let map = cal.badpixels().unwrap();
+        assert_eq!(map.count(), 1);
+        assert!(map.is_bad(0, 1));
+    }
+
+    #[test]
+    fn test_save_load() {
+        let filename = std::env::temp_dir().join("viewer_test_master_flat.fits");
+        let filename = filename.to_str().unwrap();
+        let mut cal = Calibration::new();
+        assert!(cal.save(MasterKind::Flat, filename).is_err());
+        cal.set_master(
+            MasterKind::Flat,
+            FrameData {
+                width: 2,
+                height: 1,
+                data: vec![2.0, 6.0],
+            },
+        );
+        cal.save(MasterKind::Flat, filename).unwrap();
+
+        let mut cal2 = Calibration::new();
+        cal2.load(MasterKind::Flat, filename).unwrap();
+        assert_eq!(cal2.master(MasterKind::Flat).unwrap().data, vec![0.5, 1.5]);
+        let _ = std::fs::remove_file(filename);
+    }
+}
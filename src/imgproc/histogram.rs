@@ -0,0 +1,186 @@
+//!
+//! Histograms of pixel values
+//!
+//! Bins span the range of values in the frame, so frames of any pixel type,
+//! including signed types and frames with zero or negative values, are binned.
+//!
+
+use crate::cameraframe::FrameData;
+use crate::cameraframe::MonoPixel;
+
+/// Spacing of histogram bins
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HistogramBinning {
+    /// Bins of equal width
+    Linear,
+    /// Bins of logarithmically increasing width above the minimum value
+    Log,
+}
+
+impl HistogramBinning {
+    /// Binning from its name ("Linear" or "Log", case-insensitive)
+    pub fn from_string(name: &str) -> Option<HistogramBinning> {
+        match name.to_lowercase().as_str() {
+            "linear" => Some(HistogramBinning::Linear),
+            "log" => Some(HistogramBinning::Log),
+            _ => None,
+        }
+    }
+}
+
+/// How a histogram is computed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HistogramConfig {
+    /// Maximum number of bins; integer pixels use fewer when their range is smaller
+    pub nbins: usize,
+    pub binning: HistogramBinning,
+}
+
+impl Default for HistogramConfig {
+    fn default() -> Self {
+        Self {
+            nbins: 256,
+            binning: HistogramBinning::Linear,
+        }
+    }
+}
+
+/// Histogram of pixel values
+#[derive(Clone, Debug, PartialEq)]
+pub struct Histogram {
+    /// Bin edges: bin `i` counts values in [edges[i], edges[i + 1])
+    pub edges: Vec<f64>,
+    /// Number of pixels in each bin
+    pub counts: Vec<u64>,
+}
+
+impl Histogram {
+    /// Compute the histogram of a frame
+    pub fn compute<T: MonoPixel>(data: &FrameData<T>, config: &HistogramConfig) -> Histogram {
+        if data.data.is_empty() {
+            return Histogram {
+                edges: vec![0.0, 1.0],
+                counts: vec![0],
+            };
+        }
+        let (min, max) = data.minmax();
+        let (min, max) = (min.to_f64().unwrap(), max.to_f64().unwrap());
+        // Number of distinct integer values
+        let span = max - min + 1.0;
+        let nbins = config.nbins.max(1);
+
+        match config.binning {
+            HistogramBinning::Linear => {
+                // Bins are a whole number of values wide, so each holds as many values
+                let width = (span / nbins as f64).ceil();
+                let nbins = (span / width).ceil() as usize;
+                let mut counts = vec![0; nbins];
+                data.data.iter().for_each(|x| {
+                    let bin = ((x.to_f64().unwrap() - min) / width) as usize;
+                    counts[bin.min(nbins - 1)] += 1;
+                });
+                Histogram {
+                    edges: (0..=nbins).map(|i| min + i as f64 * width).collect(),
+                    counts,
+                }
+            }
+            HistogramBinning::Log => {
+                // Bins are equally spaced in log(x - min + 1), which runs from 0 to log(span + 1)
+                let nbins = nbins.min(span as usize);
+                let scale = nbins as f64 / (span + 1.0).ln();
+                let mut counts = vec![0; nbins];
+                data.data.iter().for_each(|x| {
+                    let bin = ((x.to_f64().unwrap() - min + 1.0).ln() * scale) as usize;
+                    counts[bin.min(nbins - 1)] += 1;
+                });
+                Histogram {
+                    edges: (0..=nbins)
+                        .map(|i| min - 1.0 + (i as f64 / scale).exp())
+                        .collect(),
+                    counts,
+                }
+            }
+        }
+    }
+
+    /// Centers of the bins
+    pub fn centers(&self) -> Vec<f64> {
+        self.edges.windows(2).map(|e| (e[0] + e[1]) / 2.0).collect()
+    }
+
+    /// Range of values spanned by the bins
+    pub fn range(&self) -> (f64, f64) {
+        (self.edges[0], self.edges[self.edges.len() - 1])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_linear() {
+        let config = HistogramConfig::default();
+        // Zeros, and the maximum value, are binned
+        let data = FrameData::<u16> {
+            width: 4,
+            height: 1,
+            data: vec![0, 0, 1000, 65535],
+        };
+        let hist = Histogram::compute(&data, &config);
+        assert_eq!(hist.counts.len(), 256);
+        assert_eq!(hist.range(), (0.0, 65536.0));
+        assert_eq!(hist.counts[0], 2);
+        assert_eq!(hist.counts[3], 1);
+        assert_eq!(hist.counts[255], 1);
+
+        // Signed values, with one bin per value when the range is small
+        let data = FrameData::<i16> {
+            width: 5,
+            height: 1,
+            data: vec![-3, -3, -1, 0, 2],
+        };
+        let hist = Histogram::compute(&data, &config);
+        assert_eq!(hist.counts, vec![2, 0, 1, 1, 0, 1]);
+        assert_eq!(hist.range(), (-3.0, 3.0));
+
+        // A uniform frame
+        let hist = Histogram::compute(&FrameData::<u8>::zeros(3, 3), &config);
+        assert_eq!(hist.counts, vec![9]);
+
+        // The full range of a wide type
+        let data = FrameData::<i64> {
+            width: 3,
+            height: 1,
+            data: vec![i64::MIN, 0, i64::MAX],
+        };
+        let hist = Histogram::compute(&data, &config);
+        assert_eq!(hist.counts.iter().sum::<u64>(), 3);
+        assert_eq!(
+            (hist.counts[0], hist.counts[128], hist.counts[255]),
+            (1, 1, 1)
+        );
+    }
+
+    #[test]
+    fn test_log() {
+        let config = HistogramConfig {
+            nbins: 16,
+            binning: HistogramBinning::Log,
+        };
+        let data = FrameData::<i32> {
+            width: 6,
+            height: 1,
+            data: vec![-10, -10, -9, 0, 100, 65525],
+        };
+        let hist = Histogram::compute(&data, &config);
+        assert_eq!(hist.counts.len(), 16);
+        assert_eq!(hist.counts.iter().sum::<u64>(), 6);
+        assert_eq!((hist.counts[0], hist.counts[15]), (3, 1));
+        let (lo, hi) = hist.range();
+        assert!((lo + 10.0).abs() < 1e-6 && (hi - 65526.0).abs() < 1e-6);
+        // Bins widen with value
+        let centers = hist.centers();
+        assert!(centers[15] - centers[14] > centers[1] - centers[0]);
+    }
+}
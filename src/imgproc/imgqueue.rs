@@ -1,56 +1,403 @@
 use crate::cameraframe::CameraFrame;
 use crate::cameraframe::MonoPixel;
+use std::collections::VecDeque;
 use std::sync::Arc;
 use std::sync::Condvar;
 use std::sync::Mutex;
 use std::thread;
 
-/// A simple queue with a way to add images when they are received,
-/// and pull them off the queue and process them.
+/// What to do with an incoming frame when the queue is full
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QueuePolicy {
+    /// Only the most recent frame is kept; older unprocessed frames are dropped
+    KeepLatest,
+    /// Frames are processed in order; the producer blocks while the queue is full
+    Fifo,
+    /// Frames are processed in order; the oldest queued frame is dropped when the queue is full
+    DropOldest,
+}
+
+impl QueuePolicy {
+    /// Parse a queue policy from its name, as shown in the GUI (case-insensitive)
+    ///
+    /// # Arguments
+    /// * `name` - Name of the policy
+    ///
+    /// # Returns
+    /// The queue policy, or None if the name is not recognized
+    ///
+    pub fn from_string(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "keep latest" => Some(QueuePolicy::KeepLatest),
+            "fifo" => Some(QueuePolicy::Fifo),
+            "drop oldest" => Some(QueuePolicy::DropOldest),
+            _ => None,
+        }
+    }
+
+    /// Name of the policy, as shown in the GUI
+    pub fn name(&self) -> &'static str {
+        match self {
+            QueuePolicy::KeepLatest => "Keep Latest",
+            QueuePolicy::Fifo => "FIFO",
+            QueuePolicy::DropOldest => "Drop Oldest",
+        }
+    }
+}
+
+/// Counters describing the frames that have passed through the queue
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct QueueStats {
+    /// Number of frames handed to the queue
+    pub received: u64,
+    /// Number of frames passed to the processing function
+    pub processed: u64,
+    /// Number of frames discarded without being processed
+    pub dropped: u64,
+    /// Number of frames currently waiting in the queue
+    pub queued: usize,
+}
+
+struct QueueState<T>
+where
+    T: MonoPixel,
+{
+    frames: VecDeque<CameraFrame<T>>,
+    policy: QueuePolicy,
+    capacity: usize,
+    running: bool,
+    stats: QueueStats,
+}
+
+struct QueueShared<T>
+where
+    T: MonoPixel,
+{
+    state: Mutex<QueueState<T>>,
+    notempty: Condvar,
+    notfull: Condvar,
+}
+
+/// A bounded queue with a way to add images when they are received,
+/// and pull them off the queue and process them on a separate thread.
+///
+/// Frames that cannot be queued are handled according to the `QueuePolicy`
 ///
 pub struct ImageQueue<T>
 where
     T: MonoPixel + 'static,
 {
-    framelist: Arc<Mutex<Vec<CameraFrame<T>>>>,
-    framelistsync: Arc<(Mutex<()>, Condvar)>,
+    shared: Arc<QueueShared<T>>,
+    thread: Mutex<Option<thread::JoinHandle<()>>>,
 }
 
 impl<T> ImageQueue<T>
 where
     T: MonoPixel + 'static,
 {
+    /// Create a queue that only keeps the most recent frame
     pub fn new() -> Self {
+        Self::with_policy(QueuePolicy::KeepLatest, 1)
+    }
+
+    /// Create a queue with the given policy and capacity
+    ///
+    /// # Arguments
+    /// * `policy` - What to do with frames when the queue is full
+    /// * `capacity` - Maximum number of frames waiting in the queue (at least 1).
+    ///   Ignored for `QueuePolicy::KeepLatest`, which always holds one frame.
+    ///
+    pub fn with_policy(policy: QueuePolicy, capacity: usize) -> Self {
         ImageQueue {
-            framelist: Arc::new(Mutex::new(Vec::new())),
-            framelistsync: Arc::new((Mutex::new(()), Condvar::new())),
+            shared: Arc::new(QueueShared {
+                state: Mutex::new(QueueState {
+                    frames: VecDeque::new(),
+                    policy,
+                    capacity: capacity.max(1),
+                    running: false,
+                    stats: QueueStats::default(),
+                }),
+                notempty: Condvar::new(),
+                notfull: Condvar::new(),
+            }),
+            thread: Mutex::new(None),
+        }
+    }
+
+    /// Change the queue policy and capacity
+    ///
+    /// Frames in excess of the new capacity are dropped, oldest first.
+    pub fn set_policy(&self, policy: QueuePolicy, capacity: usize) {
+        let mut state = self.shared.state.lock().unwrap();
+        state.policy = policy;
+        state.capacity = capacity.max(1);
+        let limit = Self::limit(&state);
+        while state.frames.len() > limit {
+            state.frames.pop_front();
+            state.stats.dropped += 1;
+        }
+        self.shared.notfull.notify_all();
+    }
+
+    /// Current queue policy and capacity
+    pub fn policy(&self) -> (QueuePolicy, usize) {
+        let state = self.shared.state.lock().unwrap();
+        (state.policy, state.capacity)
+    }
+
+    /// Snapshot of the queue counters
+    pub fn stats(&self) -> QueueStats {
+        let state = self.shared.state.lock().unwrap();
+        QueueStats {
+            queued: state.frames.len(),
+            ..state.stats
+        }
+    }
+
+    fn limit(state: &QueueState<T>) -> usize {
+        match state.policy {
+            QueuePolicy::KeepLatest => 1,
+            _ => state.capacity,
         }
     }
 
     /// Start the image processing chain when a frame is available
+    ///
+    /// With `QueuePolicy::Fifo` this blocks while the queue is full.
+    /// Frames received while the queue is not running are dropped.
     pub fn on_frame_available(&self, frame: CameraFrame<T>) {
-        let (lock, cvar) = &*self.framelistsync;
-        let _guard = lock.lock().unwrap();
-        self.framelist.lock().unwrap().push(frame);
-        cvar.notify_one();
+        let mut state = self.shared.state.lock().unwrap();
+        state.stats.received += 1;
+
+        if state.policy == QueuePolicy::Fifo {
+            while state.running && state.frames.len() >= state.capacity {
+                state = self.shared.notfull.wait(state).unwrap();
+            }
+        }
+        if !state.running {
+            state.stats.dropped += 1;
+            return;
+        }
+        let limit = Self::limit(&state);
+        while state.frames.len() >= limit {
+            state.frames.pop_front();
+            state.stats.dropped += 1;
+        }
+        state.frames.push_back(frame);
+        self.shared.notempty.notify_one();
     }
 
+    /// Start a thread that runs `procfunc` on queued frames, oldest first
+    ///
+    /// # Arguments
+    /// * `procfunc` - Function called on each frame pulled off the queue
+    ///
+    /// # Panics
+    /// Panics if the queue is already running
+    ///
     pub fn start<F>(&self, procfunc: F)
     where
         F: Fn(CameraFrame<T>) + Send + 'static,
     {
-        let framelistsync = self.framelistsync.clone();
-        let framelist = self.framelist.clone();
-        let _thread = thread::spawn(move || {
-            loop {
-                let (lock, cvar) = &*framelistsync;
-                let guard = lock.lock().unwrap();
-                let _unused = cvar.wait(guard).unwrap();
-                while let Some(frame) = framelist.lock().unwrap().pop() {
-                    // Process the frame
-                    procfunc(frame);
+        {
+            let mut state = self.shared.state.lock().unwrap();
+            assert!(!state.running, "Image queue is already running");
+            state.running = true;
+        }
+        let shared = self.shared.clone();
+        *self.thread.lock().unwrap() = Some(thread::spawn(move || loop {
+            let frame = {
+                let mut state = shared.state.lock().unwrap();
+                while state.frames.is_empty() && state.running {
+                    state = shared.notempty.wait(state).unwrap();
                 }
+                if !state.running {
+                    break;
+                }
+                let frame = state.frames.pop_front().unwrap();
+                state.stats.processed += 1;
+                shared.notfull.notify_one();
+                frame
+            };
+            // Process the frame
+            procfunc(frame);
+        }));
+    }
+
+    /// Stop the processing thread and wait for it to exit
+    ///
+    /// Frames still in the queue are discarded and counted as dropped.
+    /// A frame that is being processed when `stop` is called is allowed to finish.
+    pub fn stop(&self) {
+        {
+            let mut state = self.shared.state.lock().unwrap();
+            state.running = false;
+            state.stats.dropped += state.frames.len() as u64;
+            state.frames.clear();
+            self.shared.notempty.notify_all();
+            self.shared.notfull.notify_all();
+        }
+        if let Some(thread) = self.thread.lock().unwrap().take() {
+            let _ = thread.join();
+        }
+    }
+
+    /// Used only for testing:
+    ///
+    /// Is the processing thread running?
+    #[cfg(test)]
+    pub fn is_running(&self) -> bool {
+        self.shared.state.lock().unwrap().running
+    }
+}
+
+impl<T> Drop for ImageQueue<T>
+where
+    T: MonoPixel + 'static,
+{
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cameraframe::FrameData;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    fn frame(value: u16) -> CameraFrame<u16> {
+        let mut data = FrameData::<u16>::zeros(4, 4);
+        data.data[0] = value;
+        CameraFrame::create(0.1, chrono::Utc::now(), 16, data)
+    }
+
+    /// Wait until `cond` is true, or give up after 5 seconds
+    fn wait_for(cond: impl Fn() -> bool) {
+        let start = std::time::Instant::now();
+        while !cond() && start.elapsed().as_secs() < 5 {
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    /// Start `queue` with a processing function that blocks until `gate` is set,
+    /// recording the first pixel of each processed frame
+    fn start_gated(queue: &ImageQueue<u16>) -> (Arc<AtomicBool>, Arc<Mutex<Vec<u16>>>) {
+        let gate = Arc::new(AtomicBool::new(false));
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let (g, s) = (gate.clone(), seen.clone());
+        queue.start(move |frame| {
+            while !g.load(Ordering::SeqCst) {
+                thread::sleep(Duration::from_millis(1));
             }
+            s.lock().unwrap().push(frame.data.data[0]);
         });
+        (gate, seen)
+    }
+
+    #[test]
+    fn test_start_stop() {
+        let queue = ImageQueue::<u16>::with_policy(QueuePolicy::Fifo, 16);
+        let count = Arc::new(AtomicUsize::new(0));
+        let c = count.clone();
+        queue.start(move |_frame| {
+            c.fetch_add(1, Ordering::SeqCst);
+        });
+        assert!(queue.is_running());
+
+        for i in 0..10 {
+            queue.on_frame_available(frame(i));
+        }
+        wait_for(|| count.load(Ordering::SeqCst) == 10);
+        assert_eq!(count.load(Ordering::SeqCst), 10);
+
+        queue.stop();
+        assert!(!queue.is_running());
+        assert!(queue.thread.lock().unwrap().is_none());
+        let stats = queue.stats();
+        assert_eq!(stats.received, 10);
+        assert_eq!(stats.processed, 10);
+        assert_eq!(stats.dropped, 0);
+    }
+
+    #[test]
+    fn test_drop_oldest() {
+        let queue = ImageQueue::<u16>::with_policy(QueuePolicy::DropOldest, 3);
+        let (gate, seen) = start_gated(&queue);
+
+        // First frame is picked up by the (blocked) worker, then queue fills
+        queue.on_frame_available(frame(0));
+        wait_for(|| queue.stats().processed == 1);
+        for i in 1..=5 {
+            queue.on_frame_available(frame(i));
+        }
+        let stats = queue.stats();
+        assert_eq!(stats.dropped, 2);
+        assert_eq!(stats.queued, 3);
+
+        gate.store(true, Ordering::SeqCst);
+        wait_for(|| seen.lock().unwrap().len() == 4);
+        assert_eq!(*seen.lock().unwrap(), vec![0, 3, 4, 5]);
+    }
+
+    #[test]
+    fn test_keep_latest() {
+        let queue = ImageQueue::<u16>::new();
+        let (gate, seen) = start_gated(&queue);
+
+        queue.on_frame_available(frame(0));
+        wait_for(|| queue.stats().processed == 1);
+        for i in 1..=5 {
+            queue.on_frame_available(frame(i));
+        }
+        assert_eq!(queue.stats().dropped, 4);
+
+        gate.store(true, Ordering::SeqCst);
+        wait_for(|| seen.lock().unwrap().len() == 2);
+        assert_eq!(*seen.lock().unwrap(), vec![0, 5]);
+    }
+
+    #[test]
+    fn test_policy_names() {
+        for policy in [
+            QueuePolicy::KeepLatest,
+            QueuePolicy::Fifo,
+            QueuePolicy::DropOldest,
+        ] {
+            assert_eq!(QueuePolicy::from_string(policy.name()), Some(policy));
+        }
+        assert_eq!(QueuePolicy::from_string("fifo"), Some(QueuePolicy::Fifo));
+        assert!(QueuePolicy::from_string("latest").is_none());
+    }
+
+    #[test]
+    fn test_fifo_backpressure() {
+        let queue = Arc::new(ImageQueue::<u16>::with_policy(QueuePolicy::Fifo, 2));
+        let (gate, seen) = start_gated(&queue);
+
+        let q = queue.clone();
+        let (done, finished) = mpsc::channel();
+        let producer = thread::spawn(move || {
+            for i in 0..6 {
+                q.on_frame_available(frame(i));
+            }
+            done.send(()).unwrap();
+        });
+        // Producer must block on the fourth frame: one frame in the worker, two in the queue
+        wait_for(|| {
+            let stats = queue.stats();
+            stats.received == 4 && stats.queued == 2 && stats.processed == 1
+        });
+        assert!(finished.recv_timeout(Duration::from_millis(50)).is_err());
+        assert_eq!(queue.stats().received, 4);
+
+        gate.store(true, Ordering::SeqCst);
+        finished.recv_timeout(Duration::from_secs(5)).unwrap();
+        producer.join().unwrap();
+        wait_for(|| seen.lock().unwrap().len() == 6);
+        assert_eq!(*seen.lock().unwrap(), vec![0, 1, 2, 3, 4, 5]);
+        assert_eq!(queue.stats().dropped, 0);
     }
 }
@@ -0,0 +1,182 @@
+//!
+//! Frame rate and latency measurements for the image pipeline
+//!
+
+use chrono::{DateTime, Utc};
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+/// Number of samples in the rolling averages
+const WINDOW: usize = 32;
+
+/// Rolling estimate of the rate of an event, from the times of the most recent occurrences
+#[derive(Clone, Debug, Default)]
+pub struct RateMeter {
+    times: VecDeque<DateTime<Utc>>,
+}
+
+impl RateMeter {
+    /// Record an occurrence of the event
+    ///
+    /// # Arguments
+    /// * `time` - Time of the occurrence
+    ///
+    pub fn record(&mut self, time: DateTime<Utc>) {
+        if self.times.len() == WINDOW {
+            self.times.pop_front();
+        }
+        self.times.push_back(time);
+    }
+
+    /// Average rate over the window, in Hz
+    ///
+    /// # Returns
+    /// The rate, or None if fewer than two occurrences have been recorded
+    /// (or they are not increasing in time)
+    ///
+    pub fn rate(&self) -> Option<f64> {
+        let first = self.times.front()?;
+        let last = self.times.back()?;
+        let span = (*last - *first).num_microseconds()? as f64 * 1.0e-6;
+        match span > 0.0 {
+            true => Some((self.times.len() - 1) as f64 / span),
+            false => None,
+        }
+    }
+}
+
+/// Rolling mean of a quantity
+#[derive(Clone, Debug, Default)]
+pub struct RollingMean {
+    values: VecDeque<f64>,
+}
+
+impl RollingMean {
+    /// Add a value to the window
+    pub fn record(&mut self, value: f64) {
+        if self.values.len() == WINDOW {
+            self.values.pop_front();
+        }
+        self.values.push_back(value);
+    }
+
+    /// Mean over the window, or None if no values have been recorded
+    pub fn mean(&self) -> Option<f64> {
+        match self.values.is_empty() {
+            true => None,
+            false => Some(self.values.iter().sum::<f64>() / self.values.len() as f64),
+        }
+    }
+}
+
+/// Snapshot of the pipeline rates
+///
+/// Each value is None until enough frames have passed through that stage
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PipelineRates {
+    /// Rate at which the camera acquires frames (from `center_of_integration`), in Hz
+    pub acquisition: Option<f64>,
+    /// Rate at which frames are processed, in Hz
+    pub processing: Option<f64>,
+    /// Rate at which frames are displayed, in Hz
+    pub display: Option<f64>,
+    /// Mean time from center of integration to display, in seconds
+    pub latency: Option<f64>,
+}
+
+#[derive(Default)]
+struct MetricsState {
+    acquisition: RateMeter,
+    processing: RateMeter,
+    display: RateMeter,
+    latency: RollingMean,
+}
+
+/// Thread-safe frame rate and latency measurements for the whole pipeline
+///
+/// Each stage records its events; `rates` can be called from anywhere
+#[derive(Default)]
+pub struct PipelineMetrics {
+    state: Mutex<MetricsState>,
+}
+
+impl PipelineMetrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record a frame acquired by the camera, as it arrives from the source (before
+    /// the image queue, so that frames the queue drops are still counted)
+    ///
+    /// # Arguments
+    /// * `center_of_integration` - Time at the center of the frame's integration period
+    ///
+    pub fn record_acquired(&self, center_of_integration: DateTime<Utc>) {
+        self.state
+            .lock()
+            .unwrap()
+            .acquisition
+            .record(center_of_integration);
+    }
+
+    /// Record a frame that has finished processing
+    pub fn record_processed(&self) {
+        self.state.lock().unwrap().processing.record(Utc::now());
+    }
+
+    /// Record a frame shown on the display
+    ///
+    /// # Arguments
+    /// * `center_of_integration` - Time at the center of the frame's integration period,
+    ///   used to compute the end-to-end latency
+    ///
+    pub fn record_displayed(&self, center_of_integration: DateTime<Utc>) {
+        let now = Utc::now();
+        let mut state = self.state.lock().unwrap();
+        state.display.record(now);
+        if let Some(us) = (now - center_of_integration).num_microseconds() {
+            state.latency.record(us as f64 * 1.0e-6);
+        }
+    }
+
+    /// Current rolling averages of the pipeline rates
+    pub fn rates(&self) -> PipelineRates {
+        let state = self.state.lock().unwrap();
+        PipelineRates {
+            acquisition: state.acquisition.rate(),
+            processing: state.processing.rate(),
+            display: state.display.rate(),
+            latency: state.latency.mean(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_relative_eq;
+
+    #[test]
+    fn test_rate_meter() {
+        let mut meter = RateMeter::default();
+        assert!(meter.rate().is_none());
+        let t0 = Utc::now();
+        meter.record(t0);
+        assert!(meter.rate().is_none());
+        for i in 1..100 {
+            meter.record(t0 + chrono::Duration::milliseconds(i * 40));
+        }
+        assert_relative_eq!(meter.rate().unwrap(), 25.0, max_relative = 1.0e-9);
+    }
+
+    #[test]
+    fn test_latency() {
+        let metrics = PipelineMetrics::new();
+        assert_eq!(metrics.rates(), PipelineRates::default());
+        let acquired = Utc::now() - chrono::Duration::milliseconds(100);
+        metrics.record_acquired(acquired);
+        metrics.record_displayed(acquired);
+        let latency = metrics.rates().latency.unwrap();
+        assert!((0.1..0.5).contains(&latency));
+    }
+}
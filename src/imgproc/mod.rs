@@ -2,10 +2,35 @@
 //! Image Processing Chain
 //!
 
+mod background;
+mod badpixels;
+mod calibration;
+mod histogram;
 mod imgqueue;
+mod metrics;
 mod processor;
 mod procresult;
+mod profile;
+mod psf;
+mod scaling;
+mod sources;
 
+pub use background::BackgroundConfig;
+pub use calibration::Calibration;
+pub use calibration::MasterKind;
+pub use histogram::HistogramBinning;
+pub use histogram::HistogramConfig;
 pub use imgqueue::ImageQueue;
+pub use imgqueue::QueuePolicy;
+pub use metrics::PipelineMetrics;
+pub use metrics::PipelineRates;
 pub use processor::ImageProcessor;
 pub use procresult::ProcResult;
+pub use profile::Profile;
+pub use profile::ProfileLine;
+pub use psf::PsfConfig;
+pub use psf::PsfFit;
+pub use psf::PsfModel;
+pub use scaling::ScaleMode;
+pub use sources::DetectionConfig;
+pub use sources::Source;
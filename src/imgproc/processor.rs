@@ -1,7 +1,16 @@
 use crate::CameraFrame;
 
+use super::background::Background;
+use super::calibration::Calibration;
+use super::histogram::Histogram;
+use super::metrics::PipelineMetrics;
 use super::procresult::ProcResult;
+use super::profile::Profile;
+use super::psf::PsfFit;
+use super::sources::Source;
+use crate::cameraframe::saturating_cast;
 use crate::cameraframe::MonoPixel;
+use crate::cameraframe::SigmaClip;
 use std::sync::{Arc, Mutex, RwLock};
 
 use crate::gui::GuiParams;
@@ -13,6 +22,8 @@ where
     params: Option<Arc<RwLock<GuiParams>>>,
     sink: Option<Box<dyn Fn(ProcResult<T>) + 'static + Send>>,
     lastresult: Option<ProcResult<T>>,
+    metrics: Arc<PipelineMetrics>,
+    calibration: Arc<RwLock<Calibration>>,
 }
 
 impl<T> ImageProcessor<T>
@@ -24,6 +35,8 @@ where
             params: None,
             sink: None,
             lastresult: None,
+            metrics: Arc::new(PipelineMetrics::new()),
+            calibration: Arc::new(RwLock::new(Calibration::new())),
         }))
     }
 
@@ -31,27 +44,32 @@ where
         self.params = Some(params);
     }
 
+    /// Set the sink that is run on each processed result, replacing any existing sink
     pub fn set_sink(&mut self, sink: impl Fn(ProcResult<T>) + 'static + Send) {
         self.sink = Some(Box::new(sink));
     }
 
-    fn compute_histogram(frame: &CameraFrame<T>) -> (Vec<i32>, Vec<i32>) {
-        let min = frame.data.minval();
-        let max = frame.data.maxval();
-        let histmin = f64::powf(2.0, f64::log2(min.to_f64().unwrap()).floor());
-        let histmax = f64::powf(2.0, f64::log2(max.to_f64().unwrap()).ceil());
-        let nbins = 256;
-        let histdelta: f64 = (histmax - histmin) / nbins as f64;
-        let bins = (0..nbins)
-            .map(|i| (histmin + i as f64 * histdelta) as i32)
-            .collect::<Vec<i32>>();
-        let mut hist = vec![0; nbins];
-
-        frame.data.data.iter().for_each(|&x| {
-            let bin = ((x.to_f64().unwrap() - histmin) / histdelta).floor() as usize;
-            hist[bin] += 1;
-        });
-        (bins, hist)
+    /// Frame rate and latency measurements for frames passing through this processor
+    ///
+    /// Downstream stages (e.g. the display) record their own events on the returned handle
+    pub fn metrics(&self) -> Arc<PipelineMetrics> {
+        self.metrics.clone()
+    }
+
+    /// Master calibration frames applied to frames when calibration is enabled
+    ///
+    /// Masters can be loaded or captured through the returned handle at any time
+    pub fn calibration(&self) -> Arc<RwLock<Calibration>> {
+        self.calibration.clone()
+    }
+
+    /// Remove the sink so that processed results are no longer delivered
+    ///
+    /// Used when tearing down the pipeline, after which the sink (e.g. the GUI)
+    /// may no longer exist
+    pub fn clear_sink(&mut self) {
+        self.sink = None;
+        self.lastresult = None;
     }
 
     ///
@@ -66,27 +84,108 @@ where
             None => GuiParams::default(),
         };
 
+        // Raw frames are captured for new masters, and calibrated if requested
+        let calframe = {
+            if self.calibration.read().unwrap().capture_status().is_some() {
+                self.calibration.write().unwrap().add_frame(&frame);
+            }
+            match params.calibrate {
+                true => self.calibration.read().unwrap().apply(&frame),
+                false => None,
+            }
+        };
+        // The background is estimated after calibration, and subtracted if requested,
+        // keeping the median background level so the frame stays in range
+        let background = params.background.map(|config| {
+            let shown = calframe.as_ref().unwrap_or(&frame);
+            Background::estimate(&shown.data, &config)
+        });
+        let bgsubframe = match (&background, params.subtract_background) {
+            (Some(bg), true) => {
+                let shown = calframe.as_ref().unwrap_or(&frame);
+                Some(CameraFrame::create(
+                    shown.exposure,
+                    shown.center_of_integration,
+                    shown.bit_depth,
+                    bg.subtract(&shown.data, bg.stats.level),
+                ))
+            }
+            _ => None,
+        };
+        let shown = bgsubframe.as_ref().or(calframe.as_ref()).unwrap_or(&frame);
+
         let cmap = crate::colormap::from_string(params.colorscale.as_str())
             .unwrap_or(crate::colormap::grayscale());
 
-        let (minscale, maxscale) = match params.auto_scale {
-            true => (frame.data.minval(), frame.data.maxval()),
-            false => (
-                T::from(params.scale_range.0).unwrap(),
-                T::from(params.scale_range.1).unwrap(),
-            ),
-        };
+        let (minscale, maxscale) = params.scale_mode.limits(&shown.data, params.scale_range);
 
-        let rgbaframe = frame.data.to_rgba(minscale, maxscale, params.gamma, cmap);
+        let rgbaframe = shown.data.to_rgba(minscale, maxscale, params.gamma, cmap);
 
-        let histogram = Self::compute_histogram(&frame);
+        let histogram = Histogram::compute(&shown.data, &params.histogram);
+
+        // Statistics over the full frame, and over the region of interest if one is selected
+        let stats = shown.data.stats();
+        let roi = params
+            .roi
+            .and_then(|r| r.clip(shown.data.width, shown.data.height));
+        let roistats = roi
+            .and_then(|r| shown.data.roi(&r))
+            .map(|region| region.stats());
+        // Robust statistics, if requested, ignore the pixels of the bad pixel map
+        let (robust, roirobust) = match params.robust_stats {
+            true => {
+                let clip = SigmaClip::default();
+                let calibration = self.calibration.read().unwrap();
+                let mask = calibration
+                    .badpixels()
+                    .map(|map| map.mask())
+                    .filter(|m| m.width == shown.data.width && m.height == shown.data.height);
+                (
+                    shown.data.robust_stats(&clip, mask),
+                    roi.and_then(|r| {
+                        let mask = mask.and_then(|m| m.roi(&r));
+                        shown.data.roi(&r)?.robust_stats(&clip, mask.as_ref())
+                    }),
+                )
+            }
+            false => (None, None),
+        };
+        let profile = params
+            .profile
+            .map(|line| Profile::compute(&shown.data, &line));
+        // Sources are found in the frame the background was estimated from, reusing it;
+        // subtracting it again gives the same residuals as the subtracted frame
+        let sources = params.detection.map_or_else(Vec::new, |config| {
+            let unsubtracted = calframe.as_ref().unwrap_or(&frame);
+            Source::detect(&unsubtracted.data, &config, background.as_ref())
+        });
+        // Point spread function of the selected star, or else of the region of interest
+        let psf = params.psf.and_then(|config| {
+            let region = match params.psf_star {
+                Some((x, y)) => PsfFit::star_region(&shown.data, x, y),
+                None => roi,
+            };
+            region.and_then(|r| PsfFit::fit(&shown.data, &r, &config))
+        });
 
         let result = ProcResult {
             rawframe: frame,
+            calframe,
+            bgsubframe,
             displayimage: rgbaframe,
             histogram,
-            fcrange: (minscale.to_i32().unwrap(), maxscale.to_i32().unwrap()),
+            fcrange: (saturating_cast(minscale), saturating_cast(maxscale)),
+            stats,
+            roi,
+            roistats,
+            robust,
+            roirobust,
+            profile,
+            sources,
+            psf,
+            background: background.map(|bg| bg.stats),
         };
+        self.metrics.record_processed();
         if let Some(cb) = &self.sink {
             self.lastresult = Some(result.clone());
             cb(result);
@@ -1,16 +1,32 @@
 use crate::cameraframe::FrameData;
+use crate::cameraframe::FrameStats;
 use crate::cameraframe::MonoPixel;
 use crate::cameraframe::RGBAPixel;
+use crate::cameraframe::RobustStats;
+use crate::cameraframe::Roi;
 use crate::CameraFrame;
 
+use super::background::BackgroundStats;
+use super::histogram::Histogram;
+use super::profile::Profile;
+use super::psf::PsfFit;
+use super::sources::Source;
+
 ///
 /// Output of image processing chain
 ///
 /// # Contains
-/// * raw image,
+/// * raw image, the calibrated image if enabled, and the background-subtracted
+///   image if enabled
 /// * Image with contigious memory to be displayed in color format
 /// * False color range ued in the display
 /// * Histogram of the image
+/// * Statistics of the full frame and of the region of interest, and robust
+///   statistics of both if enabled
+/// * Line profile, if a line is drawn
+/// * Sources detected in the image, if detection is enabled
+/// * Point spread function fit to the selected star or region, if enabled
+/// * Background level and noise, if background estimation is enabled
 ///
 #[derive(Clone)]
 pub struct ProcResult<T>
@@ -18,7 +34,33 @@ where
     T: MonoPixel,
 {
     pub rawframe: CameraFrame<T>,
+    pub calframe: Option<CameraFrame<T>>,
+    /// The calibrated (or else raw) image with its background subtracted
+    pub bgsubframe: Option<CameraFrame<T>>,
     pub displayimage: FrameData<RGBAPixel>,
-    pub histogram: (Vec<i32>, Vec<i32>),
+    pub histogram: Histogram,
     pub fcrange: (i32, i32),
+    pub stats: FrameStats<T>,
+    pub roi: Option<Roi>,
+    pub roistats: Option<FrameStats<T>>,
+    pub robust: Option<RobustStats>,
+    pub roirobust: Option<RobustStats>,
+    pub profile: Option<Profile>,
+    pub sources: Vec<Source>,
+    pub psf: Option<PsfFit>,
+    pub background: Option<BackgroundStats>,
+}
+
+impl<T> ProcResult<T>
+where
+    T: MonoPixel,
+{
+    /// The frame that was displayed: background-subtracted if available, else
+    /// calibrated if available, else raw
+    pub fn frame(&self) -> &CameraFrame<T> {
+        self.bgsubframe
+            .as_ref()
+            .or(self.calframe.as_ref())
+            .unwrap_or(&self.rawframe)
+    }
 }
@@ -0,0 +1,206 @@
+//!
+//! Pixel values along a line across the frame
+//!
+//! Frame coordinates are continuous, with pixel (i, j) covering [i, i + 1) x [j, j + 1)
+//! so that its center is at (i + 0.5, j + 0.5).  Values between pixel centers are
+//! interpolated bilinearly, so lines may be drawn at any angle.
+//!
+
+use crate::cameraframe::FrameData;
+use crate::cameraframe::MonoPixel;
+
+use std::error::Error;
+use std::io::Write;
+
+/// A line across the frame, in frame coordinates
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ProfileLine {
+    pub x0: f64,
+    pub y0: f64,
+    pub x1: f64,
+    pub y1: f64,
+    /// Number of pixels averaged across the line
+    pub width: u32,
+}
+
+impl ProfileLine {
+    /// A line through the centers of the pixels of a row
+    pub fn row(y: u32, frame_width: u32, width: u32) -> Self {
+        let y = y as f64 + 0.5;
+        Self {
+            x0: 0.5,
+            y0: y,
+            x1: frame_width as f64 - 0.5,
+            y1: y,
+            width,
+        }
+    }
+
+    /// A line through the centers of the pixels of a column
+    pub fn column(x: u32, frame_height: u32, width: u32) -> Self {
+        let x = x as f64 + 0.5;
+        Self {
+            x0: x,
+            y0: 0.5,
+            x1: x,
+            y1: frame_height as f64 - 0.5,
+            width,
+        }
+    }
+
+    pub fn length(&self) -> f64 {
+        (self.x1 - self.x0).hypot(self.y1 - self.y0)
+    }
+}
+
+/// Value sampled along a profile line
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ProfileSample {
+    /// Distance from the start of the line, in pixels
+    pub distance: f64,
+    pub x: f64,
+    pub y: f64,
+    /// Value, averaged across the width of the line
+    pub value: f64,
+}
+
+/// Pixel values along a line
+#[derive(Clone, Debug, PartialEq)]
+pub struct Profile {
+    pub line: ProfileLine,
+    pub samples: Vec<ProfileSample>,
+}
+
+impl Profile {
+    /// Sample a frame every pixel along a line
+    ///
+    /// Each sample averages `line.width` values, a pixel apart, perpendicular
+    /// to the line.  Values outside the frame are left out of the average, and
+    /// samples with none inside the frame are left out of the profile.
+    pub fn compute<T: MonoPixel>(data: &FrameData<T>, line: &ProfileLine) -> Profile {
+        let length = line.length();
+        let nsamples = length.floor() as usize + 1;
+        // Unit vectors along and across the line
+        let (ux, uy) = match length > 0.0 {
+            true => ((line.x1 - line.x0) / length, (line.y1 - line.y0) / length),
+            false => (1.0, 0.0),
+        };
+        let (nx, ny) = (-uy, ux);
+        let width = line.width.max(1);
+
+        let samples = (0..nsamples)
+            .filter_map(|i| {
+                let distance = i as f64;
+                let x = line.x0 + ux * distance;
+                let y = line.y0 + uy * distance;
+                let values = (0..width)
+                    .filter_map(|j| {
+                        let offset = j as f64 - (width - 1) as f64 / 2.0;
+                        interpolate(data, x + nx * offset, y + ny * offset)
+                    })
+                    .collect::<Vec<f64>>();
+                match values.is_empty() {
+                    true => None,
+                    false => Some(ProfileSample {
+                        distance,
+                        x,
+                        y,
+                        value: values.iter().sum::<f64>() / values.len() as f64,
+                    }),
+                }
+            })
+            .collect();
+        Profile {
+            line: *line,
+            samples,
+        }
+    }
+
+    /// Save the profile as CSV, with a header line
+    pub fn save_csv(&self, filename: &str) -> Result<(), Box<dyn Error>> {
+        let mut writer = std::io::BufWriter::new(std::fs::File::create(filename)?);
+        writeln!(writer, "distance,x,y,value")?;
+        for s in self.samples.iter() {
+            writeln!(writer, "{},{},{},{}", s.distance, s.x, s.y, s.value)?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Bilinear interpolation between pixel centers, or None outside the frame
+fn interpolate<T: MonoPixel>(data: &FrameData<T>, x: f64, y: f64) -> Option<f64> {
+    if x < 0.0 || y < 0.0 || x >= data.width as f64 || y >= data.height as f64 {
+        return None;
+    }
+    // Pixels beyond the edge take the value of the edge
+    let fx = (x - 0.5).clamp(0.0, (data.width - 1) as f64);
+    let fy = (y - 0.5).clamp(0.0, (data.height - 1) as f64);
+    let (ix, iy) = (fx.floor() as u32, fy.floor() as u32);
+    let (ix1, iy1) = ((ix + 1).min(data.width - 1), (iy + 1).min(data.height - 1));
+    let (dx, dy) = (fx - ix as f64, fy - iy as f64);
+    let v = |x: u32, y: u32| data.at(x, y).to_f64().unwrap();
+    Some(
+        v(ix, iy) * (1.0 - dx) * (1.0 - dy)
+            + v(ix1, iy) * dx * (1.0 - dy)
+            + v(ix, iy1) * (1.0 - dx) * dy
+            + v(ix1, iy1) * dx * dy,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ramp() -> FrameData<u16> {
+        // Value 10 * x + 100 * y
+        FrameData {
+            width: 8,
+            height: 6,
+            data: (0..48).map(|i| (i % 8) * 10 + (i / 8) * 100).collect(),
+        }
+    }
+
+    #[test]
+    fn test_row_column() {
+        let data = ramp();
+        let row = Profile::compute(&data, &ProfileLine::row(2, 8, 1));
+        assert_eq!(
+            row.samples.iter().map(|s| s.value).collect::<Vec<_>>(),
+            (0..8).map(|x| 200.0 + 10.0 * x as f64).collect::<Vec<_>>()
+        );
+        // Averaging rows 1 to 3
+        let row = Profile::compute(&data, &ProfileLine::row(2, 8, 3));
+        assert_eq!(row.samples[0].value, 200.0);
+        let column = Profile::compute(&data, &ProfileLine::column(3, 6, 1));
+        assert_eq!(column.samples.len(), 6);
+        assert_eq!(column.samples[5].value, 530.0);
+    }
+
+    #[test]
+    fn test_diagonal() {
+        let data = ramp();
+        let line = ProfileLine {
+            x0: 1.5,
+            y0: 1.5,
+            x1: 4.5,
+            y1: 5.5,
+            width: 1,
+        };
+        let profile = Profile::compute(&data, &line);
+        // Length 5, sampled every pixel
+        assert_eq!(profile.samples.len(), 6);
+        for s in profile.samples.iter() {
+            let expected = 10.0 * (s.x - 0.5) + 100.0 * (s.y - 0.5);
+            assert!((s.value - expected).abs() < 1e-9);
+        }
+
+        let filename = std::env::temp_dir().join("viewer_test_profile.csv");
+        let filename = filename.to_str().unwrap();
+        profile.save_csv(filename).unwrap();
+        let text = std::fs::read_to_string(filename).unwrap();
+        assert_eq!(text.lines().count(), 7);
+        assert_eq!(text.lines().nth(1), Some("0,1.5,1.5,110"));
+        let _ = std::fs::remove_file(filename);
+    }
+}
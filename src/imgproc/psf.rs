@@ -0,0 +1,516 @@
+//!
+//! Fitting a model of the point spread function to a star
+//!
+//! An elliptical Gaussian or Moffat profile on a flat background is fit to the
+//! pixels of a region by Levenberg-Marquardt least squares.  The profile is
+//! elliptical: its widths are along axes rotated by an angle from the frame axes.
+//!
+//! Positions are in frame coordinates, with pixel (i, j) centered on (i + 0.5, j + 0.5),
+//! and angles are measured from the x axis towards the y axis.
+//!
+
+use crate::cameraframe::median;
+use crate::cameraframe::FrameData;
+use crate::cameraframe::MonoPixel;
+use crate::cameraframe::Roi;
+
+/// Regions larger than this (in either direction) are reduced to a box of this
+/// size around their brightest pixel
+const MAX_FIT_SIZE: u32 = 48;
+/// Size of the box fit around a selected star
+const STAR_BOX: u32 = 25;
+/// A selected star is the brightest pixel within this many pixels of the selection
+const STAR_SEARCH: u32 = 5;
+const MAX_ITERATIONS: usize = 100;
+
+// Indices of the model parameters
+const BACKGROUND: usize = 0;
+const AMPLITUDE: usize = 1;
+const X: usize = 2;
+const Y: usize = 3;
+const WIDTH_U: usize = 4;
+const WIDTH_V: usize = 5;
+const ANGLE: usize = 6;
+const BETA: usize = 7;
+
+/// Shape of the point spread function
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PsfModel {
+    /// A exp(-r^2 / 2), with r in units of the standard deviations along each axis
+    Gaussian,
+    /// A (1 + r^2)^-beta, with r in units of the core widths along each axis
+    Moffat,
+}
+
+impl PsfModel {
+    /// Model from its name ("Gaussian" or "Moffat", case-insensitive)
+    pub fn from_string(name: &str) -> Option<PsfModel> {
+        match name.to_lowercase().as_str() {
+            "gaussian" => Some(PsfModel::Gaussian),
+            "moffat" => Some(PsfModel::Moffat),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            PsfModel::Gaussian => "Gaussian",
+            PsfModel::Moffat => "Moffat",
+        }
+    }
+
+    fn nparams(&self) -> usize {
+        match self {
+            PsfModel::Gaussian => 7,
+            PsfModel::Moffat => 8,
+        }
+    }
+
+    /// Value of the model with parameters `p` at (x, y)
+    fn value(&self, p: &[f64], x: f64, y: f64) -> f64 {
+        let (dx, dy) = (x - p[X], y - p[Y]);
+        let (sin, cos) = p[ANGLE].sin_cos();
+        let u = (dx * cos + dy * sin) / p[WIDTH_U];
+        let v = (dy * cos - dx * sin) / p[WIDTH_V];
+        let r2 = u * u + v * v;
+        p[BACKGROUND]
+            + p[AMPLITUDE]
+                * match self {
+                    PsfModel::Gaussian => (-r2 / 2.0).exp(),
+                    PsfModel::Moffat => (1.0 + r2).powf(-p[BETA]),
+                }
+    }
+
+    /// Value of r^2 at which the profile falls to half its peak
+    fn half_maximum_r2(&self, p: &[f64]) -> f64 {
+        match self {
+            PsfModel::Gaussian => 2.0 * std::f64::consts::LN_2,
+            PsfModel::Moffat => 2f64.powf(1.0 / p[BETA]) - 1.0,
+        }
+    }
+}
+
+/// How the point spread function is fit
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PsfConfig {
+    pub model: PsfModel,
+    /// Diffraction limit of the optics (wavelength / aperture diameter), in
+    /// pixels, from which the Strehl ratio is estimated
+    pub lambda_over_d: Option<f64>,
+}
+
+impl Default for PsfConfig {
+    fn default() -> Self {
+        Self {
+            model: PsfModel::Gaussian,
+            lambda_over_d: None,
+        }
+    }
+}
+
+/// Point spread function fit to a star
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PsfFit {
+    pub model: PsfModel,
+    /// Region of the frame that was fit
+    pub region: Roi,
+    /// Center of the star
+    pub x: f64,
+    pub y: f64,
+    /// Peak value above the background
+    pub peak: f64,
+    pub background: f64,
+    /// Integral of the profile above the background
+    pub flux: f64,
+    /// Full widths at half maximum through the center, along the frame axes
+    pub fwhm_x: f64,
+    pub fwhm_y: f64,
+    /// Full widths at half maximum along the major and minor axes
+    pub fwhm_major: f64,
+    pub fwhm_minor: f64,
+    /// 1 - minor / major
+    pub ellipticity: f64,
+    /// Angle of the major axis, in degrees in (-90, 90]
+    pub angle: f64,
+    /// Moffat power
+    pub beta: Option<f64>,
+    /// Root mean square of the fit residuals
+    pub residual_rms: f64,
+    /// Ratio of the peak to that of a diffraction-limited star of the same flux
+    pub strehl: Option<f64>,
+}
+
+impl PsfFit {
+    /// Geometric mean of the full widths at half maximum along the major and minor axes
+    pub fn fwhm(&self) -> f64 {
+        (self.fwhm_major * self.fwhm_minor).sqrt()
+    }
+
+    /// Region to fit around a star selected at (x, y), in frame coordinates
+    ///
+    /// The region is centered on the brightest pixel near the selection, so the
+    /// star need not be selected precisely
+    ///
+    pub fn star_region<T: MonoPixel>(data: &FrameData<T>, x: f64, y: f64) -> Option<Roi> {
+        if x < 0.0 || y < 0.0 {
+            return None;
+        }
+        let search = Roi {
+            x: (x as u32).saturating_sub(STAR_SEARCH),
+            y: (y as u32).saturating_sub(STAR_SEARCH),
+            width: 2 * STAR_SEARCH + 1,
+            height: 2 * STAR_SEARCH + 1,
+        };
+        box_around_peak(data, &search, STAR_BOX)
+    }
+
+    /// Fit the point spread function to the pixels of a region
+    ///
+    /// # Arguments
+    /// * `data` - The frame
+    /// * `region` - Region containing the star; if larger than `MAX_FIT_SIZE`,
+    ///   a box of that size around its brightest pixel is fit instead
+    /// * `config` - The model, and the diffraction limit
+    ///
+    /// # Returns
+    /// The fit, or None if the region is outside the frame, or the fit fails or
+    /// does not find a star in the region
+    ///
+    pub fn fit<T: MonoPixel>(
+        data: &FrameData<T>,
+        region: &Roi,
+        config: &PsfConfig,
+    ) -> Option<PsfFit> {
+        let region = region.clip(data.width, data.height)?;
+        let region = match region.width > MAX_FIT_SIZE || region.height > MAX_FIT_SIZE {
+            true => box_around_peak(data, &region, MAX_FIT_SIZE)?,
+            false => region,
+        };
+        let sub = data.roi(&region)?;
+        let model = config.model;
+        // Pixel centers and values
+        let points = (0..sub.data.len())
+            .map(|i| {
+                let (px, py) = (i as u32 % sub.width, i as u32 / sub.width);
+                (
+                    (region.x + px) as f64 + 0.5,
+                    (region.y + py) as f64 + 0.5,
+                    sub.data[i].to_f64().unwrap(),
+                )
+            })
+            .collect::<Vec<_>>();
+        if points.len() <= model.nparams() {
+            return None;
+        }
+
+        let p0 = initial_guess(&sub, &region, model)?;
+        let residuals = |p: &[f64]| {
+            points
+                .iter()
+                .map(|(x, y, v)| model.value(p, *x, *y) - v)
+                .collect::<Vec<f64>>()
+        };
+        let p = levenberg_marquardt(&residuals, p0);
+
+        let (wu, wv) = (p[WIDTH_U].abs(), p[WIDTH_V].abs());
+        let inside =
+            |c: f64, start: u32, size: u32| c >= start as f64 && c <= (start + size) as f64;
+        let valid = p.iter().all(|v| v.is_finite())
+            && p[AMPLITUDE] > 0.0
+            && wu > 0.0
+            && wv > 0.0
+            && inside(p[X], region.x, region.width)
+            && inside(p[Y], region.y, region.height)
+            && (model == PsfModel::Gaussian || p[BETA] > 1.0);
+        if !valid {
+            return None;
+        }
+
+        // Widths through the center along a direction, from the profile's quadratic form
+        let half = 2.0 * model.half_maximum_r2(&p).sqrt();
+        let (sin, cos) = p[ANGLE].sin_cos();
+        let fwhm_x = half / (cos * cos / (wu * wu) + sin * sin / (wv * wv)).sqrt();
+        let fwhm_y = half / (sin * sin / (wu * wu) + cos * cos / (wv * wv)).sqrt();
+        let (major, minor) = (half * wu.max(wv), half * wu.min(wv));
+        let angle = match wu >= wv {
+            true => p[ANGLE],
+            false => p[ANGLE] + std::f64::consts::FRAC_PI_2,
+        }
+        .rem_euclid(std::f64::consts::PI);
+        let angle = match angle > std::f64::consts::FRAC_PI_2 {
+            true => angle - std::f64::consts::PI,
+            false => angle,
+        };
+        let flux = match model {
+            PsfModel::Gaussian => 2.0 * std::f64::consts::PI * p[AMPLITUDE] * wu * wv,
+            PsfModel::Moffat => std::f64::consts::PI * p[AMPLITUDE] * wu * wv / (p[BETA] - 1.0),
+        };
+        let rms = (residuals(&p).iter().map(|r| r * r).sum::<f64>() / points.len() as f64).sqrt();
+        // The peak of an Airy pattern of unit flux is pi / 4 (D / lambda)^2
+        let strehl = config
+            .lambda_over_d
+            .filter(|l| *l > 0.0)
+            .map(|l| p[AMPLITUDE] / flux * 4.0 * l * l / std::f64::consts::PI);
+
+        Some(PsfFit {
+            model,
+            region,
+            x: p[X],
+            y: p[Y],
+            peak: p[AMPLITUDE],
+            background: p[BACKGROUND],
+            flux,
+            fwhm_x,
+            fwhm_y,
+            fwhm_major: major,
+            fwhm_minor: minor,
+            ellipticity: 1.0 - minor / major,
+            angle: angle.to_degrees(),
+            beta: p.get(BETA).copied(),
+            residual_rms: rms,
+            strehl,
+        })
+    }
+}
+
+/// Box of `size` pixels, clipped to the frame, centered on the brightest pixel of a region
+fn box_around_peak<T: MonoPixel>(data: &FrameData<T>, region: &Roi, size: u32) -> Option<Roi> {
+    let region = region.clip(data.width, data.height)?;
+    let (px, py) = (region.y..region.y + region.height)
+        .flat_map(|y| (region.x..region.x + region.width).map(move |x| (x, y)))
+        .max_by_key(|(x, y)| data.at(*x, *y))?;
+    Roi {
+        x: px.saturating_sub(size / 2),
+        y: py.saturating_sub(size / 2),
+        width: size,
+        height: size,
+    }
+    .clip(data.width, data.height)
+}
+
+/// Starting parameters: the background from the edges of the region, and a
+/// round profile at the brightest pixel as wide as the pixels above half of it
+fn initial_guess<T: MonoPixel>(
+    sub: &FrameData<T>,
+    region: &Roi,
+    model: PsfModel,
+) -> Option<Vec<f64>> {
+    let (w, h) = (sub.width, sub.height);
+    let value = |x: u32, y: u32| sub.at(x, y).to_f64().unwrap();
+    let mut edge = (0..w)
+        .flat_map(|x| [value(x, 0), value(x, h - 1)])
+        .chain((0..h).flat_map(|y| [value(0, y), value(w - 1, y)]))
+        .collect::<Vec<f64>>();
+    let background = median(&mut edge)?;
+
+    let (px, py) = (0..h)
+        .flat_map(|y| (0..w).map(move |x| (x, y)))
+        .max_by(|a, b| value(a.0, a.1).total_cmp(&value(b.0, b.1)))?;
+    let amplitude = value(px, py) - background;
+    if amplitude <= 0.0 {
+        return None;
+    }
+    let nhalf = sub
+        .data
+        .iter()
+        .filter(|v| v.to_f64().unwrap() - background > amplitude / 2.0)
+        .count();
+    let fwhm = 2.0 * (nhalf as f64 / std::f64::consts::PI).sqrt();
+
+    let mut p = vec![0.0; model.nparams()];
+    p[BACKGROUND] = background;
+    p[AMPLITUDE] = amplitude;
+    p[X] = (region.x + px) as f64 + 0.5;
+    p[Y] = (region.y + py) as f64 + 0.5;
+    let width = match model {
+        PsfModel::Gaussian => fwhm / (2.0 * (2.0 * std::f64::consts::LN_2).sqrt()),
+        PsfModel::Moffat => {
+            p[BETA] = 2.5;
+            fwhm / (2.0 * (2f64.powf(1.0 / p[BETA]) - 1.0).sqrt())
+        }
+    };
+    p[WIDTH_U] = width.max(0.5);
+    p[WIDTH_V] = width.max(0.5);
+    Some(p)
+}
+
+/// Minimize the sum of squared residuals, starting from `p`
+///
+/// The Jacobian is estimated by forward differences
+fn levenberg_marquardt(residuals: &dyn Fn(&[f64]) -> Vec<f64>, mut p: Vec<f64>) -> Vec<f64> {
+    let m = p.len();
+    let mut r = residuals(&p);
+    let mut cost = r.iter().map(|v| v * v).sum::<f64>();
+    let mut lambda = 1.0e-3;
+    for _ in 0..MAX_ITERATIONS {
+        // Columns of the Jacobian
+        let jacobian = (0..m)
+            .map(|j| {
+                let step = 1.0e-6 * (1.0 + p[j].abs());
+                let mut q = p.clone();
+                q[j] += step;
+                residuals(&q)
+                    .iter()
+                    .zip(r.iter())
+                    .map(|(a, b)| (a - b) / step)
+                    .collect::<Vec<f64>>()
+            })
+            .collect::<Vec<_>>();
+        let dot = |a: &[f64], b: &[f64]| a.iter().zip(b.iter()).map(|(x, y)| x * y).sum::<f64>();
+        let jtj = (0..m)
+            .map(|i| {
+                (0..m)
+                    .map(|j| dot(&jacobian[i], &jacobian[j]))
+                    .collect::<Vec<f64>>()
+            })
+            .collect::<Vec<_>>();
+        let jtr = (0..m).map(|i| dot(&jacobian[i], &r)).collect::<Vec<f64>>();
+        // Parameters the residuals do not depend on (such as the angle of a round profile) are damped too
+        let floor = 1.0e-9 * (0..m).map(|i| jtj[i][i]).fold(0.0, f64::max);
+
+        let mut improved = false;
+        while lambda < 1.0e10 {
+            let mut a = jtj.clone();
+            (0..m).for_each(|i| a[i][i] += lambda * jtj[i][i].max(floor));
+            let b = jtr.iter().map(|v| -v).collect::<Vec<f64>>();
+            if let Some(delta) = solve(a, b) {
+                let q = p
+                    .iter()
+                    .zip(delta.iter())
+                    .map(|(a, b)| a + b)
+                    .collect::<Vec<f64>>();
+                let rq = residuals(&q);
+                let cq = rq.iter().map(|v| v * v).sum::<f64>();
+                if cq.is_finite() && cq < cost {
+                    let converged = (cost - cq) <= 1.0e-12 * cost;
+                    (p, r, cost) = (q, rq, cq);
+                    lambda = (lambda / 10.0).max(1.0e-12);
+                    improved = !converged;
+                    break;
+                }
+            }
+            lambda *= 10.0;
+        }
+        if !improved {
+            break;
+        }
+    }
+    p
+}
+
+/// Solve a x = b by Gaussian elimination with partial pivoting, or None if a is singular
+fn solve(mut a: Vec<Vec<f64>>, mut b: Vec<f64>) -> Option<Vec<f64>> {
+    let n = b.len();
+    for col in 0..n {
+        let pivot = (col..n).max_by(|i, j| a[*i][col].abs().total_cmp(&a[*j][col].abs()))?;
+        if a[pivot][col] == 0.0 || !a[pivot][col].is_finite() {
+            return None;
+        }
+        a.swap(col, pivot);
+        b.swap(col, pivot);
+        let pivot_row = a[col].clone();
+        for row in col + 1..n {
+            let factor = a[row][col] / pivot_row[col];
+            a[row]
+                .iter_mut()
+                .zip(pivot_row.iter())
+                .skip(col)
+                .for_each(|(v, p)| *v -= factor * p);
+            b[row] -= factor * b[col];
+        }
+    }
+    let mut x = vec![0.0; n];
+    for row in (0..n).rev() {
+        let sum = (row + 1..n).map(|k| a[row][k] * x[k]).sum::<f64>();
+        x[row] = (b[row] - sum) / a[row][row];
+    }
+    Some(x)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Frame of noise around 100, with a star of parameters `p` added
+    fn star_frame(model: PsfModel, p: &[f64], size: u32) -> FrameData<u16> {
+        let mut frame = FrameData::<u16>::rand_norm(100.0, 2.0, size, size);
+        for i in 0..frame.data.len() {
+            let (x, y) = (
+                (i as u32 % size) as f64 + 0.5,
+                (i as u32 / size) as f64 + 0.5,
+            );
+            let star = model.value(p, x, y) - p[BACKGROUND];
+            frame.data[i] = (frame.data[i] as f64 + star).round() as u16;
+        }
+        frame
+    }
+
+    #[test]
+    fn test_gaussian() {
+        // Widths of 2 and 1.2 pixels, with the wider axis at 30 degrees
+        let p = [100.0, 1000.0, 15.3, 14.7, 2.0, 1.2, 30f64.to_radians()];
+        let frame = star_frame(PsfModel::Gaussian, &p, 31);
+        let config = PsfConfig {
+            model: PsfModel::Gaussian,
+            lambda_over_d: Some(2.0),
+        };
+        let region = Roi {
+            x: 0,
+            y: 0,
+            width: 31,
+            height: 31,
+        };
+        let fit = PsfFit::fit(&frame, &region, &config).unwrap();
+        let factor = 2.0 * (2.0 * std::f64::consts::LN_2).sqrt();
+        assert!((fit.x - 15.3).abs() < 0.05 && (fit.y - 14.7).abs() < 0.05);
+        assert!((fit.fwhm_major - 2.0 * factor).abs() < 0.05);
+        assert!((fit.fwhm_minor - 1.2 * factor).abs() < 0.05);
+        assert!((fit.ellipticity - 0.4).abs() < 0.02);
+        assert!((fit.angle - 30.0).abs() < 1.0);
+        // The noise is truncated to integers, lowering the background by half a count
+        assert!((fit.peak - 1000.0).abs() < 10.0 && (fit.background - 99.5).abs() < 0.5);
+        // Widths along the frame axes lie between the minor and major widths
+        assert!(fit.fwhm_x > fit.fwhm_y && fit.fwhm_x < fit.fwhm_major);
+        // Noise, and rounding to integers
+        assert!((fit.residual_rms - 2.0).abs() < 0.3);
+        // Peak over flux, relative to pi / 4 / (lambda / D)^2
+        let strehl = 2.0 * 2.0 * 2.0 / (std::f64::consts::PI.powi(2) * 2.0 * 1.2);
+        assert!((fit.strehl.unwrap() - strehl).abs() < 0.02);
+
+        // Selecting the star near its center
+        let region = PsfFit::star_region(&frame, 17.0, 12.0).unwrap();
+        assert_eq!(
+            (region.x, region.y, region.width, region.height),
+            (3, 2, 25, 25)
+        );
+        assert!(PsfFit::fit(&frame, &region, &config).is_some());
+    }
+
+    #[test]
+    fn test_moffat() {
+        let p = [100.0, 800.0, 20.5, 22.0, 3.0, 3.0, 0.0, 3.0];
+        let frame = star_frame(PsfModel::Moffat, &p, 64);
+        let config = PsfConfig {
+            model: PsfModel::Moffat,
+            lambda_over_d: None,
+        };
+        let region = Roi {
+            x: 0,
+            y: 0,
+            width: 64,
+            height: 64,
+        };
+        let fit = PsfFit::fit(&frame, &region, &config).unwrap();
+        // The region is reduced around the star
+        assert_eq!((fit.region.width, fit.region.height), (48, 48));
+        let fwhm = 2.0 * 3.0 * (2f64.powf(1.0 / 3.0) - 1.0).sqrt();
+        assert!((fit.fwhm() - fwhm).abs() < 0.1);
+        assert!((fit.beta.unwrap() - 3.0).abs() < 0.3);
+        assert!(fit.ellipticity < 0.05);
+        assert!(fit.strehl.is_none());
+
+        // No star to fit
+        let flat = FrameData::<u16>::zeros(20, 20);
+        assert!(PsfFit::fit(&flat, &region, &config).is_none());
+    }
+}
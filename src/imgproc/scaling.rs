@@ -0,0 +1,281 @@
+//!
+//! Choice of the pixel values mapped to the ends of the color scale
+//!
+
+use crate::cameraframe::percentiles;
+use crate::cameraframe::FrameData;
+use crate::cameraframe::MonoPixel;
+
+/// Pixels sampled to compute percentiles
+const PERCENTILE_SAMPLES: usize = 100_000;
+/// Pixels sampled by zscale
+const ZSCALE_SAMPLES: usize = 1000;
+/// Zscale contrast: the scale spans the slope of the sorted samples divided by this
+const ZSCALE_CONTRAST: f64 = 0.25;
+/// Zscale rejection threshold, in standard deviations of the residuals of the fit
+const ZSCALE_KREJ: f64 = 2.5;
+/// Zscale gives up fitting when fewer than this fraction of samples remain
+const ZSCALE_MAX_REJECT: f64 = 0.5;
+const ZSCALE_MIN_SAMPLES: usize = 5;
+const ZSCALE_ITERATIONS: usize = 5;
+
+/// How the color scale limits are chosen
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ScaleMode {
+    /// Limits set by the user
+    Manual,
+    /// Minimum and maximum pixel values
+    MinMax,
+    /// Percentiles of the pixel values, in percent
+    Percentile { low: f64, high: f64 },
+    /// The IRAF "zscale" algorithm, spanning the values near the median
+    ZScale,
+}
+
+impl ScaleMode {
+    /// Scale mode from its name: "Manual", "Min/Max", "ZScale", or a
+    /// percentile range such as "0.5-99.5%" (case-insensitive)
+    pub fn from_string(name: &str) -> Option<ScaleMode> {
+        match name.to_lowercase().as_str() {
+            "manual" => Some(ScaleMode::Manual),
+            "min/max" => Some(ScaleMode::MinMax),
+            "zscale" => Some(ScaleMode::ZScale),
+            s => {
+                let (low, high) = s.strip_suffix('%')?.split_once('-')?;
+                let (low, high) = (low.trim().parse().ok()?, high.trim().parse().ok()?);
+                match (0.0..high).contains(&low) && high <= 100.0 {
+                    true => Some(ScaleMode::Percentile { low, high }),
+                    false => None,
+                }
+            }
+        }
+    }
+
+    /// Color scale limits for a frame
+    ///
+    /// # Arguments
+    /// * `data` - The frame
+    /// * `manual` - The limits used in manual mode
+    ///
+    /// # Returns
+    /// The (minimum, maximum) limits, in the range of the pixel type and with
+    /// the maximum above the minimum
+    ///
+    pub fn limits<T: MonoPixel>(&self, data: &FrameData<T>, manual: (i32, i32)) -> (T, T) {
+        let (lo, hi) = match self {
+            ScaleMode::Manual => (manual.0 as f64, manual.1 as f64),
+            ScaleMode::MinMax => (
+                data.minval().to_f64().unwrap(),
+                data.maxval().to_f64().unwrap(),
+            ),
+            ScaleMode::Percentile { low, high } => {
+                let mut samples = sample(data, PERCENTILE_SAMPLES);
+                match percentiles(&mut samples, &[*low, *high]) {
+                    Some(limits) => (limits[0], limits[1]),
+                    None => (0.0, 0.0),
+                }
+            }
+            ScaleMode::ZScale => zscale(data),
+        };
+        let (tmin, tmax) = (
+            T::min_value().to_f64().unwrap(),
+            T::max_value().to_f64().unwrap(),
+        );
+        let lo = lo.round().clamp(tmin, tmax - 1.0);
+        let hi = hi.round().clamp(lo + 1.0, tmax);
+        (saturating_from(lo), saturating_from(hi))
+    }
+}
+
+/// A value converted to the pixel type, saturating at the ends of its range
+///
+/// The maximum of a 64-bit type rounds up to a float just out of its range
+fn saturating_from<T: MonoPixel>(v: f64) -> T {
+    T::from(v).unwrap_or(match v < 0.0 {
+        true => T::min_value(),
+        false => T::max_value(),
+    })
+}
+
+/// Up to `n` pixel values, evenly spread over the frame
+fn sample<T: MonoPixel>(data: &FrameData<T>, n: usize) -> Vec<f64> {
+    let stride = data.data.len().div_ceil(n).max(1);
+    data.data
+        .iter()
+        .step_by(stride)
+        .map(|x| x.to_f64().unwrap())
+        .collect()
+}
+
+/// Zscale limits: fit a line to the sorted samples, rejecting outliers, and
+/// span the fitted slope (divided by the contrast) around the median
+fn zscale<T: MonoPixel>(data: &FrameData<T>) -> (f64, f64) {
+    let mut samples = sample(data, ZSCALE_SAMPLES);
+    // An empty frame (or region) has nothing to fit
+    if samples.is_empty() {
+        return (
+            data.minval().to_f64().unwrap(),
+            data.maxval().to_f64().unwrap(),
+        );
+    }
+    // Finding the median sorts the samples, which the line is fit to in order
+    let median = percentiles(&mut samples, &[50.0]).map_or(0.0, |p| p[0]);
+    let npix = samples.len();
+    let (vmin, vmax) = (samples[0], samples[npix - 1]);
+    let center = (npix - 1) / 2;
+    let minpix = ZSCALE_MIN_SAMPLES.max((npix as f64 * ZSCALE_MAX_REJECT) as usize);
+    // Rejected samples are grown by this many neighbors
+    let ngrow = (npix / 100).max(1);
+
+    let mut good = vec![true; npix];
+    let mut ngood = npix;
+    let mut last_ngood = npix + 1;
+    let mut slope = 0.0;
+    for _ in 0..ZSCALE_ITERATIONS {
+        if ngood >= last_ngood || ngood < minpix {
+            break;
+        }
+        // Least-squares line through the good samples
+        let (mut sx, mut sy, mut sxx, mut sxy) = (0.0, 0.0, 0.0, 0.0);
+        for (i, y) in samples.iter().enumerate().filter(|(i, _)| good[*i]) {
+            let x = i as f64;
+            sx += x;
+            sy += y;
+            sxx += x * x;
+            sxy += x * y;
+        }
+        let n = ngood as f64;
+        let denom = n * sxx - sx * sx;
+        if denom == 0.0 {
+            break;
+        }
+        slope = (n * sxy - sx * sy) / denom;
+        let intercept = (sy - slope * sx) / n;
+
+        let residuals = samples
+            .iter()
+            .enumerate()
+            .map(|(i, y)| y - (intercept + slope * i as f64))
+            .collect::<Vec<f64>>();
+        let sd = (residuals
+            .iter()
+            .zip(good.iter())
+            .filter(|(_, g)| **g)
+            .map(|(r, _)| r * r)
+            .sum::<f64>()
+            / n)
+            .sqrt();
+        let threshold = ZSCALE_KREJ * sd;
+        let mut next = good.clone();
+        for (i, r) in residuals.iter().enumerate() {
+            if r.abs() > threshold {
+                let (a, b) = (i.saturating_sub(ngrow / 2), (i + ngrow / 2).min(npix - 1));
+                next[a..=b].iter_mut().for_each(|g| *g = false);
+            }
+        }
+        good = next;
+        last_ngood = ngood;
+        ngood = good.iter().filter(|g| **g).count();
+    }
+    if ngood < minpix {
+        return (vmin, vmax);
+    }
+    let slope = slope / ZSCALE_CONTRAST;
+    (
+        vmin.max(median - center as f64 * slope),
+        vmax.min(median + (npix - center) as f64 * slope),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cameraframe::saturating_cast;
+
+    #[test]
+    fn test_names() {
+        for name in ["Manual", "Min/Max", "0.5-99.5%", "1-99%", "ZScale"] {
+            assert!(ScaleMode::from_string(name).is_some());
+        }
+        assert_eq!(ScaleMode::from_string("min/max"), Some(ScaleMode::MinMax));
+        assert_eq!(
+            ScaleMode::from_string("0.5-99.5%"),
+            Some(ScaleMode::Percentile {
+                low: 0.5,
+                high: 99.5
+            })
+        );
+        assert!(ScaleMode::from_string("99-1%").is_none());
+        assert!(ScaleMode::from_string("auto").is_none());
+    }
+
+    #[test]
+    fn test_limits() {
+        // Background of 990 to 1010, with 1% of pixels saturated
+        let data = FrameData::<u16> {
+            width: 100,
+            height: 100,
+            data: (0..10000_usize)
+                .map(|i| match i % 100 {
+                    0 => 65535,
+                    _ => 990 + (i * 7919 % 21) as u16,
+                })
+                .collect(),
+        };
+        assert_eq!(ScaleMode::MinMax.limits(&data, (0, 0)), (990, 65535));
+        assert_eq!(ScaleMode::Manual.limits(&data, (100, 200)), (100, 200));
+        // Limits are kept in order and in range
+        assert_eq!(ScaleMode::Manual.limits(&data, (500, 100)), (500, 501));
+        assert_eq!(ScaleMode::Manual.limits(&data, (-5, 70000)), (0, 65535));
+
+        let (lo, hi) = ScaleMode::from_string("2-98%")
+            .unwrap()
+            .limits(&data, (0, 0));
+        assert!((990..=991).contains(&lo) && (1009..=1010).contains(&hi));
+        let (lo, hi) = ScaleMode::ZScale.limits(&data, (0, 0));
+        assert!((990..1000).contains(&lo) && (1001..1100).contains(&hi));
+    }
+
+    #[test]
+    fn test_limits_empty() {
+        let data = FrameData::<u16> {
+            width: 0,
+            height: 0,
+            data: vec![],
+        };
+        for name in ["Min/Max", "1-99%", "ZScale"] {
+            let (lo, hi) = ScaleMode::from_string(name).unwrap().limits(&data, (0, 0));
+            assert!(lo < hi);
+        }
+    }
+
+    #[test]
+    fn test_limits_64bit() {
+        // The ends of the 64-bit ranges are not exact as floats
+        let data = FrameData::<i64> {
+            width: 3,
+            height: 1,
+            data: vec![i64::MIN, 0, i64::MAX],
+        };
+        let (lo, hi) = ScaleMode::MinMax.limits(&data, (0, 0));
+        assert_eq!((lo, hi), (i64::MIN, i64::MAX));
+        // The full range maps onto the color map, and the GUI sees it clamped
+        let cmap = crate::colormap::grayscale();
+        let rgba = data.to_rgba(lo, hi, 1.0, cmap);
+        assert_eq!((rgba.data[0].r, rgba.data[2].r), (cmap[0].r, cmap[255].r));
+        assert_eq!(
+            (
+                saturating_cast::<i64, i32>(lo),
+                saturating_cast::<i64, i32>(hi)
+            ),
+            (i32::MIN, i32::MAX)
+        );
+        let data = FrameData::<u64> {
+            width: 3,
+            height: 1,
+            data: vec![0, 1000, u64::MAX],
+        };
+        assert_eq!(ScaleMode::MinMax.limits(&data, (0, 0)), (0, u64::MAX));
+        assert_eq!(ScaleMode::Manual.limits(&data, (5, 10)), (5, 10));
+    }
+}
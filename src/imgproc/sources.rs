@@ -0,0 +1,305 @@
+//!
+//! Detection and measurement of point sources, such as stars
+//!
+//! The background level and noise are estimated on a mesh of boxes tiling the
+//! frame (see `Background`).  Connected pixels (including diagonal neighbors) more
+//! than a threshold above their local background form a source, which is measured
+//! from its background-subtracted values.
+//!
+//! Positions are in frame coordinates, with pixel (i, j) centered on (i + 0.5, j + 0.5).
+//!
+
+use super::background::Background;
+use super::background::BackgroundConfig;
+use crate::cameraframe::FrameData;
+use crate::cameraframe::MonoPixel;
+
+/// Ratio of the full width at half maximum of a Gaussian to its standard deviation
+const FWHM_PER_SIGMA: f64 = 2.354_820_045;
+
+/// How sources are detected
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DetectionConfig {
+    /// Threshold above the local background, in standard deviations of its noise
+    pub nsigma: f64,
+    /// Width and height of the boxes of the background mesh
+    pub box_size: u32,
+    /// Sources with fewer pixels above the threshold are ignored
+    pub min_pixels: usize,
+    /// Only the brightest sources are kept
+    pub max_sources: usize,
+}
+
+impl Default for DetectionConfig {
+    fn default() -> Self {
+        Self {
+            nsigma: 5.0,
+            box_size: 64,
+            min_pixels: 3,
+            max_sources: 500,
+        }
+    }
+}
+
+/// A detected source
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Source {
+    /// Center of mass of the background-subtracted source pixels
+    pub x: f64,
+    pub y: f64,
+    /// Centers of Gaussians fit to the peaks of the row and column sums around the source
+    pub gauss_x: f64,
+    pub gauss_y: f64,
+    /// Sum of the background-subtracted source pixels
+    pub flux: f64,
+    /// Highest background-subtracted value
+    pub peak: f64,
+    /// Full width at half maximum, in pixels
+    pub fwhm: f64,
+    /// Number of pixels above the threshold
+    pub npixels: usize,
+    /// Local background level
+    pub background: f64,
+}
+
+impl Source {
+    /// Detect and measure the sources in a frame
+    ///
+    /// # Arguments
+    /// * `data` - The frame
+    /// * `config` - How sources are detected
+    /// * `background` - The background of the frame, if already estimated; otherwise
+    ///   (or if its size differs from the frame) it is estimated with `config.box_size`
+    ///
+    /// # Returns
+    /// The sources, brightest first
+    ///
+    pub fn detect<T: MonoPixel>(
+        data: &FrameData<T>,
+        config: &DetectionConfig,
+        background: Option<&Background>,
+    ) -> Vec<Source> {
+        if data.data.is_empty() {
+            return Vec::new();
+        }
+        let (width, height) = (data.width as usize, data.height as usize);
+        let estimated;
+        let background = match background
+            .filter(|bg| bg.level.width == data.width && bg.level.height == data.height)
+        {
+            Some(bg) => bg,
+            None => {
+                estimated = Background::estimate(
+                    data,
+                    &BackgroundConfig {
+                        box_size: config.box_size,
+                        ..Default::default()
+                    },
+                );
+                &estimated
+            }
+        };
+        let residual = data
+            .data
+            .iter()
+            .zip(background.level.data.iter())
+            .map(|(v, b)| v.to_f64().unwrap() - *b as f64)
+            .collect::<Vec<f64>>();
+        let above = residual
+            .iter()
+            .zip(background.noise.data.iter())
+            .map(|(r, n)| *r > config.nsigma * *n as f64)
+            .collect::<Vec<bool>>();
+
+        // Group the pixels above the threshold by flood filling from each in turn
+        let mut visited = vec![false; residual.len()];
+        let mut stack = Vec::new();
+        let mut sources = Vec::new();
+        for start in 0..residual.len() {
+            if !above[start] || visited[start] {
+                continue;
+            }
+            visited[start] = true;
+            stack.push(start);
+            let mut pixels = Vec::new();
+            while let Some(i) = stack.pop() {
+                pixels.push(i);
+                let (x, y) = (i % width, i / width);
+                for ny in y.saturating_sub(1)..=(y + 1).min(height - 1) {
+                    for nx in x.saturating_sub(1)..=(x + 1).min(width - 1) {
+                        let j = ny * width + nx;
+                        if above[j] && !visited[j] {
+                            visited[j] = true;
+                            stack.push(j);
+                        }
+                    }
+                }
+            }
+            if pixels.len() >= config.min_pixels {
+                let level = background.level.data[start] as f64;
+                sources.extend(measure(&pixels, &residual, width, height, level));
+            }
+        }
+        sources.sort_by(|a, b| b.flux.total_cmp(&a.flux));
+        sources.truncate(config.max_sources);
+        sources
+    }
+}
+
+/// Measure a source from the background-subtracted values of the frame
+///
+/// # Arguments
+/// * `pixels` - Indices of the source pixels
+/// * `residual` - Background-subtracted frame
+/// * `level` - Background level, reported with the source
+///
+/// # Returns
+/// The source, or None if its flux is not positive
+///
+fn measure(
+    pixels: &[usize],
+    residual: &[f64],
+    width: usize,
+    height: usize,
+    level: f64,
+) -> Option<Source> {
+    let flux = pixels.iter().map(|i| residual[*i]).sum::<f64>();
+    if flux <= 0.0 {
+        return None;
+    }
+    let (mut sx, mut sy, mut sxx, mut syy) = (0.0, 0.0, 0.0, 0.0);
+    let (mut x0, mut y0, mut x1, mut y1) = (width, height, 0, 0);
+    for i in pixels.iter() {
+        let (px, py) = (i % width, i / width);
+        let (x, y, v) = (px as f64 + 0.5, py as f64 + 0.5, residual[*i]);
+        sx += v * x;
+        sy += v * y;
+        sxx += v * x * x;
+        syy += v * y * y;
+        (x0, y0, x1, y1) = (x0.min(px), y0.min(py), x1.max(px), y1.max(py));
+    }
+    let (x, y) = (sx / flux, sy / flux);
+    let peak = pixels.iter().map(|i| residual[*i]).fold(f64::MIN, f64::max);
+
+    // Sums of the rows and columns through the source, a pixel beyond it on each side
+    let (x0, y0) = (x0.saturating_sub(1), y0.saturating_sub(1));
+    let (x1, y1) = ((x1 + 1).min(width - 1), (y1 + 1).min(height - 1));
+    let columns = (x0..=x1)
+        .map(|x| (y0..=y1).map(|y| residual[y * width + x]).sum())
+        .collect::<Vec<f64>>();
+    let rows = (y0..=y1)
+        .map(|y| (x0..=x1).map(|x| residual[y * width + x]).sum())
+        .collect::<Vec<f64>>();
+    let gx = fit_peak(&columns);
+    let gy = fit_peak(&rows);
+
+    // Width of the fitted Gaussians, or from the second moments if they could not be fit
+    let variance = match (gx, gy) {
+        (Some((_, vx)), Some((_, vy))) => (vx + vy) / 2.0,
+        _ => ((sxx / flux - x * x) + (syy / flux - y * y)) / 2.0,
+    };
+    Some(Source {
+        x,
+        y,
+        gauss_x: gx.map_or(x, |(c, _)| x0 as f64 + c),
+        gauss_y: gy.map_or(y, |(c, _)| y0 as f64 + c),
+        flux,
+        peak,
+        fwhm: FWHM_PER_SIGMA * variance.max(0.0).sqrt(),
+        npixels: pixels.len(),
+        background: level,
+    })
+}
+
+/// Fit a Gaussian through the highest value and its neighbors
+///
+/// # Returns
+/// The (center, variance) of the Gaussian, with the center in the same
+/// coordinates as pixel positions, or None if the peak is at either end or
+/// the values around it are not positive and peaked
+///
+fn fit_peak(values: &[f64]) -> Option<(f64, f64)> {
+    let k = values
+        .iter()
+        .enumerate()
+        .max_by(|a, b| a.1.total_cmp(b.1))?
+        .0;
+    if k == 0 || k + 1 >= values.len() {
+        return None;
+    }
+    let (a, b, c) = (values[k - 1], values[k], values[k + 1]);
+    if a <= 0.0 || b <= 0.0 || c <= 0.0 {
+        return None;
+    }
+    // The log of a Gaussian is a parabola
+    let (la, lb, lc) = (a.ln(), b.ln(), c.ln());
+    let curvature = la - 2.0 * lb + lc;
+    if curvature >= 0.0 {
+        return None;
+    }
+    let offset = (la - lc) / (2.0 * curvature);
+    Some((k as f64 + 0.5 + offset, -1.0 / curvature))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Add a Gaussian star to a frame
+    fn add_star(frame: &mut FrameData<u16>, x: f64, y: f64, sigma: f64, amplitude: f64) {
+        for py in 0..frame.height {
+            for px in 0..frame.width {
+                let (dx, dy) = (px as f64 + 0.5 - x, py as f64 + 0.5 - y);
+                let v = amplitude * (-(dx * dx + dy * dy) / (2.0 * sigma * sigma)).exp();
+                let i = (py * frame.width + px) as usize;
+                frame.data[i] = (frame.data[i] as f64 + v).round() as u16;
+            }
+        }
+    }
+
+    #[test]
+    fn test_detect() {
+        let mut frame = FrameData::<u16>::rand_norm(1000.0, 5.0, 128, 96);
+        add_star(&mut frame, 20.3, 30.7, 1.5, 2000.0);
+        add_star(&mut frame, 90.8, 60.2, 2.0, 500.0);
+        let sources = Source::detect(&frame, &DetectionConfig::default(), None);
+        assert_eq!(sources.len(), 2);
+
+        // Brightest first
+        let s = &sources[0];
+        assert!((s.x - 20.3).abs() < 0.1 && (s.y - 30.7).abs() < 0.1);
+        assert!((s.gauss_x - 20.3).abs() < 0.1 && (s.gauss_y - 30.7).abs() < 0.1);
+        assert!((s.fwhm - 1.5 * FWHM_PER_SIGMA).abs() < 0.3);
+        // Most of the flux of 2 pi sigma^2 A is above the threshold
+        let total = 2.0 * std::f64::consts::PI * 1.5 * 1.5 * 2000.0;
+        assert!(s.flux > 0.9 * total && s.flux < 1.02 * total);
+        assert!((s.peak - 2000.0).abs() < 400.0);
+        assert!((s.background - 1000.0).abs() < 2.0);
+
+        let s = &sources[1];
+        assert!((s.gauss_x - 90.8).abs() < 0.2 && (s.gauss_y - 60.2).abs() < 0.2);
+        assert!((s.fwhm - 2.0 * FWHM_PER_SIGMA).abs() < 0.5);
+
+        // A background estimated beforehand is used as it is
+        let background = Background::estimate(
+            &frame,
+            &BackgroundConfig {
+                box_size: 64,
+                ..Default::default()
+            },
+        );
+        let config = DetectionConfig::default();
+        assert_eq!(Source::detect(&frame, &config, Some(&background)), sources);
+    }
+
+    #[test]
+    fn test_no_sources() {
+        // Noise alone, and a uniform frame
+        let frame = FrameData::<u16>::rand_norm(1000.0, 5.0, 128, 128);
+        assert!(Source::detect(&frame, &DetectionConfig::default(), None).is_empty());
+        let frame = FrameData::<u16>::zeros(16, 16);
+        assert!(Source::detect(&frame, &DetectionConfig::default(), None).is_empty());
+        let frame = FrameData::<u16>::zeros(0, 0);
+        assert!(Source::detect(&frame, &DetectionConfig::default(), None).is_empty());
+    }
+}
@@ -2,17 +2,28 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
 mod cameraframe;
+mod camerasource;
 mod colormap;
 mod gui;
 mod imgproc;
+mod playbacksource;
+mod recording;
 mod simsource;
 
 pub use cameraframe::CameraFrame;
 use imgproc::ImageQueue;
-use simsource::SimSource;
 use std::error::Error;
+use std::sync::Arc;
 
 fn main() -> Result<(), Box<dyn Error>> {
+    // Camera source is selected by name on the command line (default: simulated)
+    let source_name = std::env::args().nth(1).unwrap_or("sim".to_string());
+    let mut source = camerasource::from_string(source_name.as_str()).ok_or(format!(
+        "Unknown camera source \"{}\"; available sources: {}",
+        source_name,
+        camerasource::SOURCE_NAMES.join(", ")
+    ))?;
+
     // Create a GUI
     let mut thegui = gui::Gui::new()?;
 
@@ -21,26 +32,53 @@ fn main() -> Result<(), Box<dyn Error>> {
     // set parameter structure for image processor
     imgproc.lock().unwrap().set_params(thegui.get_params());
     // Tell the chain to call the gui processor when it is complete
-    imgproc
-        .lock()
-        .unwrap()
-        .set_sink(thegui.processed_callback::<u16>());
+    let metrics = imgproc.lock().unwrap().metrics();
+    {
+        let mut p = imgproc.lock().unwrap();
+        p.set_sink(thegui.processed_callback::<u16>(metrics.clone()));
+        thegui.watch_calibration(p.calibration());
+    }
+
+    // Recorder writes raw frames to disk when started from the GUI
+    let recorder = recording::Recorder::new();
+    thegui.watch_recorder(recorder.clone());
 
     // Image queue: creates a separate thread to process frames
-    let imgqueue = ImageQueue::<u16>::new();
+    let imgqueue = Arc::new(ImageQueue::<u16>::new());
     // Process images whenever a frame arrives
     let pclone = imgproc.clone();
     // Start the image queue (creates a thread)
     imgqueue.start(move |frame: CameraFrame<u16>| {
         pclone.lock().unwrap().process_frame(frame);
     });
+    // Show queue counters in the GUI
+    thegui.watch_queue(imgqueue.clone());
+
+    // Playback sources can be paused, stepped and seeked from the GUI
+    if let Some(control) = source.playback_control() {
+        thegui.watch_playback(control);
+    }
+
+    // Name the source in the window title
+    thegui.show_source(&source.describe());
 
-    // Simulated camera source
-    let mut simsource = SimSource::new();
     // Dump frames into image queue when they are ready
-    simsource.start(move |frame: CameraFrame<u16>| imgqueue.on_frame_available(frame));
+    // Frames are counted as acquired, and recorded, before the queue, which may drop them
+    let qclone = imgqueue.clone();
+    let rclone = recorder.clone();
+    source.start(Box::new(move |frame: CameraFrame<u16>| {
+        metrics.record_acquired(frame.center_of_integration);
+        rclone.record(&frame);
+        qclone.on_frame_available(frame)
+    }))?;
 
     thegui.run()?;
 
+    // Window has been closed; tear down the pipeline from the source downstream
+    source.stop();
+    imgqueue.stop();
+    imgproc.lock().unwrap().clear_sink();
+    recorder.stop();
+
     Ok(())
 }
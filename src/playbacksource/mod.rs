@@ -0,0 +1,444 @@
+//!
+//! Playback of previously acquired frames as a camera source
+//!
+//! Frames are read from a recording file (see `recording`) or from a directory
+//! of FITS and PNG images, and emitted with the same spacing between their
+//! centers of integration as when they were acquired, optionally sped up or
+//! slowed down.  Playback can be paused, single-stepped, looped and seeked
+//! through a `PlaybackControl` handle.
+//!
+
+use crate::cameraframe::CameraFrame;
+use crate::cameraframe::FrameData;
+use crate::cameraframe::PngError;
+use crate::camerasource::CameraSource;
+use crate::camerasource::FrameCallback;
+use crate::recording::RecordingReader;
+
+use chrono::{DateTime, Utc};
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Longest time to wait between two frames, in seconds, regardless of their timestamps
+///
+/// Keeps playback responsive across gaps in a recording and for image files
+/// whose timestamps come from the file system
+const MAX_FRAME_DELAY: f64 = 5.0;
+
+/// File extensions recognized when playing back a directory of images
+const FITS_EXTENSIONS: [&str; 3] = ["fits", "fit", "fts"];
+const PNG_EXTENSIONS: [&str; 1] = ["png"];
+
+/// Frames available for playback, read on demand
+enum FrameList {
+    Recording {
+        reader: RecordingReader<u16>,
+        offsets: Vec<u64>,
+    },
+    Files(Vec<PathBuf>),
+}
+
+impl FrameList {
+    /// Open a recording file, or a directory of FITS and PNG images sorted by name
+    fn open(path: &str) -> Result<Self, Box<dyn Error>> {
+        if !Path::new(path).is_dir() {
+            let mut reader = RecordingReader::<u16>::open(path)?;
+            let offsets = reader.index()?;
+            return Ok(FrameList::Recording { reader, offsets });
+        }
+        let mut files = std::fs::read_dir(path)?
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|p| {
+                p.extension()
+                    .and_then(|e| e.to_str())
+                    .map(|e| e.to_lowercase())
+                    .is_some_and(|e| {
+                        FITS_EXTENSIONS.contains(&e.as_str())
+                            || PNG_EXTENSIONS.contains(&e.as_str())
+                    })
+            })
+            .collect::<Vec<_>>();
+        if files.is_empty() {
+            return Err(format!("No FITS or PNG files found in {}", path).into());
+        }
+        files.sort();
+        Ok(FrameList::Files(files))
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            FrameList::Recording { offsets, .. } => offsets.len(),
+            FrameList::Files(files) => files.len(),
+        }
+    }
+
+    /// Read frame `index`
+    ///
+    /// PNG files carry no timing, so their center of integration is taken
+    /// from the file modification time
+    fn get(&mut self, index: usize) -> Result<CameraFrame<u16>, Box<dyn Error>> {
+        match self {
+            FrameList::Recording { reader, offsets } => {
+                reader.seek(offsets[index])?;
+                let recorded = reader.read_frame()?.ok_or("Recording ended unexpectedly")?;
+                // Frames are recorded without gaps, so any mismatch means a damaged file
+                if recorded.sequence != index as u64 {
+                    return Err(format!(
+                        "Corrupt recording: frame {} has sequence number {}",
+                        index, recorded.sequence
+                    )
+                    .into());
+                }
+                Ok(recorded.frame)
+            }
+            FrameList::Files(files) => {
+                let file = &files[index];
+                let filename = file.to_str().ok_or("Invalid file name")?;
+                let ext = file
+                    .extension()
+                    .and_then(|e| e.to_str())
+                    .unwrap_or_default()
+                    .to_lowercase();
+                if FITS_EXTENSIONS.contains(&ext.as_str()) {
+                    return CameraFrame::<u16>::load_fits(filename);
+                }
+                let data = FrameData::<u16>::load_png(filename)
+                    .map_err(|e: PngError| format!("{}: {}", filename, e))?;
+                let modified: DateTime<Utc> = std::fs::metadata(file)?.modified()?.into();
+                Ok(CameraFrame::create(0.0, modified, 16, data))
+            }
+        }
+    }
+}
+
+/// Snapshot of the state of a playback
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PlaybackStatus {
+    /// Index of the next frame to be emitted
+    pub position: usize,
+    /// Number of frames available
+    pub nframes: usize,
+    pub paused: bool,
+    pub looping: bool,
+    /// Playback speed relative to the original timing
+    pub speed: f64,
+    /// Why playback was paused, if a frame could not be read
+    pub error: Option<String>,
+}
+
+struct PlaybackState {
+    status: PlaybackStatus,
+    running: bool,
+    /// Emit the frame at `position` even though playback is paused
+    step: bool,
+    /// Incremented on every change, so a thread waiting for the next frame
+    /// knows to re-evaluate what to emit
+    generation: u64,
+}
+
+/// Handle used to control a running playback from another thread (e.g., the GUI)
+pub struct PlaybackControl {
+    state: Mutex<PlaybackState>,
+    changed: Condvar,
+}
+
+impl PlaybackControl {
+    fn new() -> Self {
+        PlaybackControl {
+            state: Mutex::new(PlaybackState {
+                status: PlaybackStatus {
+                    speed: 1.0,
+                    ..Default::default()
+                },
+                running: false,
+                step: false,
+                generation: 0,
+            }),
+            changed: Condvar::new(),
+        }
+    }
+
+    /// Apply a change to the state and wake the playback thread
+    fn update(&self, change: impl FnOnce(&mut PlaybackState)) {
+        let mut state = self.state.lock().unwrap();
+        change(&mut state);
+        state.generation += 1;
+        self.changed.notify_all();
+    }
+
+    /// Current state of the playback
+    pub fn status(&self) -> PlaybackStatus {
+        self.state.lock().unwrap().status.clone()
+    }
+
+    /// Pause or resume playback
+    ///
+    /// Resuming a playback that has reached the end starts again from the first frame
+    pub fn set_paused(&self, paused: bool) {
+        self.update(|s| {
+            if !paused && s.status.position >= s.status.nframes {
+                s.status.position = 0;
+            }
+            s.status.paused = paused;
+        });
+    }
+
+    /// Pause playback and emit the next frame
+    pub fn step(&self) {
+        self.update(|s| {
+            s.status.paused = true;
+            s.step = true;
+        });
+    }
+
+    /// Move to a frame
+    ///
+    /// If playback is paused, the frame is emitted immediately
+    ///
+    /// # Arguments
+    /// * `position` - Index of the frame; clamped to the last frame
+    ///
+    pub fn seek(&self, position: usize) {
+        self.update(|s| {
+            s.status.position = position.min(s.status.nframes.saturating_sub(1));
+            s.step = s.status.paused;
+        });
+    }
+
+    /// Set the playback speed relative to the original timing (e.g., 2.0 is twice as fast)
+    pub fn set_speed(&self, speed: f64) {
+        if speed > 0.0 && speed.is_finite() {
+            self.update(|s| s.status.speed = speed);
+        }
+    }
+
+    /// Restart from the first frame after the last, instead of pausing
+    pub fn set_looping(&self, looping: bool) {
+        self.update(|s| s.status.looping = looping);
+    }
+}
+
+/// Camera source that plays back a recording or a directory of images
+pub struct PlaybackSource {
+    path: String,
+    thread: Option<thread::JoinHandle<()>>,
+    control: Arc<PlaybackControl>,
+}
+
+impl PlaybackSource {
+    /// Create a playback source
+    ///
+    /// The path is opened when the source is started
+    ///
+    /// # Arguments
+    /// * `path` - Recording file, or directory of FITS and PNG images
+    ///
+    pub fn new(path: &str) -> Self {
+        PlaybackSource {
+            path: path.to_string(),
+            thread: None,
+            control: Arc::new(PlaybackControl::new()),
+        }
+    }
+
+    /// Emit frames until the source is stopped
+    fn run(control: Arc<PlaybackControl>, mut frames: FrameList, onframe: FrameCallback<u16>) {
+        // Wall-clock time and center of integration of the last frame emitted
+        let mut last: Option<(Instant, DateTime<Utc>)> = None;
+        let mut state = control.state.lock().unwrap();
+        while state.running {
+            if state.status.position >= state.status.nframes {
+                if state.status.looping && state.status.nframes > 0 {
+                    state.status.position = 0;
+                    last = None;
+                } else {
+                    state.status.paused = true;
+                    state.step = false;
+                }
+            }
+            if state.status.paused && !state.step {
+                last = None;
+                state = control.changed.wait(state).unwrap();
+                continue;
+            }
+            let stepping = std::mem::take(&mut state.step);
+            let position = state.status.position;
+            let generation = state.generation;
+            drop(state);
+
+            let frame = frames.get(position);
+            state = control.state.lock().unwrap();
+            let frame = match frame {
+                Ok(frame) => frame,
+                Err(e) => {
+                    state.status.error = Some(format!("Cannot read frame {}: {}", position, e));
+                    state.status.paused = true;
+                    continue;
+                }
+            };
+
+            // Wait until the frame is due, unless the state changes in the meantime
+            if let (false, Some((wall, coi))) = (stepping, last) {
+                let spacing = (frame.center_of_integration - coi)
+                    .num_microseconds()
+                    .unwrap_or(0) as f64
+                    * 1.0e-6;
+                let delay = (spacing / state.status.speed).clamp(0.0, MAX_FRAME_DELAY);
+                let deadline = wall + Duration::from_secs_f64(delay);
+                while state.running && state.generation == generation {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        break;
+                    }
+                    state = control
+                        .changed
+                        .wait_timeout(state, deadline - now)
+                        .unwrap()
+                        .0;
+                }
+                if state.generation != generation {
+                    continue;
+                }
+            }
+            if !state.running {
+                break;
+            }
+
+            state.status.position = position + 1;
+            state.status.error = None;
+            last = Some((Instant::now(), frame.center_of_integration));
+            drop(state);
+            onframe(frame);
+            state = control.state.lock().unwrap();
+        }
+    }
+}
+
+impl CameraSource<u16> for PlaybackSource {
+    fn start(&mut self, onframe: FrameCallback<u16>) -> Result<(), Box<dyn Error>> {
+        if self.is_running() {
+            return Err("Playback source is already running".into());
+        }
+        let frames = FrameList::open(&self.path)?;
+        self.control.update(|s| {
+            s.status.nframes = frames.len();
+            s.status.position = 0;
+            s.running = true;
+        });
+        let control = self.control.clone();
+        self.thread = Some(thread::spawn(move || Self::run(control, frames, onframe)));
+        Ok(())
+    }
+
+    fn stop(&mut self) {
+        self.control.update(|s| s.running = false);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+
+    fn is_running(&self) -> bool {
+        self.control.state.lock().unwrap().running
+    }
+
+    fn describe(&self) -> String {
+        format!("Playback of {}", self.path)
+    }
+
+    fn playback_control(&self) -> Option<Arc<PlaybackControl>> {
+        Some(self.control.clone())
+    }
+}
+
+impl Drop for PlaybackSource {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::recording::{Recorder, RecordingLimits};
+    use std::sync::mpsc;
+
+    #[test]
+    fn test_playback_recording() {
+        let filename = std::env::temp_dir().join("viewer_test_playback.vrec");
+        let filename = filename.to_str().unwrap();
+        let t0 = chrono::Utc::now();
+        let recorder = Recorder::new();
+        recorder
+            .start(filename, RecordingLimits::default())
+            .unwrap();
+        for i in 0..5 {
+            recorder.record(&CameraFrame::create(
+                0.01,
+                t0 + chrono::Duration::milliseconds(100 * i),
+                16,
+                &FrameData::<u16>::ones(4, 4) * (i as u16),
+            ));
+        }
+        recorder.stop();
+
+        let mut source = PlaybackSource::new(filename);
+        let control = source.playback_control().unwrap();
+        control.set_speed(10.0);
+        let (tx, rx) = mpsc::channel();
+        let tx = Mutex::new(tx);
+        source
+            .start(Box::new(move |frame: CameraFrame<u16>| {
+                let _ = tx.lock().unwrap().send(frame.data.data[0]);
+            }))
+            .unwrap();
+        let timeout = Duration::from_secs(5);
+
+        // Frames arrive in order, spaced by 100 ms / 10
+        let start = Instant::now();
+        let values = (0..5)
+            .map(|_| rx.recv_timeout(timeout).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(values, vec![0, 1, 2, 3, 4]);
+        assert!(start.elapsed() >= Duration::from_millis(35));
+
+        // Without looping, playback pauses at the end
+        assert!(rx.recv_timeout(Duration::from_millis(100)).is_err());
+        assert!(control.status().paused);
+
+        control.seek(2);
+        assert_eq!(rx.recv_timeout(timeout).unwrap(), 2);
+        control.step();
+        assert_eq!(rx.recv_timeout(timeout).unwrap(), 3);
+        assert_eq!(control.status().position, 4);
+
+        source.stop();
+        assert!(!source.is_running());
+        let _ = std::fs::remove_file(filename);
+    }
+
+    #[test]
+    fn test_playback_error() {
+        let dir = std::env::temp_dir().join("viewer_test_playback_error");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("frame.fits"), b"not a FITS file").unwrap();
+
+        // A frame that cannot be read pauses playback, with the error in its status
+        let mut source = PlaybackSource::new(dir.to_str().unwrap());
+        let control = source.playback_control().unwrap();
+        source.start(Box::new(|_: CameraFrame<u16>| {})).unwrap();
+        let start = Instant::now();
+        while control.status().error.is_none() && start.elapsed() < Duration::from_secs(5) {
+            thread::sleep(Duration::from_millis(10));
+        }
+        let status = control.status();
+        assert!(status.error.unwrap().starts_with("Cannot read frame 0"));
+        assert!(status.paused);
+        assert_eq!(status.position, 0);
+
+        source.stop();
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
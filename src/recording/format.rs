@@ -0,0 +1,339 @@
+//!
+//! Streamable container for raw camera frames
+//!
+//! A recording is a file header followed by any number of frame records, each
+//! self-describing, so a recording can be read while it is still being written
+//! and a truncated file loses at most its last frame.  All values are little-endian.
+//!
+//! File header:
+//! * 8 bytes: magic `VIEWREC\0`
+//! * u32: format version
+//!
+//! Frame record:
+//! * 4 bytes: magic `FRME`
+//! * u64: sequence number within the recording
+//! * f64: exposure in seconds
+//! * i64: center of integration, in nanoseconds since the Unix epoch (UTC)
+//! * u8: bit depth
+//! * u8: bytes per pixel
+//! * u8: 1 if pixels are signed, else 0
+//! * u8: reserved
+//! * u32: width
+//! * u32: height
+//! * width * height pixels
+//!
+
+use crate::cameraframe::CameraFrame;
+use crate::cameraframe::FrameData;
+use crate::cameraframe::MonoPixel;
+
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+
+const FILE_MAGIC: &[u8; 8] = b"VIEWREC\0";
+const FRAME_MAGIC: &[u8; 4] = b"FRME";
+const VERSION: u32 = 1;
+
+/// Size of the file header in bytes
+pub const FILE_HEADER_SIZE: u64 = 12;
+/// Size of a frame record header in bytes
+pub const FRAME_HEADER_SIZE: u64 = 40;
+
+/// A frame read from a recording, with its sequence number
+#[derive(Clone)]
+pub struct RecordedFrame<T>
+where
+    T: MonoPixel,
+{
+    pub sequence: u64,
+    pub frame: CameraFrame<T>,
+}
+
+/// Writes camera frames to a recording file
+pub struct RecordingWriter {
+    writer: BufWriter<File>,
+    bytes: u64,
+}
+
+impl RecordingWriter {
+    /// Create a new recording file, overwriting any existing file
+    ///
+    /// # Arguments
+    /// * `filename` - Name of the recording file
+    ///
+    pub fn create(filename: &str) -> Result<Self, Box<dyn Error>> {
+        let mut writer = BufWriter::new(File::create(filename)?);
+        writer.write_all(FILE_MAGIC)?;
+        writer.write_all(&VERSION.to_le_bytes())?;
+        Ok(RecordingWriter {
+            writer,
+            bytes: FILE_HEADER_SIZE,
+        })
+    }
+
+    /// Number of bytes written so far
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+
+    /// Size in bytes of the record for a frame
+    pub fn record_size<T: MonoPixel>(frame: &CameraFrame<T>) -> u64 {
+        FRAME_HEADER_SIZE + (frame.data.data.len() * std::mem::size_of::<T>()) as u64
+    }
+
+    /// Append a frame record made by `encode_frame` to the recording
+    pub fn write_record(&mut self, record: &[u8]) -> Result<(), Box<dyn Error>> {
+        self.writer.write_all(record)?;
+        self.bytes += record.len() as u64;
+        Ok(())
+    }
+
+    /// Encode a frame as a record of the recording, so that it can be written later
+    /// (or on another thread)
+    ///
+    /// # Arguments
+    /// * `sequence` - Sequence number of the frame
+    /// * `frame` - The frame
+    ///
+    /// # Returns
+    /// The `record_size(frame)` bytes of the record
+    ///
+    pub fn encode_frame<T: MonoPixel>(
+        sequence: u64,
+        frame: &CameraFrame<T>,
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        let time = frame
+            .center_of_integration
+            .timestamp_nanos_opt()
+            .ok_or("Frame time out of range for recording")?;
+        let mut record = Vec::with_capacity(Self::record_size(frame) as usize);
+        record.extend_from_slice(FRAME_MAGIC);
+        record.extend_from_slice(&sequence.to_le_bytes());
+        record.extend_from_slice(&frame.exposure.to_le_bytes());
+        record.extend_from_slice(&time.to_le_bytes());
+        record.push(frame.bit_depth);
+        record.push(std::mem::size_of::<T>() as u8);
+        record.push((T::min_value() < T::zero()) as u8);
+        record.push(0);
+        record.extend_from_slice(&frame.data.width.to_le_bytes());
+        record.extend_from_slice(&frame.data.height.to_le_bytes());
+
+        let data = frame
+            .data
+            .data
+            .iter()
+            .map(|x| x.to_le())
+            .collect::<Vec<T>>();
+        record.extend_from_slice(unsafe {
+            std::slice::from_raw_parts(
+                data.as_ptr() as *const u8,
+                data.len() * std::mem::size_of::<T>(),
+            )
+        });
+        Ok(record)
+    }
+
+    /// Flush buffered data to the file
+    pub fn flush(&mut self) -> Result<(), Box<dyn Error>> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Reads camera frames with pixel type `T` from a recording file
+pub struct RecordingReader<T>
+where
+    T: MonoPixel,
+{
+    reader: BufReader<File>,
+    _pixel: std::marker::PhantomData<T>,
+}
+
+impl<T> RecordingReader<T>
+where
+    T: MonoPixel,
+{
+    /// Open a recording file
+    ///
+    /// # Arguments
+    /// * `filename` - Name of the recording file
+    ///
+    pub fn open(filename: &str) -> Result<Self, Box<dyn Error>> {
+        let mut reader = BufReader::new(File::open(filename)?);
+        let mut header = [0u8; FILE_HEADER_SIZE as usize];
+        reader.read_exact(&mut header)?;
+        if &header[0..8] != FILE_MAGIC {
+            return Err(format!("{} is not a recording file", filename).into());
+        }
+        let version = u32::from_le_bytes(header[8..12].try_into().unwrap());
+        if version != VERSION {
+            return Err(format!("Unsupported recording version {}", version).into());
+        }
+        Ok(RecordingReader {
+            reader,
+            _pixel: std::marker::PhantomData,
+        })
+    }
+
+    /// Read the next frame
+    ///
+    /// # Returns
+    /// The frame, or None at the end of the recording (including a truncated final frame)
+    ///
+    pub fn read_frame(&mut self) -> Result<Option<RecordedFrame<T>>, Box<dyn Error>> {
+        let mut header = [0u8; FRAME_HEADER_SIZE as usize];
+        if !self.read_or_eof(&mut header)? {
+            return Ok(None);
+        }
+        if &header[0..4] != FRAME_MAGIC {
+            return Err("Corrupt recording: bad frame marker".into());
+        }
+        let u32_at = |i: usize| u32::from_le_bytes(header[i..i + 4].try_into().unwrap());
+        let u64_at = |i: usize| u64::from_le_bytes(header[i..i + 8].try_into().unwrap());
+        let sequence = u64_at(4);
+        let exposure = f64::from_bits(u64_at(12));
+        let time = u64_at(20) as i64;
+        let bit_depth = header[28];
+        let pixel_bytes = header[29] as usize;
+        let signed = header[30] != 0;
+        let width = u32_at(32);
+        let height = u32_at(36);
+
+        if pixel_bytes != std::mem::size_of::<T>() || signed != (T::min_value() < T::zero()) {
+            return Err(format!(
+                "Recording has {}-byte {} pixels, which do not match the requested pixel type",
+                pixel_bytes,
+                if signed { "signed" } else { "unsigned" }
+            )
+            .into());
+        }
+
+        let npixels = width as usize * height as usize;
+        let mut data = vec![T::zero(); npixels];
+        let bytes = unsafe {
+            std::slice::from_raw_parts_mut(data.as_mut_ptr() as *mut u8, npixels * pixel_bytes)
+        };
+        if !self.read_or_eof(bytes)? {
+            return Ok(None);
+        }
+        data.iter_mut().for_each(|x| *x = T::from_le(*x));
+
+        Ok(Some(RecordedFrame {
+            sequence,
+            frame: CameraFrame::create(
+                exposure,
+                chrono::DateTime::from_timestamp_nanos(time),
+                bit_depth,
+                FrameData {
+                    width,
+                    height,
+                    data,
+                },
+            ),
+        }))
+    }
+
+    /// Byte offsets of every complete frame record in the recording
+    ///
+    /// Leaves the reader positioned at the first frame
+    pub fn index(&mut self) -> Result<Vec<u64>, Box<dyn Error>> {
+        let end = self.reader.seek(SeekFrom::End(0))?;
+        let mut offsets = Vec::new();
+        let mut offset = FILE_HEADER_SIZE;
+        let mut header = [0u8; FRAME_HEADER_SIZE as usize];
+        while offset + FRAME_HEADER_SIZE <= end {
+            self.reader.seek(SeekFrom::Start(offset))?;
+            self.reader.read_exact(&mut header)?;
+            if &header[0..4] != FRAME_MAGIC {
+                return Err("Corrupt recording: bad frame marker".into());
+            }
+            let pixel_bytes = header[29] as u64;
+            let width = u32::from_le_bytes(header[32..36].try_into().unwrap()) as u64;
+            let height = u32::from_le_bytes(header[36..40].try_into().unwrap()) as u64;
+            let next = offset + FRAME_HEADER_SIZE + width * height * pixel_bytes;
+            if next > end {
+                break;
+            }
+            offsets.push(offset);
+            offset = next;
+        }
+        self.seek(FILE_HEADER_SIZE)?;
+        Ok(offsets)
+    }
+
+    /// Position the reader at a byte offset returned by `index`
+    pub fn seek(&mut self, offset: u64) -> Result<(), Box<dyn Error>> {
+        self.reader.seek(SeekFrom::Start(offset))?;
+        Ok(())
+    }
+
+    /// Fill `buf`, returning false if the end of the file is reached first
+    fn read_or_eof(&mut self, buf: &mut [u8]) -> Result<bool, Box<dyn Error>> {
+        match self.reader.read_exact(buf) {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_read() {
+        let filename = std::env::temp_dir().join("viewer_test_format.vrec");
+        let filename = filename.to_str().unwrap();
+        let t0 = chrono::Utc::now();
+        let frames = (0..4)
+            .map(|i| {
+                CameraFrame::<u16>::create(
+                    0.1,
+                    t0 + chrono::Duration::milliseconds(30 * i),
+                    12,
+                    FrameData::<u16>::rand_norm(1000.0, 100.0, 8, 6),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        let mut writer = RecordingWriter::create(filename).unwrap();
+        for (i, f) in frames.iter().enumerate() {
+            let record = RecordingWriter::encode_frame(i as u64, f).unwrap();
+            writer.write_record(&record).unwrap();
+        }
+        assert_eq!(
+            writer.bytes(),
+            FILE_HEADER_SIZE + 4 * (FRAME_HEADER_SIZE + 8 * 6 * 2)
+        );
+        writer.flush().unwrap();
+        drop(writer);
+
+        // Truncate part of the last frame; it should be silently ignored
+        let file = std::fs::OpenOptions::new()
+            .write(true)
+            .open(filename)
+            .unwrap();
+        file.set_len(FILE_HEADER_SIZE + 4 * (FRAME_HEADER_SIZE + 96) - 10)
+            .unwrap();
+
+        let mut reader = RecordingReader::<u16>::open(filename).unwrap();
+        assert_eq!(reader.index().unwrap().len(), 3);
+        for (i, f) in frames.iter().take(3).enumerate() {
+            let r = reader.read_frame().unwrap().unwrap();
+            assert_eq!(r.sequence, i as u64);
+            assert_eq!(r.frame.exposure, f.exposure);
+            assert_eq!(r.frame.bit_depth, 12);
+            assert_eq!(r.frame.center_of_integration, f.center_of_integration);
+            assert_eq!(r.frame.data.data, f.data.data);
+        }
+        assert!(reader.read_frame().unwrap().is_none());
+
+        assert!(RecordingReader::<u8>::open(filename)
+            .unwrap()
+            .read_frame()
+            .is_err());
+        let _ = std::fs::remove_file(filename);
+    }
+}
@@ -0,0 +1,10 @@
+//!
+//! Recording of raw camera frames to disk
+//!
+
+mod format;
+mod recorder;
+
+pub use format::RecordingReader;
+pub use recorder::Recorder;
+pub use recorder::RecordingLimits;
@@ -0,0 +1,299 @@
+use super::format::RecordingWriter;
+use crate::cameraframe::CameraFrame;
+use crate::cameraframe::MonoPixel;
+
+use std::error::Error;
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// Frames that can wait to be written to disk; further frames are dropped until
+/// the writer catches up
+const RECORD_QUEUE_DEPTH: usize = 64;
+
+/// Limits at which a recording automatically stops
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct RecordingLimits {
+    /// Maximum size of the recording file, in bytes
+    pub max_bytes: Option<u64>,
+    /// Maximum time span of the recorded frames, in seconds
+    pub max_duration: Option<f64>,
+}
+
+/// State of the recorder
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RecordingStatus {
+    /// Is a recording in progress?
+    pub recording: bool,
+    /// File being (or most recently) recorded to
+    pub filename: String,
+    /// Number of frames recorded
+    pub frames: u64,
+    /// Number of frames dropped because the disk could not keep up
+    pub dropped: u64,
+    /// Number of bytes recorded
+    pub bytes: u64,
+    /// Time between the first and last recorded frames, in seconds
+    pub duration: f64,
+    /// Error that stopped the recording, if any
+    pub error: Option<String>,
+}
+
+struct ActiveRecording {
+    /// Queue of frame records for the writer thread; closed (None) once a limit
+    /// or an error ends the recording, while the writer finishes
+    records: Option<mpsc::SyncSender<Vec<u8>>>,
+    writer: thread::JoinHandle<Result<(), String>>,
+    limits: RecordingLimits,
+    start: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+#[derive(Default)]
+struct RecorderState {
+    active: Option<ActiveRecording>,
+    status: RecordingStatus,
+}
+
+/// Records raw camera frames to a file
+///
+/// Frames are passed to `record` as they arrive from the camera source, before the
+/// image queue can drop any, and are only recorded between calls to `start` and
+/// `stop`.  They are written to disk by a thread of the recording's own, so a slow
+/// disk does not hold up the source; frames that arrive while the queue to the
+/// writer is full are dropped, and counted.
+#[derive(Default)]
+pub struct Recorder {
+    state: Mutex<RecorderState>,
+}
+
+impl Recorder {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self::default())
+    }
+
+    /// Start recording to a file
+    ///
+    /// # Arguments
+    /// * `filename` - Name of the recording file; an existing file is overwritten
+    /// * `limits` - Size and duration at which the recording automatically stops
+    ///
+    /// # Returns
+    /// An empty Result if the recording was started, or an error if the file could
+    /// not be created or a recording is already in progress
+    ///
+    pub fn start(&self, filename: &str, limits: RecordingLimits) -> Result<(), Box<dyn Error>> {
+        if self.status().recording {
+            return Err("A recording is already in progress".into());
+        }
+        // A recording ended by a limit may still be writing its last frames
+        self.finish();
+        let mut state = self.state.lock().unwrap();
+        if state.active.is_some() {
+            return Err("A recording is already in progress".into());
+        }
+        let mut writer = RecordingWriter::create(filename)?;
+        state.status = RecordingStatus {
+            recording: true,
+            filename: filename.to_string(),
+            bytes: writer.bytes(),
+            ..Default::default()
+        };
+        // The writer thread stops at the first error, or when the sender is dropped
+        let (records, received) = mpsc::sync_channel::<Vec<u8>>(RECORD_QUEUE_DEPTH);
+        let writer = thread::spawn(move || {
+            for record in received.iter() {
+                writer.write_record(&record).map_err(|e| e.to_string())?;
+            }
+            writer.flush().map_err(|e| e.to_string())
+        });
+        state.active = Some(ActiveRecording {
+            records: Some(records),
+            writer,
+            limits,
+            start: None,
+        });
+        Ok(())
+    }
+
+    /// Stop recording, once every recorded frame has been written
+    ///
+    /// # Returns
+    /// The final status of the recording
+    pub fn stop(&self) -> RecordingStatus {
+        self.finish();
+        self.status()
+    }
+
+    /// Current status of the recorder
+    ///
+    /// A recording ended by a limit or an error is closed here once its writer
+    /// thread has finished, so that a write error shows up in the status
+    pub fn status(&self) -> RecordingStatus {
+        let finished = self
+            .state
+            .lock()
+            .unwrap()
+            .active
+            .as_ref()
+            .is_some_and(|a| a.records.is_none() && a.writer.is_finished());
+        if finished {
+            self.finish();
+        }
+        self.state.lock().unwrap().status.clone()
+    }
+
+    /// Record a frame, if a recording is in progress
+    ///
+    /// The recording is ended (without recording the frame) if the frame would
+    /// exceed one of the limits.  This never waits for the disk: the writer thread
+    /// finishes on its own, and is joined by `stop`.
+    pub fn record<T: MonoPixel>(&self, frame: &CameraFrame<T>) {
+        let mut guard = self.state.lock().unwrap();
+        let state = &mut *guard;
+        let Some(active) = state.active.as_mut() else {
+            return;
+        };
+        let Some(records) = active.records.as_ref() else {
+            return;
+        };
+
+        let start = *active.start.get_or_insert(frame.center_of_integration);
+        let duration = (frame.center_of_integration - start)
+            .num_microseconds()
+            .unwrap_or(i64::MAX) as f64
+            * 1.0e-6;
+        let bytes = state.status.bytes + RecordingWriter::record_size(frame);
+        let over_size = active.limits.max_bytes.is_some_and(|m| bytes > m);
+        let over_time = active.limits.max_duration.is_some_and(|m| duration > m);
+        if over_size || over_time {
+            active.records = None;
+            state.status.recording = false;
+            return;
+        }
+        let sequence = state.status.frames;
+        match RecordingWriter::encode_frame(sequence, frame) {
+            Ok(record) => match records.try_send(record) {
+                Ok(()) => {
+                    state.status.frames += 1;
+                    state.status.bytes = bytes;
+                    state.status.duration = duration;
+                }
+                Err(mpsc::TrySendError::Full(_)) => state.status.dropped += 1,
+                // The writer thread has stopped on an error, which `finish` reports
+                Err(mpsc::TrySendError::Disconnected(_)) => {
+                    active.records = None;
+                    state.status.recording = false;
+                }
+            },
+            Err(e) => {
+                active.records = None;
+                state.status.recording = false;
+                state.status.error = Some(e.to_string());
+            }
+        }
+    }
+
+    /// Close the active recording, if any, waiting for its frames to be written
+    fn finish(&self) {
+        // The lock is not held while waiting, so the status can still be read
+        let Some(mut active) = self.state.lock().unwrap().active.take() else {
+            return;
+        };
+        active.records = None;
+        let written = active
+            .writer
+            .join()
+            .unwrap_or(Err("Recording thread panicked".to_string()));
+        let mut state = self.state.lock().unwrap();
+        if state.active.is_none() {
+            state.status.recording = false;
+            if let Err(e) = written {
+                state.status.error.get_or_insert(e);
+            }
+        }
+    }
+}
+
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::format::RecordingReader;
+    use super::*;
+    use crate::cameraframe::FrameData;
+
+    fn frame(ms: i64) -> CameraFrame<u16> {
+        let t0 = chrono::DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        CameraFrame::create(
+            0.01,
+            t0 + chrono::Duration::milliseconds(ms),
+            16,
+            FrameData::<u16>::ones(10, 10),
+        )
+    }
+
+    #[test]
+    fn test_duration_limit() {
+        let filename = std::env::temp_dir().join("viewer_test_recorder.vrec");
+        let filename = filename.to_str().unwrap();
+        let recorder = Recorder::new();
+
+        // Not recording: frame is ignored
+        recorder.record(&frame(0));
+        assert_eq!(recorder.status().frames, 0);
+
+        recorder
+            .start(
+                filename,
+                RecordingLimits {
+                    max_bytes: None,
+                    max_duration: Some(0.1),
+                },
+            )
+            .unwrap();
+        assert!(recorder
+            .start(filename, RecordingLimits::default())
+            .is_err());
+        for i in 0..10 {
+            recorder.record(&frame(i * 30));
+        }
+        let status = recorder.status();
+        assert!(!status.recording);
+        assert_eq!(status.frames, 4);
+        assert!(status.error.is_none());
+        // The frames are all in the file once the writer thread has been joined
+        recorder.stop();
+
+        let mut reader = RecordingReader::<u16>::open(filename).unwrap();
+        assert_eq!(reader.index().unwrap().len(), 4);
+        let _ = std::fs::remove_file(filename);
+    }
+
+    #[test]
+    fn test_size_limit() {
+        let filename = std::env::temp_dir().join("viewer_test_recorder_size.vrec");
+        let filename = filename.to_str().unwrap();
+        let recorder = Recorder::new();
+        let record_size = RecordingWriter::record_size(&frame(0));
+        recorder
+            .start(
+                filename,
+                RecordingLimits {
+                    max_bytes: Some(super::super::format::FILE_HEADER_SIZE + 2 * record_size),
+                    max_duration: None,
+                },
+            )
+            .unwrap();
+        for i in 0..5 {
+            recorder.record(&frame(i * 30));
+        }
+        let status = recorder.stop();
+        assert_eq!(status.frames, 2);
+        assert!(!status.recording);
+        let _ = std::fs::remove_file(filename);
+    }
+}
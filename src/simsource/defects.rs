@@ -0,0 +1,359 @@
+//!
+//! Sensor defects for the simulated camera
+//!
+//! Fixed defects (hot and warm pixels, dead rows and columns, column banding and
+//! pixel response non-uniformity) are generated once from a seed and kept in a
+//! `DefectMap`, which serves as ground truth for calibration and defect-detection
+//! tests.  Transient defects (cosmic-ray hits and row banding) are derived from the
+//! seed and the frame timestamp, so they can be recomputed for any frame.
+//!
+
+use chrono::{DateTime, Utc};
+use rand::{Rng, SeedableRng};
+use rand_distr::{Distribution, Exp, Normal, Poisson};
+
+/// Rates and amplitudes of the simulated defects
+#[derive(Clone, Debug, PartialEq)]
+pub struct DefectConfig {
+    /// Fraction of pixels that are hot
+    pub hot_pixel_fraction: f64,
+    /// Extra dark current of a hot pixel, in electrons per second
+    pub hot_pixel_dark: f64,
+    /// Fraction of pixels that are warm
+    pub warm_pixel_fraction: f64,
+    /// Extra dark current of a warm pixel, in electrons per second
+    pub warm_pixel_dark: f64,
+    /// Fraction of rows that collect no charge
+    pub dead_row_fraction: f64,
+    /// Fraction of columns that collect no charge
+    pub dead_column_fraction: f64,
+    /// Cosmic-ray hits per million pixels per second
+    pub cosmic_ray_rate: f64,
+    /// Mean charge deposited by a cosmic ray, in electrons
+    pub cosmic_ray_charge: f64,
+    /// RMS offset of each row, redrawn every frame, in electrons
+    pub row_banding: f64,
+    /// RMS fixed offset of each column, in electrons
+    pub column_banding: f64,
+    /// RMS relative variation of the pixel response (PRNU)
+    pub prnu: f64,
+    /// Seed for all the defects
+    pub seed: u64,
+}
+
+impl Default for DefectConfig {
+    fn default() -> Self {
+        DefectConfig {
+            hot_pixel_fraction: 1.0e-4,
+            hot_pixel_dark: 20000.0,
+            warm_pixel_fraction: 1.0e-3,
+            warm_pixel_dark: 2000.0,
+            dead_row_fraction: 0.0,
+            dead_column_fraction: 2.0e-3,
+            cosmic_ray_rate: 50.0,
+            cosmic_ray_charge: 2000.0,
+            row_banding: 3.0,
+            column_banding: 2.0,
+            prnu: 0.01,
+            seed: 0,
+        }
+    }
+}
+
+/// A cosmic-ray hit: a short straight track of pixels sharing the deposited charge
+#[derive(Clone, Debug, PartialEq)]
+pub struct CosmicRay {
+    /// Pixels hit, as (x, y)
+    pub pixels: Vec<(u32, u32)>,
+    /// Charge deposited in each pixel, in electrons
+    pub charge: f64,
+}
+
+/// Ground truth of the defects of a simulated sensor
+#[derive(Clone, Debug, PartialEq)]
+pub struct DefectMap {
+    pub config: DefectConfig,
+    pub width: u32,
+    pub height: u32,
+    /// Hot pixels, as (x, y)
+    pub hot_pixels: Vec<(u32, u32)>,
+    /// Warm pixels, as (x, y)
+    pub warm_pixels: Vec<(u32, u32)>,
+    pub dead_rows: Vec<u32>,
+    pub dead_columns: Vec<u32>,
+    /// Relative response of each pixel, in row-major order
+    pub response: Vec<f64>,
+    /// Fixed offset of each column, in electrons
+    pub column_offsets: Vec<f64>,
+}
+
+impl DefectMap {
+    /// Generate the fixed defects of a sensor
+    ///
+    /// # Arguments
+    /// * `config` - Rates and amplitudes of the defects
+    /// * `width`, `height` - Size of the sensor in pixels
+    ///
+    pub fn generate(config: &DefectConfig, width: u32, height: u32) -> DefectMap {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(config.seed);
+        let npixels = width as usize * height as usize;
+        let count = |fraction: f64, n: usize| ((fraction * n as f64).round() as usize).min(n);
+
+        // Hot and warm pixels are drawn together so they never coincide
+        let nhot = count(config.hot_pixel_fraction, npixels);
+        let nwarm = count(config.warm_pixel_fraction, npixels).min(npixels - nhot);
+        let to_xy = |i: usize| ((i % width as usize) as u32, (i / width as usize) as u32);
+        let mut bright = rand::seq::index::sample(&mut rng, npixels, nhot + nwarm)
+            .into_iter()
+            .map(to_xy);
+        let mut hot_pixels = bright.by_ref().take(nhot).collect::<Vec<_>>();
+        let mut warm_pixels = bright.collect::<Vec<_>>();
+        hot_pixels.sort();
+        warm_pixels.sort();
+
+        let mut lines = |fraction: f64, n: u32| {
+            let mut v = rand::seq::index::sample(&mut rng, n as usize, count(fraction, n as usize))
+                .into_iter()
+                .map(|i| i as u32)
+                .collect::<Vec<_>>();
+            v.sort();
+            v
+        };
+        let dead_rows = lines(config.dead_row_fraction, height);
+        let dead_columns = lines(config.dead_column_fraction, width);
+
+        let prnu = Normal::new(1.0, config.prnu.max(0.0)).unwrap();
+        let response = (0..npixels)
+            .map(|_| prnu.sample(&mut rng).max(0.0))
+            .collect();
+        let banding = Normal::new(0.0, config.column_banding.max(0.0)).unwrap();
+        let column_offsets = (0..width).map(|_| banding.sample(&mut rng)).collect();
+
+        DefectMap {
+            config: config.clone(),
+            width,
+            height,
+            hot_pixels,
+            warm_pixels,
+            dead_rows,
+            dead_columns,
+            response,
+            column_offsets,
+        }
+    }
+
+    /// Used only for testing:
+    ///
+    /// Mask of the defective pixels: 1 for hot, warm and dead pixels, else 0,
+    /// to check what is found in the simulated frames against
+    #[cfg(test)]
+    pub fn mask(&self) -> crate::cameraframe::FrameData<u8> {
+        let mut mask = crate::cameraframe::FrameData::<u8>::zeros(self.width, self.height);
+        let w = self.width as usize;
+        for (x, y) in self.hot_pixels.iter().chain(self.warm_pixels.iter()) {
+            mask.data[*y as usize * w + *x as usize] = 1;
+        }
+        for row in self.dead_rows.iter() {
+            let start = *row as usize * w;
+            mask.data[start..start + w].fill(1);
+        }
+        for col in self.dead_columns.iter() {
+            mask.data
+                .iter_mut()
+                .skip(*col as usize)
+                .step_by(w)
+                .for_each(|m| *m = 1);
+        }
+        mask
+    }
+
+    /// Random number generator for the transient defects of the frame at `time`
+    fn frame_rng(&self, time: DateTime<Utc>) -> rand::rngs::StdRng {
+        let nanos = time.timestamp_nanos_opt().unwrap_or_default() as u64;
+        rand::rngs::StdRng::seed_from_u64(self.config.seed.wrapping_mul(0x9e3779b97f4a7c15) ^ nanos)
+    }
+
+    /// Cosmic rays hitting the sensor during an exposure
+    ///
+    /// # Arguments
+    /// * `time` - Center of integration of the frame
+    /// * `exposure` - Exposure time in seconds
+    ///
+    pub fn cosmic_rays(&self, time: DateTime<Utc>, exposure: f64) -> Vec<CosmicRay> {
+        let npixels = self.width as f64 * self.height as f64;
+        let mean = self.config.cosmic_ray_rate * npixels * 1.0e-6 * exposure;
+        if mean <= 0.0 || self.config.cosmic_ray_charge <= 0.0 {
+            return Vec::new();
+        }
+        let mut rng = self.frame_rng(time);
+        let nhits = Poisson::new(mean).unwrap().sample(&mut rng) as usize;
+        let charge = Exp::new(1.0 / self.config.cosmic_ray_charge).unwrap();
+        (0..nhits)
+            .map(|_| {
+                let x = rng.gen_range(0..self.width) as f64;
+                let y = rng.gen_range(0..self.height) as f64;
+                let angle = rng.gen::<f64>() * std::f64::consts::TAU;
+                let length = rng.gen_range(1..=5);
+                let mut pixels = Vec::<(u32, u32)>::new();
+                for i in 0..length {
+                    let px = (x + angle.cos() * i as f64).round();
+                    let py = (y + angle.sin() * i as f64).round();
+                    if px >= 0.0 && py >= 0.0 && px < self.width as f64 && py < self.height as f64 {
+                        let p = (px as u32, py as u32);
+                        if !pixels.contains(&p) {
+                            pixels.push(p);
+                        }
+                    }
+                }
+                let total: f64 = charge.sample(&mut rng);
+                CosmicRay {
+                    charge: total / pixels.len() as f64,
+                    pixels,
+                }
+            })
+            .collect()
+    }
+
+    /// Offset of each row in the frame at `time`, in electrons
+    pub fn row_offsets(&self, time: DateTime<Utc>) -> Vec<f64> {
+        if self.config.row_banding <= 0.0 {
+            return vec![0.0; self.height as usize];
+        }
+        // Use a different stream than the cosmic rays of the same frame
+        let mut rng = self.frame_rng(time + chrono::Duration::nanoseconds(1));
+        let banding = Normal::new(0.0, self.config.row_banding).unwrap();
+        (0..self.height).map(|_| banding.sample(&mut rng)).collect()
+    }
+
+    /// Apply the response non-uniformity and the hot and warm pixel dark current
+    /// to the photo-electron flux on each pixel
+    pub fn apply_to_flux(&self, flux: &mut [f64]) {
+        assert_eq!(flux.len(), self.response.len());
+        flux.iter_mut()
+            .zip(self.response.iter())
+            .for_each(|(f, r)| *f *= r);
+        let w = self.width as usize;
+        for (x, y) in self.hot_pixels.iter() {
+            flux[*y as usize * w + *x as usize] += self.config.hot_pixel_dark;
+        }
+        for (x, y) in self.warm_pixels.iter() {
+            flux[*y as usize * w + *x as usize] += self.config.warm_pixel_dark;
+        }
+    }
+
+    /// Apply dead rows and columns, banding and cosmic rays to the charge collected by each pixel
+    ///
+    /// # Arguments
+    /// * `electrons` - Electrons in each pixel, in row-major order
+    /// * `time` - Center of integration of the frame
+    /// * `exposure` - Exposure time in seconds
+    ///
+    pub fn apply_to_charge(&self, electrons: &mut [f64], time: DateTime<Utc>, exposure: f64) {
+        assert_eq!(electrons.len(), self.response.len());
+        let w = self.width as usize;
+        for row in self.dead_rows.iter() {
+            let start = *row as usize * w;
+            electrons[start..start + w].fill(0.0);
+        }
+        for col in self.dead_columns.iter() {
+            electrons
+                .iter_mut()
+                .skip(*col as usize)
+                .step_by(w)
+                .for_each(|e| *e = 0.0);
+        }
+        for ray in self.cosmic_rays(time, exposure) {
+            for (x, y) in ray.pixels {
+                electrons[y as usize * w + x as usize] += ray.charge;
+            }
+        }
+        let rows = self.row_offsets(time);
+        for (i, e) in electrons.iter_mut().enumerate() {
+            *e += rows[i / w] + self.column_offsets[i % w];
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simsource::SensorConfig;
+
+    fn config() -> DefectConfig {
+        DefectConfig {
+            hot_pixel_fraction: 0.001,
+            warm_pixel_fraction: 0.005,
+            dead_row_fraction: 0.01,
+            dead_column_fraction: 0.01,
+            cosmic_ray_rate: 10000.0,
+            row_banding: 0.0,
+            column_banding: 0.0,
+            prnu: 0.0,
+            seed: 7,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_defect_map() {
+        let map = DefectMap::generate(&config(), 200, 100);
+        assert_eq!(map, DefectMap::generate(&config(), 200, 100));
+        assert_eq!(map.hot_pixels.len(), 20);
+        assert_eq!(map.warm_pixels.len(), 100);
+        assert!(map.hot_pixels.iter().all(|p| !map.warm_pixels.contains(p)));
+        assert_eq!(map.dead_rows.len(), 1);
+        assert_eq!(map.dead_columns.len(), 2);
+
+        let mask = map.mask();
+        let ndead = 200 + 2 * 100 - 2;
+        let nmasked = mask.data.iter().filter(|m| **m != 0).count();
+        assert!(nmasked <= 120 + ndead && nmasked > ndead);
+
+        let time = Utc::now();
+        assert_eq!(map.cosmic_rays(time, 0.1), map.cosmic_rays(time, 0.1));
+        assert_ne!(
+            map.cosmic_rays(time, 0.1),
+            map.cosmic_rays(time + chrono::Duration::milliseconds(30), 0.1)
+        );
+    }
+
+    #[test]
+    fn test_defects_in_frame() {
+        let sensor = SensorConfig {
+            width: 200,
+            height: 100,
+            read_noise: 1.0,
+            dark_current: 0.0,
+            ..Default::default()
+        };
+        let map = DefectMap::generate(&config(), 200, 100);
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        let time = Utc::now();
+        let exposure = 1.0;
+
+        let mut flux = vec![100.0; 200 * 100];
+        map.apply_to_flux(&mut flux);
+        let mut electrons = sensor.integrate(&flux, exposure, &mut rng);
+        map.apply_to_charge(&mut electrons, time, exposure);
+        let frame = sensor.readout(&electrons, &mut rng);
+        let at = |x: u32, y: u32| frame.data[(y * 200 + x) as usize] as f64;
+
+        let rays = map.cosmic_rays(time, exposure);
+        assert!(!rays.is_empty());
+        let struck = |x: u32, y: u32| rays.iter().any(|r| r.pixels.contains(&(x, y)));
+        for (x, y) in map.hot_pixels.iter() {
+            if !map.dead_rows.contains(y) && !map.dead_columns.contains(x) {
+                assert!(at(*x, *y) > sensor.bias + 10000.0);
+            }
+        }
+        for col in map.dead_columns.iter() {
+            for y in (0..100).filter(|y| !struck(*col, *y)) {
+                assert!((at(*col, y) - sensor.bias).abs() < 10.0);
+            }
+        }
+        for ray in rays.iter() {
+            let (x, y) = ray.pixels[0];
+            assert!(at(x, y) >= sensor.bias + ray.charge.min(sensor.full_well) - 10.0);
+        }
+    }
+}
@@ -1,63 +1,157 @@
 use crate::cameraframe::CameraFrame;
-use crate::cameraframe::FrameData;
+use crate::camerasource::CameraSource;
+use crate::camerasource::FrameCallback;
+use rand::SeedableRng;
+use std::error::Error;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use std::thread;
 
-pub struct SimSource {
-    thread: Option<thread::JoinHandle<()>>,
+mod defects;
+mod scene;
+mod sensor;
+
+pub use defects::DefectConfig;
+pub use defects::DefectMap;
+pub use scene::Psf;
+pub use scene::Scene;
+pub use sensor::SensorConfig;
+
+/// Configuration of the simulated camera
+#[derive(Clone, Debug, PartialEq)]
+pub struct SimConfig {
+    pub sensor: SensorConfig,
+    /// Exposure time in seconds
+    pub exposure: f64,
+    /// Time between frames in seconds
+    pub frame_interval: f64,
+    /// What the camera is looking at
+    pub scene: Scene,
+    /// Defects of the sensor, which must match its size; None for a perfect sensor
+    pub defects: Option<DefectMap>,
+    /// Seed for the noise; None for a different sequence on every run
+    pub seed: Option<u64>,
 }
 
-fn test_data(xoffset: f64, yoffset: f64) -> FrameData<u16> {
-    use rand::distributions::Distribution;
-    use rand_distr::Normal;
-
-    let normal = Normal::new(0.0, 200.0).unwrap();
-    let mut rng = rand::thread_rng();
-
-    let width = 1024;
-    let height = 768;
-    let npixels = width * height;
-    FrameData::<u16> {
-        width,
-        height,
-        data: (0..npixels)
-            .map(|x| {
-                let mut row = (x % width) as f64;
-                let mut col = (x / width) as f64;
-                row -= 256.0;
-                row -= yoffset;
-                col -= 256.0;
-                col -= xoffset;
-                let g = f64::exp(-(row * row + col * col) / 400.0) * 16384.0;
-                (g + 10000.0 + normal.sample(&mut rng)) as u16
-            })
-            .collect(),
+impl Default for SimConfig {
+    fn default() -> Self {
+        let sensor = SensorConfig::default();
+        SimConfig {
+            sensor,
+            exposure: 0.1,
+            frame_interval: 0.03,
+            scene: Scene::default(),
+            defects: Some(DefectMap::generate(
+                &DefectConfig::default(),
+                sensor.width,
+                sensor.height,
+            )),
+            seed: None,
+        }
     }
 }
 
+impl SimConfig {
+    /// Simulate a frame
+    ///
+    /// # Arguments
+    /// * `time` - Center of integration of the frame
+    /// * `rng` - Random number generator for the noise
+    ///
+    pub fn frame<R: rand::Rng>(
+        &self,
+        time: chrono::DateTime<chrono::Utc>,
+        rng: &mut R,
+    ) -> CameraFrame<u16> {
+        let (width, height) = (self.sensor.width, self.sensor.height);
+        let mut flux = self.scene.flux(width, height, time, self.exposure);
+        // Defects alter the light reaching the sensor and the charge it collects
+        let data = match &self.defects {
+            None => self.sensor.expose(&flux, self.exposure, rng),
+            Some(defects) => {
+                defects.apply_to_flux(&mut flux);
+                let mut electrons = self.sensor.integrate(&flux, self.exposure, rng);
+                defects.apply_to_charge(&mut electrons, time, self.exposure);
+                self.sensor.readout(&electrons, rng)
+            }
+        };
+        CameraFrame::<u16>::create(self.exposure, time, self.sensor.bit_depth, data)
+    }
+}
+
+/// Simulated camera producing a star field seen through a noisy sensor
+pub struct SimSource {
+    config: SimConfig,
+    thread: Option<thread::JoinHandle<()>>,
+    running: Arc<AtomicBool>,
+}
+
 impl SimSource {
     pub fn new() -> Self {
-        SimSource { thread: None }
+        Self::with_config(SimConfig::default())
+    }
+
+    pub fn with_config(config: SimConfig) -> Self {
+        SimSource {
+            config,
+            thread: None,
+            running: Arc::new(AtomicBool::new(false)),
+        }
     }
+}
+
+impl CameraSource<u16> for SimSource {
+    fn start(&mut self, onframe: FrameCallback<u16>) -> Result<(), Box<dyn Error>> {
+        if self.is_running() {
+            return Err("Simulated source is already running".into());
+        }
+        self.running.store(true, Ordering::SeqCst);
+        let running = self.running.clone();
+
+        let config = self.config.clone();
+        let mut rng = match config.seed {
+            Some(seed) => rand::rngs::StdRng::seed_from_u64(seed),
+            None => rand::rngs::StdRng::from_entropy(),
+        };
 
-    pub fn start<F>(&mut self, onframe: F)
-    where
-        F: Fn(CameraFrame<u16>) + Send + 'static,
-    {
         // Spawn a thread that continuously generates frames
         self.thread = Some(thread::spawn(move || {
-            use std::f64::consts::PI;
-            loop {
-                thread::sleep(std::time::Duration::from_millis(30));
-                let now = chrono::Utc::now();
-
-                // Create a frame
-                let xoffset = (now.timestamp_millis() as f64 * 2.0 * PI / 1000.0).cos() * 10.0;
-                let yoffset = (now.timestamp_millis() as f64 * 2.0 * PI / 3000.0).cos() * 20.0;
-
-                let frame = CameraFrame::<u16>::create(0.1, now, 16, test_data(xoffset, yoffset));
-                // Run the callback
-                onframe(frame);
+            let interval = std::time::Duration::from_secs_f64(config.frame_interval.max(0.0));
+            while running.load(Ordering::SeqCst) {
+                thread::sleep(interval);
+                // Create a frame and run the callback
+                onframe(config.frame(chrono::Utc::now(), &mut rng));
             }
         }));
+        Ok(())
+    }
+
+    fn stop(&mut self) {
+        self.running.store(false, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+
+    fn is_running(&self) -> bool {
+        self.running.load(Ordering::SeqCst)
+    }
+
+    fn describe(&self) -> String {
+        let (sensor, sky) = (&self.config.sensor, self.config.scene.background);
+        format!(
+            "Simulated camera ({}x{}, {}-bit, sky {:.0} ± {:.1} ADU)",
+            sensor.width,
+            sensor.height,
+            sensor.bit_depth,
+            sensor.mean_adu(sky, self.config.exposure),
+            sensor.noise_adu(sky, self.config.exposure)
+        )
+    }
+}
+
+impl Drop for SimSource {
+    fn drop(&mut self) {
+        self.stop();
     }
 }
@@ -0,0 +1,428 @@
+//!
+//! Scenes for the simulated camera
+//!
+//! A scene is a set of point sources (stars, which drift together across the
+//! field) and moving targets (e.g., satellites, which leave a streak during
+//! the exposure) on a uniform sky background, all blurred by a point spread
+//! function and jittered by atmospheric seeing.  Everything is a function of the
+//! frame timestamp and a seed, so the same time always yields the same scene.
+//!
+//! Positions are in pixels, with (0, 0) at the center of the upper-left pixel.
+//! Sources wrap around the edges of the frame so a drifting field never empties.
+//!
+
+use chrono::{DateTime, Utc};
+use rand::{Rng, SeedableRng};
+use rand_distr::StandardNormal;
+
+/// Point spread function
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Psf {
+    /// Gaussian with the given full width at half maximum, in pixels
+    Gaussian { fwhm: f64 },
+    /// Moffat profile with the given full width at half maximum (pixels) and
+    /// power-law index; smaller `beta` gives broader wings
+    Moffat { fwhm: f64, beta: f64 },
+    /// Diffraction pattern of a circular aperture with the given full width at
+    /// half maximum of the central peak, in pixels
+    Airy { fwhm: f64 },
+}
+
+impl Psf {
+    /// Point spread function from its name ("Gaussian", "Moffat" or "Airy",
+    /// case-insensitive), with a full width at half maximum of 3 pixels
+    pub fn from_string(name: &str) -> Option<Psf> {
+        match name.to_lowercase().as_str() {
+            "gaussian" => Some(Psf::Gaussian { fwhm: 3.0 }),
+            "moffat" => Some(Psf::Moffat {
+                fwhm: 3.0,
+                beta: 3.5,
+            }),
+            "airy" => Some(Psf::Airy { fwhm: 3.0 }),
+            _ => None,
+        }
+    }
+
+    /// Unnormalized intensity at a distance `r` pixels from the center
+    fn profile(&self, r: f64) -> f64 {
+        match *self {
+            Psf::Gaussian { fwhm } => {
+                let sigma = fwhm / (2.0 * (2.0 * std::f64::consts::LN_2).sqrt());
+                (-r * r / (2.0 * sigma * sigma)).exp()
+            }
+            Psf::Moffat { fwhm, beta } => {
+                let alpha = fwhm / (2.0 * (2.0f64.powf(1.0 / beta) - 1.0).sqrt());
+                (1.0 + r * r / (alpha * alpha)).powf(-beta)
+            }
+            Psf::Airy { fwhm } => {
+                // The FWHM of the central peak is 1.029 lambda / D
+                let x = std::f64::consts::PI * r * 1.029 / fwhm;
+                if x < 1.0e-8 {
+                    1.0
+                } else {
+                    (2.0 * bessel_j1(x) / x).powi(2)
+                }
+            }
+        }
+    }
+
+    /// Radius in pixels beyond which the PSF is not rendered
+    fn radius(&self) -> f64 {
+        match *self {
+            Psf::Gaussian { fwhm } => 2.0 * fwhm,
+            Psf::Moffat { fwhm, .. } | Psf::Airy { fwhm } => 5.0 * fwhm,
+        }
+        .max(2.0)
+    }
+}
+
+/// Bessel function of the first kind of order one
+///
+/// Rational approximation from Numerical Recipes, accurate to about 1e-8
+fn bessel_j1(x: f64) -> f64 {
+    let ax = x.abs();
+    if ax < 8.0 {
+        let y = x * x;
+        let num = x
+            * (72362614232.0
+                + y * (-7895059235.0
+                    + y * (242396853.1
+                        + y * (-2972611.439 + y * (15704.48260 + y * (-30.16036606))))));
+        let den = 144725228442.0
+            + y * (2300535178.0 + y * (18583304.74 + y * (99447.43394 + y * (376.9991397 + y))));
+        num / den
+    } else {
+        let z = 8.0 / ax;
+        let y = z * z;
+        let xx = ax - 2.356194491;
+        let p = 1.0
+            + y * (0.183105e-2
+                + y * (-0.3516396496e-4 + y * (0.2457520174e-5 + y * (-0.240337019e-6))));
+        let q = 0.04687499995
+            + y * (-0.2002690873e-3
+                + y * (0.8449199096e-5 + y * (-0.88228987e-6 + y * 0.105787412e-6)));
+        let ans = (std::f64::consts::FRAC_2_PI / ax).sqrt() * (xx.cos() * p - z * xx.sin() * q);
+        if x < 0.0 {
+            -ans
+        } else {
+            ans
+        }
+    }
+}
+
+/// A fixed point source
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Star {
+    /// Position at the scene epoch, in pixels
+    pub x: f64,
+    pub y: f64,
+    pub magnitude: f64,
+}
+
+/// A point source moving independently of the stars, such as a satellite
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MovingTarget {
+    /// Position at the scene epoch, in pixels
+    pub x: f64,
+    pub y: f64,
+    /// Velocity in pixels per second
+    pub vx: f64,
+    pub vy: f64,
+    pub magnitude: f64,
+}
+
+/// Description of what the simulated camera sees
+#[derive(Clone, Debug, PartialEq)]
+pub struct Scene {
+    pub psf: Psf,
+    /// Photo-electrons per second per pixel from the sky background
+    pub background: f64,
+    /// Photo-electrons per second from a magnitude 0 source
+    pub zero_point: f64,
+    pub stars: Vec<Star>,
+    /// Drift of the whole star field (e.g., sidereal motion), in pixels per second
+    pub drift: (f64, f64),
+    pub targets: Vec<MovingTarget>,
+    /// RMS random displacement of the whole image from frame to frame, in pixels
+    pub jitter: f64,
+    /// Time at which the source positions are given
+    pub epoch: DateTime<Utc>,
+    /// Seed for the seeing jitter
+    pub seed: u64,
+}
+
+impl Default for Scene {
+    fn default() -> Self {
+        let mut scene = Scene::star_field(1024, 768, 300, 0);
+        scene.targets.push(MovingTarget {
+            x: 0.0,
+            y: 300.0,
+            vx: 150.0,
+            vy: 20.0,
+            magnitude: 7.0,
+        });
+        scene
+    }
+}
+
+impl Scene {
+    /// Scene with randomly placed stars
+    ///
+    /// Magnitudes are drawn between 6 and 12, with the number of stars growing
+    /// by a factor of two per magnitude as in the real sky
+    ///
+    /// # Arguments
+    /// * `width`, `height` - Size of the field in pixels
+    /// * `nstars` - Number of stars
+    /// * `seed` - Seed for the star positions, magnitudes and seeing jitter
+    ///
+    pub fn star_field(width: u32, height: u32, nstars: usize, seed: u64) -> Scene {
+        const BRIGHT: f64 = 6.0;
+        const FAINT: f64 = 12.0;
+        let mut rng = rand::rngs::StdRng::seed_from_u64(seed);
+        let (lo, hi) = (10f64.powf(0.3 * BRIGHT), 10f64.powf(0.3 * FAINT));
+        let stars = (0..nstars)
+            .map(|_| Star {
+                x: rng.gen::<f64>() * width as f64,
+                y: rng.gen::<f64>() * height as f64,
+                magnitude: (lo + rng.gen::<f64>() * (hi - lo)).log10() / 0.3,
+            })
+            .collect();
+        Scene {
+            psf: Psf::Moffat {
+                fwhm: 3.0,
+                beta: 3.5,
+            },
+            background: 2000.0,
+            zero_point: 1.0e9,
+            stars,
+            drift: (2.0, 0.5),
+            targets: Vec::new(),
+            jitter: 0.3,
+            epoch: DateTime::UNIX_EPOCH,
+            seed,
+        }
+    }
+
+    /// Photo-electrons per second from a source of the given magnitude
+    pub fn magnitude_to_flux(&self, magnitude: f64) -> f64 {
+        self.zero_point * 10f64.powf(-0.4 * magnitude)
+    }
+
+    /// Displacement of the image due to seeing at a given time
+    pub fn jitter_offset(&self, time: DateTime<Utc>) -> (f64, f64) {
+        if self.jitter <= 0.0 {
+            return (0.0, 0.0);
+        }
+        let nanos = time.timestamp_nanos_opt().unwrap_or_default() as u64;
+        let mut rng = rand::rngs::StdRng::seed_from_u64(self.seed ^ nanos);
+        let dx: f64 = rng.sample(StandardNormal);
+        let dy: f64 = rng.sample(StandardNormal);
+        (dx * self.jitter, dy * self.jitter)
+    }
+
+    /// Position of each star at a given time, before jitter
+    pub fn star_positions(&self, width: u32, height: u32, time: DateTime<Utc>) -> Vec<(f64, f64)> {
+        let dt = seconds(time - self.epoch);
+        self.stars
+            .iter()
+            .map(|s| {
+                (
+                    (s.x + self.drift.0 * dt).rem_euclid(width as f64),
+                    (s.y + self.drift.1 * dt).rem_euclid(height as f64),
+                )
+            })
+            .collect()
+    }
+
+    /// Noise-free photo-electron flux on each pixel, in electrons per second
+    ///
+    /// # Arguments
+    /// * `width`, `height` - Size of the frame in pixels
+    /// * `time` - Center of integration of the frame
+    /// * `exposure` - Exposure time in seconds, over which moving targets streak
+    ///
+    /// # Returns
+    /// The flux for each pixel, in row-major order
+    ///
+    pub fn flux(&self, width: u32, height: u32, time: DateTime<Utc>, exposure: f64) -> Vec<f64> {
+        let mut flux = vec![self.background; width as usize * height as usize];
+        let stamp = Stamp::new(&self.psf);
+        let (jx, jy) = self.jitter_offset(time);
+
+        for (star, (x, y)) in self
+            .stars
+            .iter()
+            .zip(self.star_positions(width, height, time))
+        {
+            let f = self.magnitude_to_flux(star.magnitude);
+            stamp.add(&mut flux, width, height, x + jx, y + jy, f);
+        }
+
+        // Moving targets are rendered at points along their track during the exposure
+        let dt = seconds(time - self.epoch);
+        for t in self.targets.iter() {
+            let length = (t.vx * t.vx + t.vy * t.vy).sqrt() * exposure;
+            let nsteps = (length.ceil() as usize).max(1);
+            let f = self.magnitude_to_flux(t.magnitude) / nsteps as f64;
+            for i in 0..nsteps {
+                let ts = dt + exposure * ((i as f64 + 0.5) / nsteps as f64 - 0.5);
+                let x = (t.x + t.vx * ts).rem_euclid(width as f64);
+                let y = (t.y + t.vy * ts).rem_euclid(height as f64);
+                stamp.add(&mut flux, width, height, x + jx, y + jy, f);
+            }
+        }
+        flux
+    }
+}
+
+fn seconds(d: chrono::TimeDelta) -> f64 {
+    d.num_microseconds()
+        .map(|us| us as f64 * 1.0e-6)
+        .unwrap_or(d.num_seconds() as f64)
+}
+
+/// Renders a PSF into a flux image, normalized so the rendered pixels hold the total flux
+struct Stamp<'a> {
+    psf: &'a Psf,
+    radius: f64,
+}
+
+impl<'a> Stamp<'a> {
+    fn new(psf: &'a Psf) -> Self {
+        Stamp {
+            psf,
+            radius: psf.radius(),
+        }
+    }
+
+    fn add(&self, flux: &mut [f64], width: u32, height: u32, x: f64, y: f64, total: f64) {
+        let x0 = ((x - self.radius).floor().max(0.0)) as u32;
+        let y0 = ((y - self.radius).floor().max(0.0)) as u32;
+        let x1 = ((x + self.radius).ceil().max(0.0) as u32).min(width);
+        let y1 = ((y + self.radius).ceil().max(0.0) as u32).min(height);
+        if x0 >= x1 || y0 >= y1 {
+            return;
+        }
+
+        // Normalize over the full stamp, so sources near the edges lose the flux that falls outside
+        let norm = self.norm(x - x.floor(), y - y.floor());
+        for row in y0..y1 {
+            for col in x0..x1 {
+                let (dx, dy) = (col as f64 - x, row as f64 - y);
+                let r = (dx * dx + dy * dy).sqrt();
+                if r <= self.radius {
+                    flux[(row * width + col) as usize] += total * self.psf.profile(r) / norm;
+                }
+            }
+        }
+    }
+
+    /// Sum of the PSF over the pixels within the radius, for a source at a sub-pixel offset
+    fn norm(&self, fx: f64, fy: f64) -> f64 {
+        let n = self.radius.ceil() as i64 + 1;
+        let mut sum = 0.0;
+        for row in -n..=n {
+            for col in -n..=n {
+                let (dx, dy) = (col as f64 - fx, row as f64 - fy);
+                let r = (dx * dx + dy * dy).sqrt();
+                if r <= self.radius {
+                    sum += self.psf.profile(r);
+                }
+            }
+        }
+        sum
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_relative_eq;
+
+    fn single_star(psf: Psf) -> Scene {
+        Scene {
+            psf,
+            background: 10.0,
+            zero_point: 1.0e6,
+            stars: vec![Star {
+                x: 50.3,
+                y: 40.7,
+                magnitude: 5.0,
+            }],
+            drift: (0.0, 0.0),
+            targets: Vec::new(),
+            jitter: 0.0,
+            epoch: DateTime::UNIX_EPOCH,
+            seed: 0,
+        }
+    }
+
+    #[test]
+    fn test_psf_flux() {
+        let time = Utc::now();
+        for psf in [
+            Psf::Gaussian { fwhm: 3.0 },
+            Psf::Moffat {
+                fwhm: 3.0,
+                beta: 2.5,
+            },
+            Psf::Airy { fwhm: 3.0 },
+        ] {
+            let scene = single_star(psf);
+            let flux = scene.flux(100, 80, time, 0.1);
+            let total = flux.iter().map(|f| f - 10.0).sum::<f64>();
+            assert_relative_eq!(total, scene.magnitude_to_flux(5.0), max_relative = 1.0e-9);
+
+            // Peak is at the pixel nearest the star
+            let imax = (0..flux.len())
+                .max_by(|a, b| flux[*a].total_cmp(&flux[*b]))
+                .unwrap();
+            assert_eq!((imax % 100, imax / 100), (50, 41));
+        }
+        assert_relative_eq!(
+            Psf::Airy { fwhm: 3.0 }.profile(1.5),
+            0.5,
+            max_relative = 1.0e-3
+        );
+    }
+
+    #[test]
+    fn test_time_driven() {
+        let scene = Scene::default();
+        let t0 = Utc::now();
+        let t1 = t0 + chrono::Duration::seconds(10);
+        assert_eq!(
+            scene.flux(1024, 768, t0, 0.1),
+            scene.flux(1024, 768, t0, 0.1)
+        );
+        assert_ne!(scene.jitter_offset(t0), scene.jitter_offset(t1));
+
+        let p0 = scene.star_positions(1024, 768, t0);
+        let p1 = scene.star_positions(1024, 768, t1);
+        for ((x0, y0), (x1, y1)) in p0.iter().zip(p1.iter()) {
+            assert_relative_eq!((x1 - x0).rem_euclid(1024.0), 20.0, epsilon = 1.0e-6);
+            assert_relative_eq!((y1 - y0).rem_euclid(768.0), 5.0, epsilon = 1.0e-6);
+        }
+    }
+
+    #[test]
+    fn test_streak() {
+        let mut scene = single_star(Psf::Gaussian { fwhm: 2.0 });
+        scene.stars.clear();
+        scene.targets.push(MovingTarget {
+            x: 20.0,
+            y: 40.0,
+            vx: 100.0,
+            vy: 0.0,
+            magnitude: 5.0,
+        });
+        // Half a second after the epoch the streak runs from x = 60 to 80 during a 0.2 s exposure
+        let time = DateTime::UNIX_EPOCH + chrono::Duration::milliseconds(500);
+        let flux = scene.flux(100, 80, time, 0.2);
+        let row = &flux[40 * 100..41 * 100];
+        assert!(row[70] > 100.0);
+        assert_relative_eq!(row[62], row[78], max_relative = 0.05);
+        assert!(row[55] < 11.0 && row[85] < 11.0);
+    }
+}
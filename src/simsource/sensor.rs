@@ -0,0 +1,202 @@
+//!
+//! Sensor model for the simulated camera
+//!
+//! Converts the photo-electron flux falling on each pixel into ADU, with photon
+//! shot noise, dark current, full-well saturation, read noise, gain, bias and
+//! quantization to the sensor bit depth.
+//!
+
+use crate::cameraframe::FrameData;
+
+use rand::Rng;
+use rand_distr::{Distribution, Normal, Poisson, StandardNormal};
+
+/// Mean electron count above which shot noise is drawn from the normal
+/// approximation to the Poisson distribution, for speed
+const POISSON_NORMAL_THRESHOLD: f64 = 1000.0;
+
+/// Physical parameters of a simulated sensor
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SensorConfig {
+    pub width: u32,
+    pub height: u32,
+    /// Electrons per ADU
+    pub gain: f64,
+    /// Offset added by the readout electronics, in ADU
+    pub bias: f64,
+    /// RMS read noise, in electrons
+    pub read_noise: f64,
+    /// Thermal electrons per pixel per second
+    pub dark_current: f64,
+    /// Electrons a pixel can hold before saturating
+    pub full_well: f64,
+    /// Number of bits output by the ADC (1 to 16)
+    pub bit_depth: u8,
+}
+
+impl Default for SensorConfig {
+    fn default() -> Self {
+        SensorConfig {
+            width: 1024,
+            height: 768,
+            gain: 1.0,
+            bias: 1000.0,
+            read_noise: 10.0,
+            dark_current: 5.0,
+            full_well: 60000.0,
+            bit_depth: 16,
+        }
+    }
+}
+
+impl SensorConfig {
+    /// Largest value the ADC can output
+    pub fn max_adu(&self) -> f64 {
+        ((1u32 << self.bit_depth.clamp(1, 16)) - 1) as f64
+    }
+
+    /// Expected pixel value in ADU, ignoring saturation and quantization
+    ///
+    /// # Arguments
+    /// * `flux` - Photo-electrons per second falling on the pixel
+    /// * `exposure` - Exposure time in seconds
+    ///
+    pub fn mean_adu(&self, flux: f64, exposure: f64) -> f64 {
+        (flux + self.dark_current) * exposure / self.gain + self.bias
+    }
+
+    /// Expected RMS noise of a pixel in ADU, ignoring saturation and quantization
+    ///
+    /// # Arguments
+    /// * `flux` - Photo-electrons per second falling on the pixel
+    /// * `exposure` - Exposure time in seconds
+    ///
+    pub fn noise_adu(&self, flux: f64, exposure: f64) -> f64 {
+        let electrons = ((flux + self.dark_current) * exposure).max(0.0);
+        (electrons + self.read_noise * self.read_noise).sqrt() / self.gain
+    }
+
+    /// Simulate an exposure
+    ///
+    /// # Arguments
+    /// * `flux` - Photo-electrons per second falling on each pixel, in row-major
+    ///   order; must hold `width * height` values
+    /// * `exposure` - Exposure time in seconds
+    /// * `rng` - Random number generator for the noise
+    ///
+    /// # Returns
+    /// The pixel values in ADU
+    ///
+    pub fn expose<R: Rng>(&self, flux: &[f64], exposure: f64, rng: &mut R) -> FrameData<u16> {
+        let electrons = self.integrate(flux, exposure, rng);
+        self.readout(&electrons, rng)
+    }
+
+    /// Electrons collected by each pixel during an exposure, with shot noise and dark current
+    ///
+    /// # Arguments
+    /// * `flux` - Photo-electrons per second falling on each pixel, in row-major order
+    /// * `exposure` - Exposure time in seconds
+    /// * `rng` - Random number generator for the noise
+    ///
+    pub fn integrate<R: Rng>(&self, flux: &[f64], exposure: f64, rng: &mut R) -> Vec<f64> {
+        flux.iter()
+            .map(|f| shot_noise(((f + self.dark_current) * exposure).max(0.0), rng))
+            .collect()
+    }
+
+    /// Read out the collected charge, with full-well saturation, read noise, gain,
+    /// bias and quantization
+    ///
+    /// # Arguments
+    /// * `electrons` - Electrons in each pixel, in row-major order; must hold
+    ///   `width * height` values
+    /// * `rng` - Random number generator for the noise
+    ///
+    /// # Returns
+    /// The pixel values in ADU
+    ///
+    pub fn readout<R: Rng>(&self, electrons: &[f64], rng: &mut R) -> FrameData<u16> {
+        assert_eq!(electrons.len(), self.width as usize * self.height as usize);
+        let read_noise = Normal::new(0.0, self.read_noise.max(0.0)).unwrap();
+        let max_adu = self.max_adu();
+        FrameData {
+            width: self.width,
+            height: self.height,
+            data: electrons
+                .iter()
+                .map(|e| {
+                    let adu =
+                        (e.min(self.full_well) + read_noise.sample(rng)) / self.gain + self.bias;
+                    adu.round().clamp(0.0, max_adu) as u16
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Number of electrons collected for a given mean, with Poisson statistics
+fn shot_noise<R: Rng>(mean: f64, rng: &mut R) -> f64 {
+    if mean <= 0.0 {
+        0.0
+    } else if mean < POISSON_NORMAL_THRESHOLD {
+        Poisson::new(mean).unwrap().sample(rng)
+    } else {
+        let z: f64 = rng.sample(StandardNormal);
+        (mean + z * mean.sqrt()).max(0.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_relative_eq;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_noise_model() {
+        let sensor = SensorConfig {
+            width: 200,
+            height: 200,
+            gain: 2.0,
+            bias: 100.0,
+            read_noise: 8.0,
+            dark_current: 20.0,
+            full_well: 1.0e6,
+            bit_depth: 16,
+        };
+        let exposure = 0.5;
+        for flux in [0.0, 400.0, 10000.0] {
+            let mut rng = rand::rngs::StdRng::seed_from_u64(42);
+            let frame = sensor.expose(&vec![flux; 200 * 200], exposure, &mut rng);
+            let (mean, var) = frame.mean_and_var();
+            assert_relative_eq!(mean, sensor.mean_adu(flux, exposure), max_relative = 0.005);
+            // Quantization adds 1/12 ADU^2 of variance
+            let expected = sensor.noise_adu(flux, exposure).powi(2) + 1.0 / 12.0;
+            assert_relative_eq!(var, expected, max_relative = 0.05);
+        }
+    }
+
+    #[test]
+    fn test_saturation() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(1);
+        let sensor = SensorConfig {
+            width: 10,
+            height: 10,
+            bit_depth: 12,
+            ..Default::default()
+        };
+        assert_eq!(sensor.max_adu(), 4095.0);
+        let frame = sensor.expose(&[1.0e9; 100], 1.0, &mut rng);
+        assert!(frame.data.iter().all(|x| *x == 4095));
+
+        // Full well below the ADC range: saturated pixels sit at the full well
+        let sensor = SensorConfig {
+            full_well: 2000.0,
+            read_noise: 0.0,
+            ..sensor
+        };
+        let frame = sensor.expose(&[1.0e9; 100], 1.0, &mut rng);
+        assert!(frame.data.iter().all(|x| *x == 3000));
+    }
+}